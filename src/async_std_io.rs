@@ -0,0 +1,173 @@
+// Copyright 2016 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Reading and writing `multipart/*` bodies over the `Read` and `Write` traits of
+//! `async-std`, as used by `tide` (requires the `async-std` feature)
+//!
+//! No executor or blocking thread is needed: the parsing of `MultipartReader` is simply
+//! resumed whenever more of the stream is ready.
+
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+
+use async_std::io::{Read as AsyncRead, Write as AsyncWrite};
+use header::Headers;
+
+use super::{write_multipart, Error, Node};
+use reader::MultipartReader;
+
+/// Reads a `multipart/*` body from an `async-std` stream one part at a time, as
+/// `MultipartReader` does from a blocking one.  Reading from the `AsyncMultipartReader`
+/// itself yields the body of the current part, up to the next boundary.
+pub struct AsyncMultipartReader<R: AsyncRead + Unpin> {
+    reader: MultipartReader<PollReader<R>>,
+}
+
+impl<R: AsyncRead + Unpin> AsyncMultipartReader<R> {
+    /// Create a reader over a stream positioned at the start of a `multipart/*` body.  The
+    /// boundary is taken from the `ContentType` of `headers`.
+    pub fn new(stream: R, headers: &Headers) -> Result<AsyncMultipartReader<R>, Error> {
+        let reader = PollReader {
+            stream: stream,
+            waker: None,
+        };
+        Ok(AsyncMultipartReader {
+            reader: try!(MultipartReader::new(reader, headers)),
+        })
+    }
+
+    /// Limit the number of header fields a part may have, as
+    /// `MultipartReader::set_max_headers()` does.
+    pub fn set_max_headers(&mut self, max_headers: Option<usize>) {
+        self.reader.set_max_headers(max_headers);
+    }
+
+    /// Limit the length in bytes of the header block of a part, as
+    /// `MultipartReader::set_max_header_length()` does.
+    pub fn set_max_header_length(&mut self, max_header_length: Option<usize>) {
+        self.reader.set_max_header_length(max_header_length);
+    }
+
+    /// Read up to the next part, resolving to its headers, or to `None` once the closing
+    /// boundary is reached.  Whatever was left unread of the previous part body is skipped.
+    pub fn next_part<'a>(&'a mut self)
+                         -> impl Future<Output = Result<Option<Headers>, Error>> + 'a
+    {
+        NextPart { reader: self }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for AsyncMultipartReader<R> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context, buf: &mut [u8])
+                 -> Poll<io::Result<usize>>
+    {
+        self.reader.get_mut().waker = Some(cx.waker().clone());
+        let result = self.reader.read_body(buf);
+        self.reader.get_mut().waker = None;
+        match result {
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Poll::Pending,
+            result => Poll::Ready(result),
+        }
+    }
+}
+
+// Resolves to the headers of the next part
+struct NextPart<'a, R: AsyncRead + Unpin + 'a> {
+    reader: &'a mut AsyncMultipartReader<R>,
+}
+
+impl<'a, R: AsyncRead + Unpin> Future for NextPart<'a, R> {
+    type Output = Result<Option<Headers>, Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let reader = &mut self.reader.reader;
+        reader.get_mut().waker = Some(cx.waker().clone());
+        let result = reader.next_part().map(|part| part.map(|part| part.headers));
+        reader.get_mut().waker = None;
+        match result {
+            Err(Error::Io(ref e)) if e.kind() == io::ErrorKind::WouldBlock => Poll::Pending,
+            result => Poll::Ready(result),
+        }
+    }
+}
+
+// Reads an `async-std` stream as a blocking `Read`, failing with `io::ErrorKind::WouldBlock`
+// whenever the stream is not ready, having arranged for the task polling to be woken once
+// it is
+struct PollReader<R> {
+    stream: R,
+    // The waker of the task polling, while it is polling
+    waker: Option<Waker>,
+}
+
+impl<R: AsyncRead + Unpin> io::Read for PollReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let waker = match self.waker {
+            Some(ref waker) => waker,
+            None => return Err(io::Error::new(io::ErrorKind::WouldBlock, "not polled")),
+        };
+        match Pin::new(&mut self.stream).poll_read(&mut Context::from_waker(waker), buf) {
+            Poll::Ready(result) => result,
+            Poll::Pending => Err(io::Error::new(io::ErrorKind::WouldBlock, "not ready")),
+        }
+    }
+}
+
+/// Write a `multipart/*` body to an `async-std` stream, as `write_multipart()` does to a
+/// blocking one, resolving to the number of bytes written.
+///
+/// The body is built in memory, including the content of any `Node::File`s, before it is
+/// written.
+pub fn write_multipart_async<'a, W>(stream: &'a mut W, boundary: &Vec<u8>, nodes: &Vec<Node>)
+                                    -> impl Future<Output = Result<usize, Error>> + 'a
+    where W: AsyncWrite + Unpin
+{
+    let mut body: Vec<u8> = Vec::new();
+    let error = write_multipart(&mut body, boundary, nodes).err();
+    WriteMultipart {
+        stream: stream,
+        body: body,
+        error: error,
+        pos: 0,
+    }
+}
+
+// Writes a body already built, then flushes the stream
+struct WriteMultipart<'a, W: AsyncWrite + Unpin + 'a> {
+    stream: &'a mut W,
+    body: Vec<u8>,
+    // An error building the body, to resolve to instead
+    error: Option<Error>,
+    pos: usize,
+}
+
+impl<'a, W: AsyncWrite + Unpin> Future for WriteMultipart<'a, W> {
+    type Output = Result<usize, Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = &mut *self;
+        if let Some(e) = this.error.take() {
+            return Poll::Ready(Err(e));
+        }
+        while this.pos < this.body.len() {
+            match Pin::new(&mut *this.stream).poll_write(cx, &this.body[this.pos..]) {
+                Poll::Ready(Ok(0)) => return Poll::Ready(Err(Error::Io(io::Error::new(
+                    io::ErrorKind::WriteZero, "failed to write the whole body")))),
+                Poll::Ready(Ok(count)) => this.pos += count,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(Error::Io(e))),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        match Pin::new(&mut *this.stream).poll_flush(cx) {
+            Poll::Ready(Ok(())) => Poll::Ready(Ok(this.pos)),
+            Poll::Ready(Err(e)) => Poll::Ready(Err(Error::Io(e))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
@@ -0,0 +1,129 @@
+// Copyright 2016 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! An `axum` extractor parsing `multipart/*` request bodies into `Node`s (requires the
+//! `axum` feature)
+//!
+//! Unlike the extractor built into `axum`, nested `multipart/mixed` parts are parsed into
+//! `Node::Multipart`s rather than being returned as opaque fields.
+
+use std::future::Future;
+use std::io::{self, Read};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use axum::body::{Body, Bytes};
+use axum::extract::{FromRequest, Request};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use http_body::Body as HttpBody;
+use header::Headers;
+
+use super::{Error, Node, ParseOptions};
+use body_reader::{self, ChunkSource, MultipartFuture};
+
+/// The `Node`s of a `multipart/*` request body, extracted by an `axum` handler.
+///
+/// The body is parsed under the `ParseOptions` of the `MultipartConfig` added to the request
+/// as an extension, if any, or else the default ones, so file parts are streamed to files in
+/// a temporary directory as they arrive, and the rest are held in memory.  Parsing is
+/// blocking, and is done on `tokio`'s blocking thread pool, so the extractor must be used
+/// within a `tokio` runtime.
+pub struct Multipart(pub Vec<Node>);
+
+impl<S: Send + Sync> FromRequest<S> for Multipart {
+    type Rejection = MultipartRejection;
+
+    fn from_request(req: Request, _state: &S)
+                    -> impl Future<Output = Result<Multipart, MultipartRejection>> + Send
+    {
+        let mut headers = Headers::new();
+        for (name, value) in req.headers() {
+            headers.append_raw(name.as_str().to_owned(), value.as_bytes().to_vec());
+        }
+        let config = req.extensions().get::<MultipartConfig>().cloned();
+        let options = move || match config {
+            Some(config) => (config.options)(),
+            None => ParseOptions::default(),
+        };
+        MultipartFuture::new(headers, req.into_body(), options, None, |result| {
+            result.map(Multipart).map_err(MultipartRejection)
+        })
+    }
+}
+
+/// The options under which the `Multipart` extractor parses request bodies, taken from the
+/// extensions of the request, as added by `Router::layer(Extension(config))`
+#[derive(Clone)]
+pub struct MultipartConfig {
+    options: Arc<dyn Fn() -> ParseOptions + Send + Sync>,
+}
+
+impl MultipartConfig {
+    /// Parse bodies under the `ParseOptions` returned by `options`, which is called for each
+    /// request, and within which limits such as `max_part_headers` may be set.
+    pub fn new<F>(options: F) -> MultipartConfig
+        where F: Fn() -> ParseOptions + Send + Sync + 'static
+    {
+        MultipartConfig {
+            options: Arc::new(options),
+        }
+    }
+}
+
+/// Rejection of a request whose body could not be parsed, responding with
+/// `400 Bad Request` and a description of the error
+pub struct MultipartRejection(pub Error);
+
+impl IntoResponse for MultipartRejection {
+    fn into_response(self) -> Response {
+        (StatusCode::BAD_REQUEST, self.0.to_string()).into_response()
+    }
+}
+
+/// Reads an `axum` `Body` as a blocking `Read`, waiting for each frame of data as it is
+/// needed.
+///
+/// As this blocks, it must only be used on a thread of `tokio`'s blocking pool (such as
+/// within `spawn_blocking()`), and not on one running async tasks.
+pub struct BodyReader(body_reader::BodyReader<Body>);
+
+impl BodyReader {
+    /// Create a reader over the body, which must be called within a `tokio` runtime
+    pub fn new(body: Body) -> BodyReader {
+        BodyReader(body_reader::BodyReader::new(body, None))
+    }
+}
+
+impl Read for BodyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl ChunkSource for Body {
+    type Chunk = Bytes;
+
+    fn poll_chunk(&mut self, cx: &mut Context) -> Poll<Option<io::Result<Bytes>>> {
+        loop {
+            match Pin::new(&mut *self).poll_frame(cx) {
+                Poll::Ready(Some(Ok(frame))) => {
+                    // Trailers are not data, and are skipped
+                    if let Ok(data) = frame.into_data() {
+                        return Poll::Ready(Some(Ok(data)));
+                    }
+                },
+                Poll::Ready(Some(Err(e))) => {
+                    return Poll::Ready(Some(Err(io::Error::new(io::ErrorKind::Other, e))));
+                },
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
@@ -0,0 +1,276 @@
+// Copyright 2016 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Support for the `application/http` parts of batch requests and responses, as used by the
+//! Google and OData batch APIs, each of which holds a whole HTTP request or response
+
+use std::io::{Read, Write};
+
+use bytes::Bytes;
+use httparse;
+use header::{ContentLength, ContentType, Headers};
+
+use super::{generate_boundary, open_content, validate_boundary, write_headers, Error, Node, Part,
+            WriteAllCount, WriteOptions};
+use related::CONTENT_ID;
+use transfer_encoding::CONTENT_TRANSFER_ENCODING;
+
+/// An HTTP request held in an `application/http` part
+#[derive(Clone, Debug, PartialEq)]
+pub struct HttpRequest {
+    /// The method, such as `GET`
+    pub method: String,
+    /// The request target, such as `/farm/v1/animals/pony`
+    pub target: String,
+    /// The minor version of HTTP/1.x
+    pub version: u8,
+    pub headers: Headers,
+    pub body: Bytes,
+}
+
+impl HttpRequest {
+    /// A request with the method and target given, over HTTP/1.1, without headers or a body
+    pub fn new(method: &str, target: &str) -> HttpRequest {
+        HttpRequest {
+            method: method.to_owned(),
+            target: target.to_owned(),
+            version: 1,
+            headers: Headers::new(),
+            body: Bytes::new(),
+        }
+    }
+
+    /// Write the request: its request line, headers and body.  A `Content-Length` header is
+    /// added for a body if there is none.  Fails with `Error::InvalidHeader` before writing
+    /// anything if a header name or value contains a CR or LF, or the method or target
+    /// contains whitespace or a control character.  Returns the number of bytes written.
+    pub fn write<S: Write>(&self, stream: &mut S) -> Result<usize, Error> {
+        // Either could otherwise end the request line early, and add headers of its own
+        for &(name, value) in &[("method", &self.method), ("target", &self.target)] {
+            if value.is_empty() || value.chars().any(|c| c.is_whitespace() || c.is_control()) {
+                return Err(Error::InvalidHeader(name.to_owned()));
+            }
+        }
+        let mut headers = self.headers.clone();
+        if ! self.body.is_empty() && ! headers.has::<ContentLength>() {
+            headers.set(ContentLength(self.body.len() as u64));
+        }
+        let mut head: Vec<u8> = Vec::new();
+        try!(write_headers(&mut head, &headers, &WriteOptions::default()));
+        let line = format!("{} {} HTTP/1.{}\r\n", self.method, self.target, self.version);
+        Ok(try!(stream.write_all_vectored_count(&[line.as_bytes(), &head, &self.body])))
+    }
+}
+
+/// An HTTP response held in an `application/http` part
+#[derive(Clone, Debug, PartialEq)]
+pub struct HttpResponse {
+    /// The status code, such as 200
+    pub status: u16,
+    /// The reason phrase, such as `OK`
+    pub reason: String,
+    /// The minor version of HTTP/1.x
+    pub version: u8,
+    pub headers: Headers,
+    pub body: Bytes,
+}
+
+/// The HTTP request or response held in an `application/http` part
+#[derive(Clone, Debug, PartialEq)]
+pub enum HttpMessage {
+    Request(HttpRequest),
+    Response(HttpResponse),
+}
+
+impl HttpMessage {
+    /// Parse an HTTP request or response: its request or status line, headers and body.
+    /// Lines may end with either CRLF or LF.  The body is cut short at its `Content-Length`
+    /// if it has one.
+    pub fn parse(bytes: &[u8]) -> Result<HttpMessage, Error> {
+        // Leading blank lines are allowed before the request or status line
+        let start = bytes.iter().take_while(|&&b| b == b'\r' || b == b'\n').count();
+        let bytes = &bytes[start..];
+        let capacity = bytes.iter().filter(|&&b| b == b'\n').count();
+        let mut header_memory = vec![httparse::EMPTY_HEADER; capacity];
+
+        if bytes.starts_with(b"HTTP/") {
+            let mut response = httparse::Response::new(&mut header_memory);
+            let len = match try!(response.parse(bytes)) {
+                httparse::Status::Complete(len) => len,
+                httparse::Status::Partial => return Err(Error::PartialHeaders),
+            };
+            let headers = to_headers(response.headers);
+            let body = body(&bytes[len..], &headers);
+            Ok(HttpMessage::Response(HttpResponse {
+                status: response.code.unwrap_or(0),
+                reason: response.reason.unwrap_or("").to_owned(),
+                version: response.version.unwrap_or(1),
+                headers: headers,
+                body: body,
+            }))
+        } else {
+            // The request line is read here rather than by httparse, as the batch APIs send
+            // it without the HTTP version
+            let line_end = match bytes.iter().position(|&b| b == b'\n') {
+                Some(line_end) => line_end,
+                None => return Err(Error::PartialHeaders),
+            };
+            let line = String::from_utf8_lossy(&bytes[..line_end]);
+            let mut words = line.split_whitespace();
+            let (method, target) = match (words.next(), words.next()) {
+                (Some(method), Some(target)) => (method.to_owned(), target.to_owned()),
+                _ => return Err(Error::MalformedHeader("Request-Line".to_owned())),
+            };
+            let version = match (words.next(), words.next()) {
+                (None, _) | (Some("HTTP/1.1"), None) => 1,
+                (Some("HTTP/1.0"), None) => 0,
+                _ => return Err(Error::MalformedHeader("Request-Line".to_owned())),
+            };
+
+            let rest = &bytes[line_end + 1..];
+            let (len, headers) = if rest.is_empty() {
+                (0, Headers::new())
+            } else {
+                match try!(httparse::parse_headers(rest, &mut header_memory)) {
+                    httparse::Status::Complete((len, raw)) => (len, to_headers(raw)),
+                    httparse::Status::Partial => return Err(Error::PartialHeaders),
+                }
+            };
+            let body = body(&rest[len..], &headers);
+            Ok(HttpMessage::Request(HttpRequest {
+                method: method,
+                target: target,
+                version: version,
+                headers: headers,
+                body: body,
+            }))
+        }
+    }
+
+    /// Parse the HTTP request or response held in a part.  Returns `None` if the node is
+    /// not a part (in memory or in a file) with a `Content-Type` of `application/http`.
+    pub fn from_node(node: &Node) -> Option<Result<HttpMessage, Error>> {
+        let is_http = node.content_type().map_or(false, |ct| {
+            ct.type_() == ::mime::APPLICATION && ct.subtype().as_str().eq_ignore_ascii_case("http")
+        });
+        if ! is_http {
+            return None;
+        }
+        let mut bytes: Vec<u8> = Vec::new();
+        match open_content(node) {
+            Ok(Some(mut content)) => {
+                if let Err(error) = content.read_to_end(&mut bytes) {
+                    return Some(Err(From::from(error)));
+                }
+            },
+            Ok(None) => return None,
+            Err(error) => return Some(Err(error)),
+        }
+        Some(HttpMessage::parse(&bytes))
+    }
+
+    /// The headers of the request or response
+    pub fn headers(&self) -> &Headers {
+        match *self {
+            HttpMessage::Request(ref request) => &request.headers,
+            HttpMessage::Response(ref response) => &response.headers,
+        }
+    }
+
+    /// The body of the request or response
+    pub fn body(&self) -> &Bytes {
+        match *self {
+            HttpMessage::Request(ref request) => &request.body,
+            HttpMessage::Response(ref response) => &response.body,
+        }
+    }
+}
+
+/// Builds the body of a batch request: a `multipart/mixed` of `application/http` parts, each
+/// holding one request and a `Content-ID` by which its response can be matched, as batch
+/// endpoints expect.
+///
+/// ```rust,ignore
+/// let (nodes, boundary, content_type) = BatchBuilder::new()
+///     .add_request(HttpRequest::new("GET", "/farm/v1/animals/pony"))?
+///     .build()?;
+/// ```
+pub struct BatchBuilder {
+    boundary: Vec<u8>,
+    nodes: Vec<Node>,
+}
+
+impl BatchBuilder {
+    /// Start building a batch
+    pub fn new() -> BatchBuilder {
+        BatchBuilder {
+            boundary: generate_boundary(),
+            nodes: Vec::new(),
+        }
+    }
+
+    /// Use the boundary given, rather than the one generated for the builder.  Fails with
+    /// `Error::InvalidBoundary` if it is not a boundary RFC 2046 allows.
+    pub fn boundary(mut self, boundary: Vec<u8>) -> Result<BatchBuilder, Error> {
+        try!(validate_boundary(&boundary));
+        self.boundary = boundary;
+        Ok(self)
+    }
+
+    /// Add a request, with a `Content-ID` of `<item`n`>` for the n'th request, counting
+    /// from 1.  Responses carry it as `<response-item`n`>`.
+    pub fn add_request(self, request: HttpRequest) -> Result<BatchBuilder, Error> {
+        let id = format!("item{}", self.nodes.len() + 1);
+        self.add_request_with_id(&id, request)
+    }
+
+    /// Add a request with the `Content-ID` given, without angle brackets
+    pub fn add_request_with_id(mut self, id: &str, request: HttpRequest)
+                               -> Result<BatchBuilder, Error>
+    {
+        let mut body: Vec<u8> = Vec::new();
+        try!(request.write(&mut body));
+        let mut part = Part::from_bytes("application/http".parse().unwrap(), body);
+        part.headers.set_raw(CONTENT_TRANSFER_ENCODING, "binary");
+        part.headers.set_raw(CONTENT_ID, format!("<{}>", id));
+        self.nodes.push(Node::Part(part));
+        Ok(self)
+    }
+
+    /// Finish building, returning the nodes, the boundary to write them with, and the
+    /// `Content-Type` of the body, carrying that boundary.  Fails with
+    /// `Error::InvalidBoundary` if the boundary cannot be written in it.
+    pub fn build(self) -> Result<(Vec<Node>, Vec<u8>, ContentType), Error> {
+        try!(validate_boundary(&self.boundary));
+        let content_type = format!("multipart/mixed; boundary=\"{}\"",
+                                   String::from_utf8_lossy(&self.boundary));
+        let content_type = try!(content_type.parse().map_err(|_| Error::InvalidBoundary));
+        Ok((self.nodes, self.boundary, ContentType(content_type)))
+    }
+}
+
+impl Default for BatchBuilder {
+    fn default() -> BatchBuilder {
+        BatchBuilder::new()
+    }
+}
+
+fn to_headers(raw_headers: &[httparse::Header]) -> Headers {
+    let mut headers = Headers::new();
+    headers.extend(raw_headers.iter().map(|rh| (rh.name, Bytes::from(rh.value))));
+    headers
+}
+
+// The body following the headers, no longer than its Content-Length
+fn body(rest: &[u8], headers: &Headers) -> Bytes {
+    match headers.get::<ContentLength>() {
+        Some(&ContentLength(len)) if (len as usize) < rest.len() => {
+            Bytes::from(&rest[..len as usize])
+        },
+        _ => Bytes::from(rest),
+    }
+}
@@ -0,0 +1,153 @@
+// Copyright 2016 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Parsing asynchronous request bodies on `tokio`'s blocking thread pool, as shared by the
+// `axum` extractor and the `warp` filter
+
+use std::future::Future;
+use std::io::{self, Read};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use header::Headers;
+use tokio::runtime::Handle;
+use tokio::task::JoinHandle;
+
+use super::{read_multipart_body_with_options, Error, Node, ParseOptions};
+
+// A request body, yielding its data a chunk at a time
+pub trait ChunkSource {
+    type Chunk: AsRef<[u8]>;
+
+    // Poll for the next chunk of data, or `None` once the body ends
+    fn poll_chunk(&mut self, cx: &mut Context) -> Poll<Option<io::Result<Self::Chunk>>>;
+}
+
+// Reads a body as a blocking `Read`, waiting for each chunk as it is needed, on a thread of
+// the blocking pool.  If `remaining` is set, reading fails once the body is longer.
+pub struct BodyReader<S: ChunkSource> {
+    body: S,
+    handle: Handle,
+    chunk: Option<S::Chunk>,
+    pos: usize,
+    remaining: Option<u64>,
+    too_long: bool,
+}
+
+impl<S: ChunkSource + Unpin> BodyReader<S> {
+    // Create a reader over the body, which must be called within a `tokio` runtime
+    pub fn new(body: S, max_length: Option<u64>) -> BodyReader<S> {
+        BodyReader {
+            body: body,
+            handle: Handle::current(),
+            chunk: None,
+            pos: 0,
+            remaining: max_length,
+            too_long: false,
+        }
+    }
+}
+
+impl<S: ChunkSource + Unpin> Read for BodyReader<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if let Some(ref chunk) = self.chunk {
+                let data = chunk.as_ref();
+                if self.pos < data.len() {
+                    let count = ::std::cmp::min(buf.len(), data.len() - self.pos);
+                    buf[..count].copy_from_slice(&data[self.pos..self.pos + count]);
+                    self.pos += count;
+                    return Ok(count);
+                }
+            }
+            match self.handle.block_on(NextChunk(&mut self.body)) {
+                Some(Ok(chunk)) => {
+                    if let Some(ref mut remaining) = self.remaining {
+                        let length = chunk.as_ref().len() as u64;
+                        if length > *remaining {
+                            self.too_long = true;
+                            return Err(io::Error::new(io::ErrorKind::Other,
+                                                      Error::BodyTooLong.to_string()));
+                        }
+                        *remaining -= length;
+                    }
+                    self.chunk = Some(chunk);
+                    self.pos = 0;
+                },
+                Some(Err(e)) => return Err(e),
+                None => return Ok(0),
+            }
+        }
+    }
+}
+
+// Waits for the next chunk of a body
+struct NextChunk<'a, S: 'a>(&'a mut S);
+
+impl<'a, S: ChunkSource + Unpin> Future for NextChunk<'a, S> {
+    type Output = Option<io::Result<S::Chunk>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        self.0.poll_chunk(cx)
+    }
+}
+
+// Parses a request body on the blocking thread pool, starting when first polled, under the
+// `ParseOptions` returned by `F` there, and resolves to the result passed through `map`
+pub struct MultipartFuture<S, F, T> {
+    request: Option<(Headers, S, F)>,
+    max_length: Option<u64>,
+    map: fn(Result<Vec<Node>, Error>) -> T,
+    task: Option<JoinHandle<Result<Vec<Node>, Error>>>,
+}
+
+impl<S, F, T> MultipartFuture<S, F, T> {
+    pub fn new(headers: Headers, body: S, options: F, max_length: Option<u64>,
+               map: fn(Result<Vec<Node>, Error>) -> T)
+               -> MultipartFuture<S, F, T>
+    {
+        MultipartFuture {
+            request: Some((headers, body, options)),
+            max_length: max_length,
+            map: map,
+            task: None,
+        }
+    }
+}
+
+impl<S, F, T> Future for MultipartFuture<S, F, T>
+    where S: ChunkSource + Unpin + Send + 'static,
+          F: FnOnce() -> ParseOptions + Unpin + Send + 'static
+{
+    type Output = T;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<T> {
+        if let Some((headers, body, options)) = self.request.take() {
+            let max_length = self.max_length;
+            self.task = Some(::tokio::task::spawn_blocking(move || {
+                let mut reader = BodyReader::new(body, max_length);
+                let result = read_multipart_body_with_options(&mut reader, &headers,
+                                                              &options());
+                if reader.too_long {
+                    return Err(Error::BodyTooLong);
+                }
+                result
+            }));
+        }
+        let task = match self.task {
+            Some(ref mut task) => task,
+            None => panic!("MultipartFuture polled after completion"),
+        };
+        let result = match Pin::new(task).poll(cx) {
+            Poll::Pending => return Poll::Pending,
+            Poll::Ready(Ok(result)) => result,
+            Poll::Ready(Err(e)) => Err(Error::Io(io::Error::new(io::ErrorKind::Other, e))),
+        };
+        self.task = None;
+        Poll::Ready((self.map)(result))
+    }
+}
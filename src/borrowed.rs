@@ -0,0 +1,166 @@
+// Copyright 2016 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Parsing of a `multipart/*` already held in memory, without copying the part bodies or
+//! writing any files
+
+use bytes::Bytes;
+use header::{ContentType, Headers};
+use mime::{self, Mime};
+
+use super::{find, get_multipart_boundary, parse_header_block, Error, Part};
+
+/// A part whose body borrows from the input it was parsed from
+#[derive(Clone, Debug, PartialEq)]
+pub struct BorrowedPart<'a> {
+    pub headers: Headers,
+    pub body: &'a [u8],
+    /// The header block exactly as it was parsed, including line terminators and the empty
+    /// line ending it
+    pub raw_headers: &'a [u8],
+}
+impl<'a> BorrowedPart<'a> {
+    /// Mime content-type specified in the header
+    pub fn content_type(&self) -> Option<Mime> {
+        let ct: Option<&ContentType> = self.headers.get();
+        ct.map(|ref ct| ct.0.clone())
+    }
+
+    /// Copy into a `Part` which owns its body
+    pub fn to_part(&self) -> Part {
+        Part {
+            headers: self.headers.clone(),
+            body: Bytes::from(self.body),
+            raw_headers: Some(self.raw_headers.to_vec()),
+        }
+    }
+}
+
+/// A part, or a nested multipart, borrowing from the input it was parsed from
+#[derive(Clone, Debug)]
+pub enum BorrowedNode<'a> {
+    /// A part
+    Part(BorrowedPart<'a>),
+    /// A nested multipart, with its headers and parts
+    Multipart((Headers, Vec<BorrowedNode<'a>>)),
+}
+
+/// Parse a MIME `multipart/*` held in memory into `BorrowedNode`s, whose bodies are slices of
+/// `input`.  Recursive `multipart/*` parts are parsed as well.
+///
+/// It is presumed that the headers are at the start of `input`.  If you have them
+/// separately, use `parse_body_bytes()` instead.
+pub fn parse_bytes<'a>(input: &'a [u8]) -> Result<Vec<BorrowedNode<'a>>, Error> {
+    let end = match find(input, b"\r\n\r\n") {
+        Some(end) => end + 4,
+        None => return Err(Error::EofInMainHeaders),
+    };
+    let headers = try!(parse_header_block(&input[..end], None));
+    parse_body_bytes(&input[end..], &headers)
+}
+
+/// Parse a MIME `multipart/*` body held in memory into `BorrowedNode`s, whose bodies are
+/// slices of `input`.  Recursive `multipart/*` parts are parsed as well.
+///
+/// It is presumed that you have the `Headers` already and `input` starts at the body.  If
+/// the headers are at the start of `input`, use `parse_bytes()` instead.
+pub fn parse_body_bytes<'a>(input: &'a [u8], headers: &Headers)
+                            -> Result<Vec<BorrowedNode<'a>>, Error>
+{
+    let boundary = try!(get_multipart_boundary(headers));
+
+    // The delimiter ending each part body, as in `read_multipart_body()`
+    let mut delimiter = Vec::with_capacity(1 + boundary.len());
+    delimiter.push(b'\n');
+    delimiter.extend(boundary.iter().cloned());
+
+    // Skip the preamble
+    let mut pos = match find(input, &boundary) {
+        Some(start) => start + boundary.len(),
+        None => return Err(Error::EofBeforeFirstBoundary),
+    };
+
+    let mut nodes: Vec<BorrowedNode<'a>> = Vec::new();
+    loop {
+        // If the next two characters are '--', parsing is finished.
+        if input[pos..].starts_with(b"--") {
+            break;
+        }
+
+        // The rest of the boundary line may only hold transport padding
+        let line = match next_line(input, &mut pos) {
+            Some(line) => line,
+            None => return Err(Error::NoCrLfAfterBoundary),
+        };
+        if line.iter().any(|&b| b != b' ' && b != b'\t') {
+            return Err(Error::NoCrLfAfterBoundary);
+        }
+
+        // Read the header lines, up to an empty line, normalizing their line terminators
+        // to CRLF as httparse expects.
+        let headers_start = pos;
+        let mut header_block: Vec<u8> = Vec::new();
+        loop {
+            let line = match next_line(input, &mut pos) {
+                Some(line) => line,
+                None => return Err(Error::EofInPartHeaders),
+            };
+            if line.is_empty() {
+                break;
+            }
+            header_block.extend(line.iter().cloned());
+            header_block.extend(b"\r\n".iter().cloned());
+        }
+        header_block.extend(b"\r\n".iter().cloned());
+        let part_headers = try!(parse_header_block(&header_block, None));
+        let raw_headers = &input[headers_start..pos];
+
+        // The body runs up to the delimiter, less the CR of a CRLF preceding it
+        let body = match find(&input[pos..], &delimiter) {
+            Some(len) => {
+                let end = pos + len;
+                let body_end = if len > 0 && input[end - 1] == b'\r' { end - 1 } else { end };
+                let body = &input[pos..body_end];
+                pos = end + delimiter.len();
+                body
+            },
+            None => return Err(Error::EofInPart),
+        };
+
+        let nested = {
+            let ct: Option<&ContentType> = part_headers.get();
+            ct.map_or(false, |ct| ct.type_() == mime::MULTIPART)
+        };
+        if nested {
+            let subnodes = try!(parse_body_bytes(body, &part_headers));
+            nodes.push(BorrowedNode::Multipart((part_headers, subnodes)));
+        } else {
+            nodes.push(BorrowedNode::Part(BorrowedPart {
+                headers: part_headers,
+                body: body,
+                raw_headers: raw_headers,
+            }));
+        }
+    }
+
+    Ok(nodes)
+}
+
+// The line starting at `pos`, without its line terminator, advancing `pos` past it.
+// Returns `None` if there is no complete line.
+fn next_line<'a>(input: &'a [u8], pos: &mut usize) -> Option<&'a [u8]> {
+    let len = match input[*pos..].iter().position(|&b| b == b'\n') {
+        Some(len) => len,
+        None => return None,
+    };
+    let mut line = &input[*pos..*pos + len];
+    *pos += len + 1;
+    if line.ends_with(b"\r") {
+        line = &line[..line.len() - 1];
+    }
+    Some(line)
+}
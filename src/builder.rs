@@ -0,0 +1,126 @@
+// Copyright 2016 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Building the `Node`s of a multipart body to be written, without constructing their
+//! headers by hand
+
+use std::fs;
+use std::path::Path;
+
+use header::{ContentType, Headers};
+use mime::{self, Mime};
+
+use super::{generate_boundary, validate_boundary, Error, Node};
+use email::email_message;
+use part_headers::PartHeaders;
+
+/// Builds the parts of a multipart body, `multipart/form-data` unless another type is
+/// given, with a boundary generated for it.
+///
+/// ```rust,ignore
+/// let (nodes, boundary, content_type) = MultipartBuilder::new()
+///     .add_text("name", "Joe Blow")
+///     .add_file("upload", "/tmp/file1.txt")?
+///     .build()?;
+/// ```
+pub struct MultipartBuilder {
+    content_type: Mime,
+    boundary: Vec<u8>,
+    nodes: Vec<Node>,
+}
+
+impl MultipartBuilder {
+    /// Start building a `multipart/form-data`
+    pub fn new() -> MultipartBuilder {
+        MultipartBuilder::with_type(mime::MULTIPART_FORM_DATA)
+    }
+
+    /// Start building a multipart of the `multipart/*` type given, such as
+    /// `multipart/mixed`.  Any boundary parameter it has is replaced.
+    pub fn with_type(content_type: Mime) -> MultipartBuilder {
+        MultipartBuilder {
+            content_type: content_type,
+            boundary: generate_boundary(),
+            nodes: Vec::new(),
+        }
+    }
+
+    /// Use the boundary given, e.g. one from `generate_boundary_with()` with a seed, rather
+    /// than the one generated for the builder.  Fails with `Error::InvalidBoundary` if it
+    /// is not a boundary RFC 2046 allows.
+    pub fn boundary(mut self, boundary: Vec<u8>) -> Result<MultipartBuilder, Error> {
+        try!(validate_boundary(&boundary));
+        self.boundary = boundary;
+        Ok(self)
+    }
+
+    /// Add a text field, as a part named `name` holding `value`
+    pub fn add_text(mut self, name: &str, value: &str) -> MultipartBuilder {
+        self.nodes.push(Node::text(name, value));
+        self
+    }
+
+    /// Add a file field, as a part named `name` whose content is read from the file at
+    /// `path` when the body is written.  The filename sent is the last component of
+    /// `path`, and the `Content-Type` is `application/octet-stream`.  Fails if there is no
+    /// file at `path`.
+    pub fn add_file<P: AsRef<Path>>(mut self, name: &str, path: P)
+                                    -> Result<MultipartBuilder, Error>
+    {
+        let metadata = try!(fs::metadata(path.as_ref()));
+        let mut node = Node::file(name, path);
+        if let Node::File(ref mut filepart) = node {
+            filepart.size = Some(metadata.len() as usize);
+        }
+        self.nodes.push(node);
+        Ok(self)
+    }
+
+    /// Add a nested multipart, built with its own boundary
+    pub fn add_nested(mut self, inner: MultipartBuilder) -> Result<MultipartBuilder, Error> {
+        let (nodes, _, content_type) = try!(inner.build());
+        let mut headers = Headers::new();
+        headers.set(content_type);
+        self.nodes.push(Node::Multipart((headers, nodes)));
+        Ok(self)
+    }
+
+    /// Add a node made some other way
+    pub fn add_node(mut self, node: Node) -> MultipartBuilder {
+        self.nodes.push(node);
+        self
+    }
+
+    /// Finish building, returning the nodes, the boundary to write them with, and the
+    /// `Content-Type` of the body, carrying that boundary.  Fails with
+    /// `Error::InvalidBoundary` if the boundary cannot be written in it.
+    pub fn build(self) -> Result<(Vec<Node>, Vec<u8>, ContentType), Error> {
+        try!(validate_boundary(&self.boundary));
+        let content_type = format!("{}/{}; boundary=\"{}\"", self.content_type.type_(),
+                                   self.content_type.subtype(),
+                                   String::from_utf8_lossy(&self.boundary));
+        let content_type = try!(content_type.parse().map_err(|_| Error::InvalidBoundary));
+        Ok((self.nodes, self.boundary, ContentType(content_type)))
+    }
+
+    /// Finish building, returning a complete email message, as written by `write_email()`,
+    /// with the message `headers` given (such as `From`, `To` and `Subject`) and the type
+    /// being built.  Start with `with_type()`, e.g. for `multipart/mixed`, as
+    /// `multipart/form-data` is not for email.
+    pub fn build_email<H: PartHeaders + ?Sized>(self, headers: &H) -> Result<Vec<u8>, Error> {
+        let (nodes, boundary, content_type) = try!(self.build());
+        let mut headers = headers.to_headers().into_owned();
+        headers.set(content_type);
+        email_message(&headers, &boundary, &nodes)
+    }
+}
+
+impl Default for MultipartBuilder {
+    fn default() -> MultipartBuilder {
+        MultipartBuilder::new()
+    }
+}
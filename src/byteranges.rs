@@ -0,0 +1,189 @@
+// Copyright 2016 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Support for `multipart/byteranges` (RFC 7233 appendix A), as sent in multi-range
+//! `206 Partial Content` responses
+
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use header::Headers;
+use mime::Mime;
+
+use super::{read_multipart_body_with_options, Error, Node, ParseOptions, WriteAllCount};
+
+/// The name of the `Content-Range` header
+pub const CONTENT_RANGE: &'static str = "Content-Range";
+
+/// A byte range as given by a `Content-Range` header: `bytes start-end/total`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ContentRange {
+    /// The position of the first byte, counting from zero
+    pub start: u64,
+    /// The position of the last byte, inclusive
+    pub end: u64,
+    /// The length of the complete representation, if known (it is given as `*` if not)
+    pub total: Option<u64>,
+}
+impl ContentRange {
+    /// Parse a `Content-Range` header value of the form `bytes start-end/total`
+    pub fn parse(value: &str) -> Result<ContentRange, Error> {
+        let value = value.trim();
+        if ! value.as_bytes().get(..6).map_or(false, |unit| unit.eq_ignore_ascii_case(b"bytes ")) {
+            return Err(Error::InvalidContentRange);
+        }
+        let mut halves = value[6..].trim_start().splitn(2, '/');
+        let (range, total) = match (halves.next(), halves.next()) {
+            (Some(range), Some(total)) => (range, total),
+            _ => return Err(Error::InvalidContentRange),
+        };
+        let mut bounds = range.splitn(2, '-');
+        let (start, end) = match (bounds.next(), bounds.next()) {
+            (Some(start), Some(end)) => {
+                (try!(parse_position(start)), try!(parse_position(end)))
+            },
+            _ => return Err(Error::InvalidContentRange),
+        };
+        let total = if total == "*" { None } else { Some(try!(parse_position(total))) };
+
+        let range = ContentRange { start: start, end: end, total: total };
+        if start > end || total.map_or(false, |total| end >= total) {
+            return Err(Error::InvalidContentRange);
+        }
+        Ok(range)
+    }
+
+    /// The `Content-Range` of the headers.  Fails if it is missing or malformed.
+    pub fn from_headers(headers: &Headers) -> Result<ContentRange, Error> {
+        match headers.get_raw(CONTENT_RANGE).and_then(|r| r.one()) {
+            Some(raw) => {
+                let value = try!(::std::str::from_utf8(raw)
+                                 .map_err(|_| Error::InvalidContentRange));
+                ContentRange::parse(value)
+            },
+            None => Err(Error::InvalidContentRange),
+        }
+    }
+
+    /// The number of bytes in the range
+    pub fn length(&self) -> u64 {
+        self.end - self.start + 1
+    }
+}
+impl fmt::Display for ContentRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.total {
+            Some(total) => write!(f, "bytes {}-{}/{}", self.start, self.end, total),
+            None => write!(f, "bytes {}-{}/*", self.start, self.end),
+        }
+    }
+}
+
+fn parse_position(digits: &str) -> Result<u64, Error> {
+    let digits = digits.trim();
+    if digits.is_empty() || ! digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(Error::InvalidContentRange);
+    }
+    digits.parse().map_err(|_| Error::InvalidContentRange)
+}
+
+/// A part of a `multipart/byteranges`, with its range
+#[derive(Clone, Debug)]
+pub struct RangePart {
+    /// The range, from the `Content-Range` of the part
+    pub range: ContentRange,
+    /// The part itself, either a `Node::Part` or a `Node::File`
+    pub node: Node,
+}
+
+/// Pair parsed `multipart/byteranges` nodes with their ranges, checking that each part has
+/// a valid `Content-Range` and a body of the length it declares.
+pub fn byte_ranges(nodes: Vec<Node>) -> Result<Vec<RangePart>, Error> {
+    let mut parts: Vec<RangePart> = Vec::with_capacity(nodes.len());
+    for node in nodes {
+        let (range, length) = match node {
+            Node::Part(ref part) => {
+                (try!(ContentRange::from_headers(&part.headers)), part.body.len())
+            },
+            Node::File(ref file) => {
+                let length = match file.size {
+                    Some(size) => size,
+                    None => try!(::std::fs::metadata(&file.path)).len() as usize,
+                };
+                (try!(ContentRange::from_headers(&file.headers)), length)
+            },
+            // Byte ranges are never nested
+            Node::Multipart(_) | Node::Message(_) | Node::Invalid(_) => {
+                return Err(Error::InvalidContentRange)
+            },
+        };
+        if range.length() != length as u64 {
+            return Err(Error::RangeLengthMismatch);
+        }
+        parts.push(RangePart { range: range, node: node });
+    }
+    Ok(parts)
+}
+
+/// Parse a `multipart/byteranges` body from a `Read`able stream into its parts and their
+/// ranges, as `byte_ranges()` does.
+///
+/// It is presumed that you have the `Headers` already and the stream starts at the body.
+pub fn read_byte_ranges<S: Read>(stream: &mut S, headers: &Headers, options: &ParseOptions)
+                                 -> Result<Vec<RangePart>, Error>
+{
+    let nodes = try!(read_multipart_body_with_options(stream, headers, options));
+    byte_ranges(nodes)
+}
+
+/// Write a `multipart/byteranges` body holding the given ranges of `source`, whose complete
+/// length is `total`.  Each range is a (start, end) pair of byte positions, inclusive, and
+/// is written as a part with a `Content-Type` of `content_type` and its `Content-Range`.
+/// Returns the number of bytes written.
+///
+/// The response itself should have a `Content-Type` of `multipart/byteranges` with this
+/// boundary.
+pub fn write_byte_ranges<W: Write, R: Read + Seek>(
+    stream: &mut W,
+    boundary: &Vec<u8>,
+    source: &mut R,
+    total: u64,
+    content_type: &Mime,
+    ranges: &[(u64, u64)])
+    -> Result<usize, Error>
+{
+    let mut count: usize = 0;
+
+    for &(start, end) in ranges {
+        if start > end || end >= total {
+            return Err(Error::InvalidContentRange);
+        }
+        let range = ContentRange { start: start, end: end, total: Some(total) };
+
+        // write a boundary, the part's headers and the blank line together
+        let headers = format!("Content-Type: {}\r\n{}: {}\r\n\r\n", content_type,
+                              CONTENT_RANGE, range);
+        count += try!(stream.write_all_vectored_count(&[b"--", &boundary, b"\r\n",
+                                                        headers.as_bytes()]));
+
+        // Write the range of the source
+        try!(source.seek(SeekFrom::Start(start)));
+        let copied = try!(io::copy(&mut source.take(range.length()), stream));
+        if copied != range.length() {
+            return Err(Error::RangeLengthMismatch);
+        }
+        count += copied as usize;
+
+        // write a line terminator
+        count += try!(stream.write_all_count(b"\r\n"));
+    }
+
+    // write a final boundary
+    count += try!(stream.write_all_vectored_count(&[b"--", &boundary, b"--"]));
+
+    Ok(count)
+}
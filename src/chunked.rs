@@ -0,0 +1,116 @@
+// Copyright 2016 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Decoding a body sent with `Transfer-Encoding: chunked` (RFC 7230 section 4.1), as
+//! `write_multipart_chunked()` writes it
+
+use std::io::{self, Read};
+
+/// The longest line a chunked body may have, be it the size line of a chunk with its
+/// extensions or a trailer, without its terminator
+pub const MAX_LINE_LENGTH: usize = 8192;
+
+/// Reads the body carried by a stream in `Transfer-Encoding: chunked`, so that it may be
+/// parsed as any other, e.g. by `read_multipart_body()`.
+///
+/// Chunk extensions and trailers are skipped.  Reading ends with the last chunk, without
+/// reading any further from the stream, which is left positioned at whatever follows the
+/// body (such as the next request on the connection).  A malformed chunk, or a line longer
+/// than `MAX_LINE_LENGTH`, fails with `io::ErrorKind::InvalidData`, and a stream ending
+/// within the body with `io::ErrorKind::UnexpectedEof`.
+pub struct ChunkedDecoder<R: Read> {
+    stream: R,
+    // The bytes left of the current chunk
+    remaining: u64,
+    done: bool,
+}
+
+impl<R: Read> ChunkedDecoder<R> {
+    /// Create a decoder of the chunked body starting at the current position of `stream`
+    pub fn new(stream: R) -> ChunkedDecoder<R> {
+        ChunkedDecoder {
+            stream: stream,
+            remaining: 0,
+            done: false,
+        }
+    }
+
+    /// Unwrap the stream, positioned after the body if it has been read to its end
+    pub fn into_inner(self) -> R {
+        self.stream
+    }
+
+    // Read a line, without its terminator, a byte at a time so as not to read beyond it
+    fn read_line(&mut self) -> io::Result<Vec<u8>> {
+        let mut line: Vec<u8> = Vec::new();
+        let mut byte = [0u8; 1];
+        loop {
+            if try!(self.stream.read(&mut byte)) == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                          "body ended within a chunk"));
+            }
+            if byte[0] == b'\n' {
+                if line.last() == Some(&b'\r') {
+                    line.pop();
+                }
+                if line.len() > MAX_LINE_LENGTH {
+                    return Err(line_too_long());
+                }
+                return Ok(line);
+            }
+            // The longest line may yet be followed by a CR
+            if line.len() > MAX_LINE_LENGTH {
+                return Err(line_too_long());
+            }
+            line.push(byte[0]);
+        }
+    }
+
+    // Read the size line of the next chunk
+    fn read_size(&mut self) -> io::Result<u64> {
+        let line = try!(self.read_line());
+        let size = line.split(|&b| b == b';').next().unwrap_or(&[]);
+        ::std::str::from_utf8(size).ok()
+            .and_then(|size| u64::from_str_radix(size.trim(), 16).ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid chunk size"))
+    }
+}
+
+impl<R: Read> Read for ChunkedDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+        if self.remaining == 0 {
+            self.remaining = try!(self.read_size());
+            if self.remaining == 0 {
+                // The last chunk, followed by any trailers and an empty line
+                while ! try!(self.read_line()).is_empty() { }
+                self.done = true;
+                return Ok(0);
+            }
+        }
+
+        let max = ::std::cmp::min(buf.len() as u64, self.remaining) as usize;
+        let count = try!(self.stream.read(&mut buf[..max]));
+        if count == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                      "body ended within a chunk"));
+        }
+        self.remaining -= count as u64;
+        if self.remaining == 0 && ! try!(self.read_line()).is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      "chunk longer than its size"));
+        }
+        Ok(count)
+    }
+}
+
+// The error of a line longer than `MAX_LINE_LENGTH`
+fn line_too_long() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "chunk line too long")
+}
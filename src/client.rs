@@ -0,0 +1,116 @@
+// Copyright 2016 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Sending a multipart body with a hyper client
+
+use std::io::{self, BufWriter, Write};
+
+use futures::{Future, Sink};
+use futures::sync::mpsc::Sender;
+use hyper::{self, Body, Chunk};
+use hyper::client::Request;
+use header::{ContentLength, ContentType};
+use mime::{self, Mime};
+
+use super::{generate_boundary, multipart_len, write_multipart, Error, Node};
+
+/// Writes to a hyper `Body` created by `Body::pair()`, sending each write as a chunk and
+/// blocking until it has been taken.  The body ends when the writer is dropped.
+///
+/// As this blocks, it must not be used on the thread running the event loop which sends
+/// the body, but on another, such as that of a `CpuPool`.
+pub struct BodyWriter {
+    sender: Option<Sender<Result<Chunk, hyper::Error>>>,
+}
+
+impl BodyWriter {
+    /// Create a writer sending chunks to the body paired with `sender`
+    pub fn new(sender: Sender<Result<Chunk, hyper::Error>>) -> BodyWriter {
+        BodyWriter { sender: Some(sender) }
+    }
+}
+
+impl Write for BodyWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let sender = match self.sender.take() {
+            Some(sender) => sender,
+            None => return Err(io::Error::new(io::ErrorKind::BrokenPipe, "body dropped")),
+        };
+        match sender.send(Ok(Chunk::from(buf.to_vec()))).wait() {
+            Ok(sender) => {
+                self.sender = Some(sender);
+                Ok(buf.len())
+            },
+            Err(_) => Err(io::Error::new(io::ErrorKind::BrokenPipe, "body dropped")),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The body of a multipart request prepared by `set_multipart_body()`, waiting to be sent
+pub struct MultipartUpload {
+    boundary: Vec<u8>,
+    nodes: Vec<Node>,
+    writer: BodyWriter,
+}
+
+impl MultipartUpload {
+    /// The boundary delimiting the parts
+    pub fn boundary(&self) -> &[u8] {
+        &self.boundary
+    }
+
+    /// Stream the body to the request, blocking until it has all been sent.  Returns the
+    /// number of bytes written.
+    ///
+    /// This must be called on a thread other than the one running the client's event loop,
+    /// as `BodyWriter` explains.
+    pub fn send(self) -> Result<usize, Error> {
+        let mut stream = BufWriter::with_capacity(8192, self.writer);
+        let count = try!(write_multipart(&mut stream, &self.boundary, &self.nodes));
+        try!(stream.flush());
+        Ok(count)
+    }
+}
+
+/// Prepare a hyper client request to send the nodes as a multipart body: a boundary is
+/// generated, the `Content-Type` is set to `content_type` (such as
+/// `mime::MULTIPART_FORM_DATA`) with that boundary, and the `Content-Length` is set to the
+/// length of the body.  The body is streamed once `send()` is called on the
+/// `MultipartUpload` returned, while the request is being made.
+///
+/// Fails with `Error::NotMultipart` if `content_type` is not a `multipart/*` type.
+pub fn set_multipart_body(request: &mut Request, content_type: &Mime, nodes: Vec<Node>)
+                          -> Result<MultipartUpload, Error>
+{
+    if content_type.type_() != mime::MULTIPART {
+        return Err(Error::NotMultipart);
+    }
+    let boundary = generate_boundary();
+    let content_type: Mime = try!(
+        format!("{}; boundary=\"{}\"", content_type, String::from_utf8_lossy(&boundary))
+            .parse().map_err(|_| Error::NotMultipart));
+
+    let length = try!(multipart_len(&boundary, &nodes));
+
+    let (sender, body) = Body::pair();
+    request.headers_mut().set(ContentType(content_type));
+    request.headers_mut().set(ContentLength(length));
+    request.set_body(body);
+
+    Ok(MultipartUpload {
+        boundary: boundary,
+        nodes: nodes,
+        writer: BodyWriter::new(sender),
+    })
+}
@@ -0,0 +1,86 @@
+// Copyright 2016 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Support for compressed bodies with a `Content-Encoding` of `gzip` or `deflate`
+//! (requires the `flate2` feature)
+
+use std::io::Read;
+
+use flate2::read::{GzDecoder, GzEncoder, ZlibDecoder, ZlibEncoder};
+use flate2::Compression;
+use header::Headers;
+
+/// The name of the `Content-Encoding` header
+pub const CONTENT_ENCODING: &'static str = "Content-Encoding";
+
+/// A `Content-Encoding` compressing a body
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ContentCoding {
+    /// `gzip` (RFC 1952)
+    Gzip,
+    /// `deflate`: the zlib format (RFC 1950)
+    Deflate,
+}
+impl ContentCoding {
+    /// The `Content-Encoding` specified in the headers, if any.  Returns `None` if the
+    /// header is missing or names another coding.
+    pub fn from_headers(headers: &Headers) -> Option<ContentCoding> {
+        let raw = match headers.get_raw(CONTENT_ENCODING).and_then(|r| r.one()) {
+            Some(raw) => raw,
+            None => return None,
+        };
+        let value = String::from_utf8_lossy(raw).trim().to_ascii_lowercase();
+        match &*value {
+            "gzip" | "x-gzip" => Some(ContentCoding::Gzip),
+            "deflate" => Some(ContentCoding::Deflate),
+            _ => None,
+        }
+    }
+
+    /// The name of the coding, as used in the `Content-Encoding` header
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            ContentCoding::Gzip => "gzip",
+            ContentCoding::Deflate => "deflate",
+        }
+    }
+}
+
+// Compress a body with the coding given as it is read
+pub(crate) fn encoder<'a>(body: Box<dyn Read + 'a>, coding: ContentCoding)
+                          -> Box<dyn Read + 'a>
+{
+    match coding {
+        ContentCoding::Gzip => Box::new(GzEncoder::new(body, Compression::default())),
+        ContentCoding::Deflate => Box::new(ZlibEncoder::new(body, Compression::default())),
+    }
+}
+
+/// Decompress a body with the coding given as it is read
+pub fn decoder<'a, R: Read + 'a>(stream: R, coding: ContentCoding) -> Box<dyn Read + 'a> {
+    match coding {
+        ContentCoding::Gzip => Box::new(GzDecoder::new(stream)),
+        ContentCoding::Deflate => Box::new(ZlibDecoder::new(stream)),
+    }
+}
+
+/// Decompress a body according to the `Content-Encoding` in its headers, so that a
+/// compressed multipart body may be parsed as any other:
+///
+/// ```rust,ignore
+/// let mut body = decode_body(stream, &headers);
+/// let nodes = read_multipart_body(&mut body, &headers, false)?;
+/// ```
+///
+/// The body is passed through unchanged if it has no `Content-Encoding`, or one other than
+/// `gzip` or `deflate`.
+pub fn decode_body<'a, R: Read + 'a>(stream: R, headers: &Headers) -> Box<dyn Read + 'a> {
+    match ContentCoding::from_headers(headers) {
+        Some(coding) => decoder(stream, coding),
+        None => Box::new(stream),
+    }
+}
@@ -0,0 +1,300 @@
+// Copyright 2016 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Deserialization of `multipart/form-data` fields with serde (requires the `serde` feature)
+
+use std::fmt::Display;
+use std::vec;
+
+use serde::de::{self, Deserialize, DeserializeSeed, IntoDeserializer, MapAccess, SeqAccess,
+                Visitor};
+
+use super::{Error, FilePart, Node};
+use form_data::field_name;
+
+impl de::Error for Error {
+    fn custom<T: Display>(msg: T) -> Error {
+        Error::Deserialize(msg.to_string())
+    }
+}
+
+/// Deserialize the fields of a parsed `multipart/form-data` into `T`, typically a struct
+/// with a member per field name.
+///
+/// Text fields deserialize as strings, or are parsed as numbers and bools (a checkbox's
+/// `on` counting as `true`) or as the names of unit enum variants.  A field submitted more
+/// than once deserializes as a sequence; otherwise its first value is used.
+///
+/// File fields deserialize as the path of their file, which remains valid for as long as
+/// `nodes` does, or as their content when bytes are asked for (as by `serde_bytes`, but not
+/// by a plain `Vec<u8>`, which asks for a sequence).  A file input left empty (with an empty
+/// filename and no content) counts as missing for an `Option`.  The files of a nested
+/// `multipart/mixed` are values of the field it names, as text if they were kept in memory.
+pub fn from_multipart<'de, T: Deserialize<'de>>(nodes: &'de [Node]) -> Result<T, Error> {
+    let mut fields: Vec<(String, Vec<Value<'de>>)> = Vec::new();
+    for node in nodes {
+        match *node {
+            Node::Part(ref part) => {
+                if let Some(name) = try!(field_name(&part.headers)) {
+                    let text = try!(part.text());
+                    add_field(&mut fields, name, Value::Text(text));
+                }
+            },
+            Node::File(ref file) => {
+                if let Some(name) = try!(field_name(&file.headers)) {
+                    add_field(&mut fields, name, Value::File(file));
+                }
+            },
+            Node::Multipart((ref headers, ref subnodes)) => {
+                if let Some(name) = try!(field_name(headers)) {
+                    for subnode in subnodes {
+                        match *subnode {
+                            Node::Part(ref part) => {
+                                let text = try!(part.text());
+                                add_field(&mut fields, name.clone(), Value::Text(text));
+                            },
+                            Node::File(ref file) => {
+                                add_field(&mut fields, name.clone(), Value::File(file));
+                            },
+                            _ => { },
+                        }
+                    }
+                }
+            },
+            // Only produced under `ParseOptions::parse_messages`, which forms don't use
+            Node::Message(_) => { },
+            // Only produced under `ParseOptions::recover`; malformed parts are left out
+            Node::Invalid(_) => { },
+        }
+    }
+    T::deserialize(FormDeserializer { fields: fields })
+}
+
+// Add a value to a field, keeping repeated fields together in the order first seen
+fn add_field<'de>(fields: &mut Vec<(String, Vec<Value<'de>>)>, name: String,
+                  value: Value<'de>)
+{
+    if let Some(&mut (_, ref mut values)) = fields.iter_mut()
+        .find(|&&mut (ref n, _)| *n == name)
+    {
+        values.push(value);
+        return;
+    }
+    fields.push((name, vec![value]));
+}
+
+enum Value<'de> {
+    Text(String),
+    File(&'de FilePart),
+}
+
+// Deserializes the form as a map from field names to values
+struct FormDeserializer<'de> {
+    fields: Vec<(String, Vec<Value<'de>>)>,
+}
+
+impl<'de> de::Deserializer<'de> for FormDeserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_map(FieldsAccess {
+            fields: self.fields.into_iter(),
+            values: None,
+        })
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 u8 u16 u32 u64 f32 f64 char str string bytes byte_buf option
+        unit unit_struct newtype_struct seq tuple tuple_struct map struct enum identifier
+        ignored_any
+    }
+}
+
+struct FieldsAccess<'de> {
+    fields: vec::IntoIter<(String, Vec<Value<'de>>)>,
+    values: Option<Vec<Value<'de>>>,
+}
+
+impl<'de> MapAccess<'de> for FieldsAccess<'de> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K)
+                                              -> Result<Option<K::Value>, Error>
+    {
+        match self.fields.next() {
+            Some((name, values)) => {
+                self.values = Some(values);
+                seed.deserialize(name.into_deserializer()).map(Some)
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        match self.values.take() {
+            Some(values) => seed.deserialize(ValueDeserializer { values: values }),
+            None => Err(de::Error::custom("value requested before key")),
+        }
+    }
+}
+
+// Deserializes the values of one field
+struct ValueDeserializer<'de> {
+    values: Vec<Value<'de>>,
+}
+
+impl<'de> ValueDeserializer<'de> {
+    fn first(self) -> Result<Value<'de>, Error> {
+        match self.values.into_iter().next() {
+            Some(value) => Ok(value),
+            None => Err(de::Error::custom("field has no value")),
+        }
+    }
+
+    fn text(self) -> Result<String, Error> {
+        match try!(self.first()) {
+            Value::Text(text) => Ok(text),
+            Value::File(_) => Err(de::Error::custom("expected a text field, found a file")),
+        }
+    }
+}
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+                let text = try!(self.text());
+                match text.trim().parse() {
+                    Ok(value) => visitor.$visit(value),
+                    Err(_) => Err(de::Error::custom(format!("invalid value {:?}", text))),
+                }
+            }
+        )*
+    }
+}
+
+impl<'de> de::Deserializer<'de> for ValueDeserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        if self.values.len() > 1 {
+            return self.deserialize_seq(visitor);
+        }
+        match try!(self.first()) {
+            Value::Text(text) => visitor.visit_string(text),
+            Value::File(file) => visitor.visit_borrowed_str(try!(file_path(file))),
+        }
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match try!(self.first()) {
+            Value::Text(text) => visitor.visit_string(text),
+            Value::File(file) => visitor.visit_borrowed_str(try!(file_path(file))),
+        }
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match try!(self.first()) {
+            Value::Text(text) => visitor.visit_byte_buf(text.into_bytes()),
+            Value::File(file) => {
+                let mut content: Vec<u8> = Vec::new();
+                try!(::std::fs::File::open(&file.path)
+                     .and_then(|mut f| ::std::io::Read::read_to_end(&mut f, &mut content)));
+                visitor.visit_byte_buf(content)
+            },
+        }
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let text = try!(self.text());
+        match &*text.trim().to_ascii_lowercase() {
+            "true" | "on" => visitor.visit_bool(true),
+            "false" | "off" => visitor.visit_bool(false),
+            _ => Err(de::Error::custom(format!("invalid bool {:?}", text))),
+        }
+    }
+
+    deserialize_parsed! {
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+        deserialize_char => visit_char,
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let empty_file = match self.values.first() {
+            Some(&Value::File(file)) => {
+                file.size == Some(0) && file.filename().ok() == Some(Some(String::new()))
+            },
+            Some(&Value::Text(_)) => false,
+            None => true,
+        };
+        if empty_file {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_seq(ValuesAccess { values: self.values.into_iter() })
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V)
+                                                   -> Result<V::Value, Error>
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, _name: &'static str,
+                                         _variants: &'static [&'static str], visitor: V)
+                                         -> Result<V::Value, Error>
+    {
+        let text = try!(self.text());
+        visitor.visit_enum(text.into_deserializer())
+    }
+
+    forward_to_deserialize_any! {
+        unit unit_struct tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+struct ValuesAccess<'de> {
+    values: vec::IntoIter<Value<'de>>,
+}
+
+impl<'de> SeqAccess<'de> for ValuesAccess<'de> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T)
+                                                  -> Result<Option<T::Value>, Error>
+    {
+        match self.values.next() {
+            Some(value) => seed.deserialize(ValueDeserializer { values: vec![value] }).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+fn file_path(file: &FilePart) -> Result<&str, Error> {
+    file.path.to_str().ok_or_else(|| de::Error::custom("file path is not UTF-8"))
+}
@@ -0,0 +1,150 @@
+// Copyright 2016 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Parsing and writing whole email messages (RFC 5322), whose headers come first and whose
+//! body may or may not be multipart
+
+use std::io::{BufRead, Read, Write};
+
+use bytes::Bytes;
+use header::{ContentType, Headers};
+use mime;
+
+use super::{inner, read_header_block, read_part_body, with_boundary, with_context,
+            write_headers, write_multipart_with_options, Error, HeaderFields, MultipartBody,
+            Node, ParseContext, ParseOptions, Part, WriteAllCount, WriteOptions};
+use part_headers::PartHeaders;
+use transfer_encoding::EncodingPolicy;
+
+const MIME_VERSION: &'static str = "MIME-Version";
+
+/// A parsed email message
+#[derive(Debug)]
+pub struct Email {
+    /// The message headers, such as `Subject` and `From`
+    pub headers: Headers,
+    /// The message headers in their original order, with repeated fields such as `Received`
+    /// kept separately, and folded lines joined
+    pub fields: HeaderFields,
+    /// The body, parsed according to its `Content-Type`: a `Node::Multipart` holding the
+    /// tree of parts if it is `multipart/*`, and otherwise a `Node::Part` or `Node::File`,
+    /// stored as a part would be.  The headers of the node are the message headers.
+    pub body: Node,
+}
+
+/// Parse an email message from a `Read`able stream: its headers, which may be folded and
+/// end lines with either CRLF or LF, and then its body according to its `Content-Type`, as
+/// `read_multipart_with_options()` would for a multipart one.  A body which is not
+/// multipart is read to the end of the stream, and stored under `ParseOptions::storage`
+/// and the other options just as the body of a part is, with any
+/// `Content-Transfer-Encoding` decoded under `ParseOptions::decode_transfer_encoding`.  A
+/// message without a `Content-Type` is taken to be `text/plain; charset=us-ascii` under
+/// `ParseOptions::default_content_types`.
+///
+/// The number of message headers is limited by `ParseOptions::max_main_headers`.
+pub fn read_email<S: Read>(stream: &mut S, options: &ParseOptions)
+                           -> Result<Email, Error>
+{
+    with_context(stream, options, |reader, context| {
+        let raw_headers = match try!(read_header_block(reader)) {
+            Some(raw_headers) => raw_headers,
+            None => return Err(Error::EofInMainHeaders),
+        };
+        let fields = HeaderFields::parse(&raw_headers);
+        if let Some(max) = context.options.max_main_headers {
+            if fields.fields.len() > max {
+                return Err(Error::TooManyHeaders { max: max });
+            }
+        }
+        let mut headers = fields.fields.to_headers().into_owned();
+        if context.options.default_content_types && ! headers.has::<ContentType>() {
+            headers.set(ContentType("text/plain; charset=us-ascii".parse().unwrap()));
+        }
+
+        let multipart = match headers.get::<ContentType>() {
+            Some(ct) => ct.type_() == mime::MULTIPART,
+            None => false,
+        };
+        let body = if multipart {
+            let mut body = MultipartBody::default();
+            try!(inner(reader, &headers, context, None, &mut body));
+            Node::Multipart((headers, body.nodes))
+        } else {
+            try!(read_single_part(reader, headers, raw_headers, context))
+        };
+        Ok(Email {
+            headers: body.headers().clone(),
+            fields: fields,
+            body: body,
+        })
+    })
+}
+
+// Read the rest of the stream as the body of a message which is not multipart, storing it
+// as the body of a part would be.  A body skipped under `ParseOptions::storage_decision` is
+// left empty.
+fn read_single_part<R: BufRead>(reader: &mut R, headers: Headers, raw_headers: Vec<u8>,
+                                context: &ParseContext)
+                                -> Result<Node, Error>
+{
+    let mut buf: Vec<u8> = Vec::new();
+    let (node, _) = try!(read_part_body(reader, b"", headers.clone(), raw_headers.clone(),
+                                        None, &mut buf, &mut MultipartBody::default(),
+                                        context));
+    Ok(node.unwrap_or_else(|| Node::Part(Part {
+        headers: headers,
+        body: Bytes::new(),
+        raw_headers: Some(raw_headers),
+    })))
+}
+
+/// Stream a complete email message to the output `stream` given: the message `headers`
+/// (such as `From`, `To` and `Subject`), with `MIME-Version: 1.0` added if missing, and the
+/// `Content-Type` set as `write_multipart_with_headers()` sets it, carrying `boundary`; then
+/// a body made up of the `nodes` given, ending with a line terminator.
+///
+/// Bodies are given a `Content-Transfer-Encoding` as under `EncodingPolicy::Auto`, so that
+/// all of the message is US-ASCII in CRLF terminated lines of at most 998 octets, as SMTP
+/// requires, and is ready for the transport to dot-stuff.  Parts which already carry a
+/// `Content-Transfer-Encoding` are written as they are, and must already be so.
+/// Returns the number of bytes written, or an error.
+pub fn write_email<S: Write, H: PartHeaders + ?Sized>(
+    stream: &mut S,
+    headers: &H,
+    boundary: &Vec<u8>,
+    nodes: &Vec<Node>)
+    -> Result<usize, Error>
+{
+    let content_type = match headers.content_type() {
+        Some(ref mime) if mime.type_() == mime::MULTIPART => mime.clone(),
+        _ => "multipart/mixed".parse().unwrap(),
+    };
+    let mut headers = headers.to_headers().into_owned();
+    if headers.get_raw(MIME_VERSION).is_none() {
+        headers.set_raw(MIME_VERSION, "1.0");
+    }
+    headers.set(ContentType(try!(with_boundary(&content_type, boundary))));
+
+    let mut options = WriteOptions::default();
+    options.transfer_encoding = EncodingPolicy::Auto;
+    let mut count: usize = 0;
+    count += try!(write_headers(stream, &headers, &options));
+    count += try!(write_multipart_with_options(stream, boundary, nodes, &options));
+    count += try!(stream.write_all_count(b"\r\n"));
+    Ok(count)
+}
+
+/// A complete email message, as written by `write_email()`, e.g. to be handed to an SMTP
+/// client such as lettre's `Transport::send_raw()`
+pub fn email_message<H: PartHeaders + ?Sized>(headers: &H, boundary: &Vec<u8>,
+                                              nodes: &Vec<Node>)
+                                              -> Result<Vec<u8>, Error>
+{
+    let mut message: Vec<u8> = Vec::new();
+    try!(write_email(&mut message, headers, boundary, nodes));
+    Ok(message)
+}
@@ -11,9 +11,21 @@ use std::fmt::{self, Display};
 use std::io;
 use std::string::FromUtf8Error;
 
-use super::{httparse, hyper};
+use super::httparse;
+#[cfg(feature = "hyper")]
+use super::hyper;
+use part_headers::PartHeaders;
+#[cfg(feature = "sniff")]
+use mime::Mime;
+#[cfg(feature = "reqwest")]
+use super::reqwest;
 
 /// An error type for the `mime-multipart` crate.
+///
+/// Variants may be added in future, so matches on it need a wildcard arm.  Errors caused by
+/// another (such as an I/O error) return it from `source()`, and `limit()` tells which limit
+/// was exceeded, whichever variant reports it.
+#[non_exhaustive]
 pub enum Error {
     /// The Hyper request did not have a Content-Type header.
     NoRequestContentType,
@@ -29,16 +41,191 @@ pub enum Error {
     EofInPartHeaders,
     EofInFile,
     EofInPart,
+    /// The boundary is longer than 70 characters, or contains characters not permitted by
+    /// RFC 2046.
+    InvalidBoundary,
+    /// A bare LF line terminator was found where CRLF is required.
+    BareLineFeed,
+    /// The parse was cancelled through `ParseOptions::cancel`.
+    Cancelled,
+    /// A `Content-Range` header is missing or malformed.
+    InvalidContentRange,
+    /// The length of a part body differs from that of its `Content-Range`.
+    RangeLengthMismatch,
+    /// The `start` parameter of a `multipart/related` names no part.
+    StartNotFound,
+    /// A `multipart/signed` does not consist of exactly two parts.
+    WrongNumberOfParts,
+    /// The body is longer than the most allowed.
+    BodyTooLong,
+    /// More was written to files than `ParseOptions::disk_quota` allows.
+    DiskQuotaExceeded,
+    /// A part was rejected by `ParseOptions::inspect`.
+    PartRejected,
+    /// A block of headers has more fields than the most allowed, `max`, such as by
+    /// `ParseOptions::max_part_headers`.
+    TooManyHeaders {
+        max: usize,
+    },
+    /// A block of headers is longer than the most bytes allowed, `max`, such as by
+    /// `MultipartReader::set_max_header_length()`.
+    HeadersTooLong {
+        max: usize,
+    },
+    /// A header is present but its value could not be parsed.  Holds the header name.
+    MalformedHeader(String),
+    /// Parsing failed where the `ErrorPosition` tells.  Only returned under
+    /// `ParseOptions::error_positions`.
+    At(Box<ErrorPosition>),
     /// An HTTP parsing error from a multipart section.
     Httparse(httparse::Error),
     /// An I/O error.
     Io(io::Error),
     /// An error was returned from Hyper.
+    #[cfg(feature = "hyper")]
     Hyper(hyper::Error),
     /// An error occurred during UTF-8 processing.
     Utf8(FromUtf8Error),
     /// An error occurred during character decoding
     Decoding(Cow<'static, str>),
+    /// Form fields could not be deserialized into the requested type
+    Deserialize(String),
+    /// A part of a `multipart/form-data` has no `Content-Disposition` of `form-data` with
+    /// the name of the field.
+    NoFieldName,
+    /// A form field has the same name as an earlier one, under `DuplicateFields::Reject`
+    DuplicateField(String),
+    /// A header to be written has a name or value containing a CR or LF, which would end it
+    /// early and let the rest pass as further headers or a boundary.  Holds the header name,
+    /// or `method` or `target` for those of an `HttpRequest` containing whitespace.
+    InvalidHeader(String),
+    /// The type detected from the content of a file part contradicts its `Content-Type`,
+    /// under `ParseOptions::content_type_policy`.
+    #[cfg(feature = "sniff")]
+    ContentTypeMismatch {
+        declared: Box<Mime>,
+        detected: Box<Mime>,
+    },
+    /// A file part is of a type not accepted by `ParseOptions::content_type_policy`.
+    #[cfg(feature = "sniff")]
+    ContentTypeNotAllowed(Mime),
+    /// The body of a part differs from its `Content-MD5`, under
+    /// `ParseOptions::verify_content_md5`.
+    #[cfg(feature = "md5")]
+    ContentMd5Mismatch,
+    /// A nested multipart, message or invalid part was given where a single part was
+    /// expected.
+    #[cfg(feature = "reqwest")]
+    NotAPart,
+    /// An error was returned from reqwest.
+    #[cfg(feature = "reqwest")]
+    Reqwest(reqwest::Error),
+    /// An object store refused a request, responding with the HTTP status and the error
+    /// code (such as `NoSuchBucket`) given, if any.
+    #[cfg(feature = "s3")]
+    ObjectStore {
+        status: u16,
+        code: Option<String>,
+    },
+}
+
+/// Where parsing failed, as reported by `Error::At`
+#[derive(Debug)]
+pub struct ErrorPosition {
+    /// The byte offset into the stream at which parsing failed
+    pub offset: u64,
+    /// The index of the part being read: the number of parts (nested ones included)
+    /// completed before it
+    pub part: usize,
+    /// The headers of that part, as `(name, raw value)` pairs, if they had been read
+    pub headers: Option<Vec<(String, Vec<u8>)>>,
+    /// The error itself
+    pub error: Error,
+}
+
+/// A limit on what is parsed, as exceeded by an `Error`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Limit {
+    /// The number of fields in a block of headers, as set by
+    /// `ParseOptions::max_part_headers` or `ParseOptions::max_main_headers`
+    Headers,
+    /// The length in bytes of a block of headers, as set by
+    /// `MultipartReader::set_max_header_length()`
+    HeaderLength,
+    /// The length of a body, such as `FilePart::bytes()` is given
+    BodyLength,
+    /// The bytes written to files, as set by `ParseOptions::disk_quota`
+    DiskQuota,
+}
+
+impl Error {
+    /// The limit which was exceeded, if that is why parsing failed
+    pub fn limit(&self) -> Option<Limit> {
+        match *self.inner() {
+            Error::TooManyHeaders { .. } => Some(Limit::Headers),
+            Error::HeadersTooLong { .. } => Some(Limit::HeaderLength),
+            Error::BodyTooLong => Some(Limit::BodyLength),
+            Error::DiskQuotaExceeded => Some(Limit::DiskQuota),
+            _ => None,
+        }
+    }
+
+    /// The error itself, without the position of an `Error::At`
+    pub fn inner(&self) -> &Error {
+        match *self {
+            Error::At(ref position) => &position.error,
+            ref error => error,
+        }
+    }
+
+    /// The error itself, without the position of an `Error::At`
+    pub fn into_inner(self) -> Error {
+        match self {
+            Error::At(position) => position.error,
+            error => error,
+        }
+    }
+
+    /// The byte offset into the stream at which parsing failed, if known
+    pub fn offset(&self) -> Option<u64> {
+        match *self {
+            Error::At(ref position) => Some(position.offset),
+            _ => None,
+        }
+    }
+
+    /// The index of the part being read when parsing failed, if known
+    pub fn part(&self) -> Option<usize> {
+        match *self {
+            Error::At(ref position) => Some(position.part),
+            _ => None,
+        }
+    }
+
+    /// The headers of the part being read when parsing failed, if they had been read
+    pub fn part_headers(&self) -> Option<&Vec<(String, Vec<u8>)>> {
+        match *self {
+            Error::At(ref position) => position.headers.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// The field name (the `name` parameter of the `Content-Disposition`) of the part being
+    /// read when parsing failed, if known
+    pub fn part_name(&self) -> Option<String> {
+        self.part_headers()
+            .and_then(|headers| headers.content_disposition_param("name").ok())
+            .and_then(|name| name)
+    }
+
+    /// The filename (the `filename` parameter of the `Content-Disposition`) of the part
+    /// being read when parsing failed, if known
+    pub fn part_filename(&self) -> Option<String> {
+        self.part_headers()
+            .and_then(|headers| headers.content_disposition_param("filename").ok())
+            .and_then(|filename| filename)
+    }
 }
 
 impl From<io::Error> for Error {
@@ -53,12 +240,20 @@ impl From<httparse::Error> for Error {
     }
 }
 
+#[cfg(feature = "hyper")]
 impl From<hyper::Error> for Error {
     fn from(err: hyper::Error) -> Error {
         Error::Hyper(err)
     }
 }
 
+#[cfg(feature = "reqwest")]
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Error {
+        Error::Reqwest(err)
+    }
+}
+
 impl From<FromUtf8Error> for Error {
     fn from(err: FromUtf8Error) -> Error {
         Error::Utf8(err)
@@ -72,12 +267,48 @@ impl Display for Error {
                 format!("{}: {:?}", self.description(), e).fmt(f),
             Error::Io(ref e) =>
                 format!("{}: {}", self.description(), e).fmt(f),
+            #[cfg(feature = "hyper")]
             Error::Hyper(ref e) =>
                 format!("{}: {}", self.description(), e).fmt(f),
             Error::Utf8(ref e) =>
                 format!("{}: {}", self.description(), e).fmt(f),
             Error::Decoding(ref e) =>
                 format!("{}: {}", self.description(), e).fmt(f),
+            Error::Deserialize(ref e) =>
+                format!("{}: {}", self.description(), e).fmt(f),
+            Error::DuplicateField(ref name) =>
+                format!("{}: {}", self.description(), name).fmt(f),
+            Error::InvalidHeader(ref name) =>
+                format!("{}: {}", self.description(), name).fmt(f),
+            Error::MalformedHeader(ref name) =>
+                format!("{}: {}", self.description(), name).fmt(f),
+            Error::TooManyHeaders { max } =>
+                format!("{} (at most {})", self.description(), max).fmt(f),
+            Error::HeadersTooLong { max } =>
+                format!("{} (at most {} bytes)", self.description(), max).fmt(f),
+            #[cfg(feature = "reqwest")]
+            Error::Reqwest(ref e) =>
+                format!("{}: {}", self.description(), e).fmt(f),
+            #[cfg(feature = "s3")]
+            Error::ObjectStore { status, code: Some(ref code) } =>
+                format!("{}: {} {}", self.description(), status, code).fmt(f),
+            #[cfg(feature = "s3")]
+            Error::ObjectStore { status, code: None } =>
+                format!("{}: {}", self.description(), status).fmt(f),
+            #[cfg(feature = "sniff")]
+            Error::ContentTypeMismatch { ref declared, ref detected } =>
+                format!("{}: declared {}, detected {}", self.description(), declared,
+                        detected).fmt(f),
+            #[cfg(feature = "sniff")]
+            Error::ContentTypeNotAllowed(ref content_type) =>
+                format!("{}: {}", self.description(), content_type).fmt(f),
+            Error::At(ref position) => match self.part_name() {
+                Some(name) => format!("{} (at byte {}, in part {}, field \"{}\")",
+                                      position.error, position.offset, position.part,
+                                      name).fmt(f),
+                None => format!("{} (at byte {}, in part {})", position.error,
+                                position.offset, position.part).fmt(f),
+            },
             _ => format!("{}", self.description()).fmt(f),
         }
     }
@@ -85,9 +316,20 @@ impl Display for Error {
 
 impl fmt::Debug for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Error::At(ref position) = *self {
+            try!( write!(f, "{:?} (at byte {}, in part {}", position.error, position.offset,
+                         position.part) );
+            if let Some(name) = self.part_name() {
+                try!( write!(f, ", field {:?}", name) );
+            }
+            if let Some(filename) = self.part_filename() {
+                try!( write!(f, ", filename {:?}", filename) );
+            }
+            return f.write_str(")");
+        }
         try!( f.write_str(&*self.description()) );
-        if self.cause().is_some() {
-            try!( write!(f, ": {:?}", self.cause().unwrap()) ); // recurse
+        if let Some(source) = self.source() {
+            try!( write!(f, ": {:?}", source) ); // recurse
         }
         Ok(())
     }
@@ -115,12 +357,76 @@ impl StdError for Error {
                 "The request body ended prematurely while streaming a file part.",
             Error::EofInPart =>
                 "The request body ended prematurely while reading a multipart part.",
+            Error::InvalidBoundary =>
+                "The boundary is too long or contains invalid characters.",
+            Error::BareLineFeed =>
+                "A bare LF line terminator was found where CRLF is required.",
+            Error::Cancelled =>
+                "The parse was cancelled.",
+            Error::InvalidContentRange =>
+                "A Content-Range header is missing or malformed.",
+            Error::RangeLengthMismatch =>
+                "The length of a part body differs from that of its Content-Range.",
+            Error::StartNotFound =>
+                "The start parameter of a multipart/related names no part.",
+            Error::NoFieldName =>
+                "A part of a multipart/form-data has no Content-Disposition of form-data \
+                 with a field name.",
+            Error::WrongNumberOfParts =>
+                "A multipart/signed does not consist of exactly two parts.",
+            Error::BodyTooLong =>
+                "The body is longer than the most allowed.",
+            Error::DiskQuotaExceeded =>
+                "More was written to files than the disk quota allows.",
+            Error::PartRejected =>
+                "A part was rejected.",
+            Error::TooManyHeaders { .. } =>
+                "A block of headers has more fields than allowed.",
+            Error::HeadersTooLong { .. } =>
+                "A block of headers is longer than allowed.",
+            Error::MalformedHeader(_) => "A header could not be parsed.",
+            Error::At(ref position) => position.error.description(),
             Error::Httparse(_) =>
                 "A parse error occurred while parsing the headers of a multipart section.",
             Error::Io(_) => "An I/O error occurred.",
+            #[cfg(feature = "hyper")]
             Error::Hyper(_) => "A Hyper error occurred.",
             Error::Utf8(_) => "A UTF-8 error occurred.",
             Error::Decoding(_) => "A decoding error occurred.",
+            Error::Deserialize(_) => "A deserialization error occurred.",
+            Error::DuplicateField(_) => "A form field was given more than once.",
+            Error::InvalidHeader(_) => "A header to be written contains a line break.",
+            #[cfg(feature = "sniff")]
+            Error::ContentTypeMismatch { .. } =>
+                "The content of a file part contradicts its Content-Type.",
+            #[cfg(feature = "sniff")]
+            Error::ContentTypeNotAllowed(_) =>
+                "A file part is of a type which is not allowed.",
+            #[cfg(feature = "md5")]
+            Error::ContentMd5Mismatch =>
+                "The body of a part differs from its Content-MD5.",
+            #[cfg(feature = "reqwest")]
+            Error::NotAPart =>
+                "A nested multipart, message or invalid part was given where a single part \
+                 was expected.",
+            #[cfg(feature = "reqwest")]
+            Error::Reqwest(_) => "A reqwest error occurred.",
+            #[cfg(feature = "s3")]
+            Error::ObjectStore { .. } => "The object store refused a request.",
+        }
+    }
+
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match *self {
+            Error::At(ref position) => Some(&position.error),
+            Error::Httparse(ref e) => Some(e),
+            Error::Io(ref e) => Some(e),
+            #[cfg(feature = "hyper")]
+            Error::Hyper(ref e) => Some(e),
+            Error::Utf8(ref e) => Some(e),
+            #[cfg(feature = "reqwest")]
+            Error::Reqwest(ref e) => Some(e),
+            _ => None,
         }
     }
 }
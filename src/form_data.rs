@@ -0,0 +1,209 @@
+// Copyright 2016 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! A `multipart/form-data` submission as named fields, built on top of parsing
+
+use std::io::Read;
+
+use header::Headers;
+
+use super::{disposition_param, read_multipart_body_with_options, Error, FilePart, Node,
+            ParseOptions};
+
+/// The value of a form field
+#[derive(Clone, Debug, PartialEq)]
+pub enum FormValue {
+    /// A field kept in memory, decoded according to the charset of its `Content-Type`
+    /// (UTF-8 if none is given)
+    Text(String),
+    /// A field streamed to a file, typically an uploaded file
+    File(FilePart),
+}
+impl FormValue {
+    /// The text, if this is a text field
+    pub fn as_text(&self) -> Option<&str> {
+        match *self {
+            FormValue::Text(ref text) => Some(text),
+            FormValue::File(_) => None,
+        }
+    }
+
+    /// The file, if this is a file field
+    pub fn as_file(&self) -> Option<&FilePart> {
+        match *self {
+            FormValue::Text(_) => None,
+            FormValue::File(ref file) => Some(file),
+        }
+    }
+}
+
+/// How fields with the same name as an earlier one are treated, as set by
+/// `ParseOptions::duplicate_fields`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DuplicateFields {
+    /// All fields are kept, so that `FormData::get_all()` returns each value given
+    KeepAll,
+    /// Only the first field with each name is kept
+    KeepFirst,
+    /// Only the last field with each name is kept, in the place of the first
+    KeepLast,
+    /// A repeated name is rejected with `Error::DuplicateField`
+    Reject,
+}
+
+/// The fields of a `multipart/form-data` submission, by name, in the order submitted
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FormData {
+    /// The (name, value) pairs.  A name may occur more than once.
+    pub entries: Vec<(String, FormValue)>,
+}
+impl FormData {
+    /// Parse a `multipart/form-data` body from a `Read`able stream into its fields.  Fields
+    /// with the same name as an earlier one are treated according to
+    /// `options.duplicate_fields`.
+    ///
+    /// It is presumed that you have the `Headers` already and the stream starts at the body.
+    pub fn read<S: Read>(stream: &mut S, headers: &Headers, options: &ParseOptions)
+                         -> Result<FormData, Error>
+    {
+        let nodes = try!(read_multipart_body_with_options(stream, headers, options));
+        let form = try!(FormData::from_nodes(nodes));
+        form.with_duplicates(options.duplicate_fields)
+    }
+
+    /// Collect the fields from parsed nodes.  Parts without a `Content-Disposition` name are
+    /// left out.  The files of a nested `multipart/mixed` (as sent for a multiple file
+    /// selection under RFC 2388) each take the name of the nested multipart, whether they
+    /// were stored in files or, as text, in memory.
+    pub fn from_nodes(nodes: Vec<Node>) -> Result<FormData, Error> {
+        let mut entries: Vec<(String, FormValue)> = Vec::new();
+        for node in nodes {
+            match node {
+                Node::Part(part) => {
+                    if let Some(name) = try!(field_name(&part.headers)) {
+                        let text = try!(part.text());
+                        entries.push((name, FormValue::Text(text)));
+                    }
+                },
+                Node::File(file) => {
+                    if let Some(name) = try!(field_name(&file.headers)) {
+                        entries.push((name, FormValue::File(file)));
+                    }
+                },
+                Node::Multipart((headers, subnodes)) => {
+                    if let Some(name) = try!(field_name(&headers)) {
+                        for subnode in subnodes {
+                            match subnode {
+                                Node::Part(part) => {
+                                    let text = try!(part.text());
+                                    entries.push((name.clone(), FormValue::Text(text)));
+                                },
+                                Node::File(file) => {
+                                    entries.push((name.clone(), FormValue::File(file)));
+                                },
+                                _ => { },
+                            }
+                        }
+                    }
+                },
+                // Only produced under `ParseOptions::parse_messages`, which forms don't use
+                Node::Message(_) => { },
+                // Only produced under `ParseOptions::recover`; malformed parts are left out
+                Node::Invalid(_) => { },
+            }
+        }
+        Ok(FormData { entries: entries })
+    }
+
+    /// Treat fields with the same name as an earlier one according to `policy`, as
+    /// `read()` does
+    pub fn with_duplicates(self, policy: DuplicateFields) -> Result<FormData, Error> {
+        let mut entries: Vec<(String, FormValue)> = Vec::with_capacity(self.entries.len());
+        for (name, value) in self.entries {
+            let earlier = entries.iter().position(|&(ref n, _)| *n == name);
+            match (earlier, policy) {
+                (None, _) | (Some(_), DuplicateFields::KeepAll) => entries.push((name, value)),
+                (Some(_), DuplicateFields::KeepFirst) => { },
+                (Some(i), DuplicateFields::KeepLast) => entries[i].1 = value,
+                (Some(_), DuplicateFields::Reject) => return Err(Error::DuplicateField(name)),
+            }
+        }
+        Ok(FormData { entries: entries })
+    }
+
+    /// The value of the first field with this name
+    pub fn get(&self, name: &str) -> Option<&FormValue> {
+        self.entries.iter()
+            .find(|&&(ref n, _)| n == name)
+            .map(|&(_, ref v)| v)
+    }
+
+    /// The values of all fields with this name, in order
+    pub fn get_all(&self, name: &str) -> Vec<&FormValue> {
+        self.entries.iter()
+            .filter(|&&(ref n, _)| n == name)
+            .map(|&(_, ref v)| v)
+            .collect()
+    }
+
+    /// The text fields, as (name, text) pairs
+    pub fn fields(&self) -> Vec<(&str, &str)> {
+        self.entries.iter()
+            .filter_map(|&(ref n, ref v)| v.as_text().map(|text| (&**n, text)))
+            .collect()
+    }
+
+    /// The file fields, as (name, file) pairs
+    pub fn files(&self) -> Vec<(&str, &FilePart)> {
+        self.entries.iter()
+            .filter_map(|&(ref n, ref v)| v.as_file().map(|file| (&**n, file)))
+            .collect()
+    }
+}
+
+/// The value of a `Content-Disposition` header for the form field `name`, as RFC 7578 and
+/// browsers write it: `form-data; name="..."`, followed by `; filename="..."` for a file.
+///
+/// Within the quoted values, `"`, CR and LF are percent-encoded (as `%22`, `%0D` and
+/// `%0A`), and other characters are written as they are, in UTF-8.  A filename which is not
+/// ASCII is also given as an RFC 5987 `filename*` parameter, which parsers preferring it
+/// (as `FilePart::filename()` does) decode unambiguously.
+pub fn form_data_disposition(name: &str, filename: Option<&str>) -> String {
+    let mut value = format!("form-data; name=\"{}\"", escape_quoted(name));
+    if let Some(filename) = filename {
+        value.push_str(&format!("; filename=\"{}\"", escape_quoted(filename)));
+        if ! filename.is_ascii() {
+            value.push_str("; filename*=UTF-8''");
+            value.push_str(&percent_encode(filename));
+        }
+    }
+    value
+}
+
+// Escape a value to be quoted within a `Content-Disposition`, as browsers do
+fn escape_quoted(value: &str) -> String {
+    value.replace('"', "%22").replace('\r', "%0D").replace('\n', "%0A")
+}
+
+// Percent-encode a value as the RFC 5987 `value-chars` of an extended parameter
+fn percent_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for &b in value.as_bytes() {
+        match b {
+            b'0'..=b'9' | b'a'..=b'z' | b'A'..=b'Z' | b'!' | b'#' | b'$' | b'&' | b'+' | b'-'
+                | b'.' | b'^' | b'_' | b'`' | b'|' | b'~' => encoded.push(b as char),
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+// The `name` parameter of the `Content-Disposition`, if any
+pub(crate) fn field_name(headers: &Headers) -> Result<Option<String>, Error> {
+    disposition_param(headers, "name")
+}
+
@@ -0,0 +1,837 @@
+// Copyright 2016 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Header fields, as held by every `Node`.
+//!
+//! With the `hyper` feature (on by default) this module is `hyper::header` itself.  Without
+//! it, it is this subset of that module, with the same names, methods and behaviour, so that
+//! code written against it builds unchanged when another crate of the build enables the
+//! feature.  Only the typed headers this crate reads and writes are here: `ContentType`,
+//! `ContentLength`, `ContentDisposition` and `Date`; any other header is reached through
+//! `get_raw()` and `set_raw()`.
+//!
+//! A few differences remain where hyper's own types could not be reproduced without its
+//! dependencies:
+//!
+//! * `Header` is sealed, so that further typed headers cannot be defined.
+//! * `DispositionParam` has only `Ext`, so that a `filename` parameter is read as
+//!   `Ext("filename", ..)`.  `FilePart::filename()` reads filenames under either.
+//! * `HttpDate` parses only the IMF-fixdate format, as every current sender writes.
+//! * `FromStr` fails with this crate's `Error`, rather than hyper's.
+
+use std::any::Any;
+use std::borrow::Cow;
+use std::fmt;
+use std::iter::FromIterator;
+use std::ops::{Deref, DerefMut};
+use std::slice;
+use std::str::{self, FromStr};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use mime::Mime;
+
+use super::Error;
+use date::{civil_from_days, days_from_civil};
+
+/// A header field with a typed value
+pub trait Header: 'static + Send + Sync + sealed::Sealed {
+    /// The name of the header field, such as `Content-Type`
+    fn header_name() -> &'static str;
+}
+
+mod sealed {
+    use std::fmt::Display;
+
+    use super::Raw;
+
+    pub trait Sealed: Clone + Display {
+        // The value of raw lines, or `None` if they are not one
+        fn parse_header(raw: &Raw) -> Option<Self>;
+    }
+}
+
+/// The raw lines of a header field's value, as received or set with `set_raw()`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Raw(Vec<Bytes>);
+
+impl Raw {
+    /// The number of lines
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// The only line, if there is exactly one
+    pub fn one(&self) -> Option<&[u8]> {
+        if self.0.len() == 1 { Some(&self.0[0]) } else { None }
+    }
+
+    /// Iterate over the lines
+    pub fn iter(&self) -> RawLines<'_> {
+        RawLines(self.0.iter())
+    }
+
+    /// Append the lines of `value`
+    pub fn push<V: Into<Raw>>(&mut self, value: V) {
+        self.0.extend(value.into().0);
+    }
+}
+
+impl<'a> IntoIterator for &'a Raw {
+    type IntoIter = RawLines<'a>;
+    type Item = &'a [u8];
+
+    fn into_iter(self) -> RawLines<'a> {
+        self.iter()
+    }
+}
+
+impl ::std::ops::Index<usize> for Raw {
+    type Output = [u8];
+
+    fn index(&self, idx: usize) -> &[u8] {
+        &self.0[idx]
+    }
+}
+
+impl PartialEq<[u8]> for Raw {
+    fn eq(&self, bytes: &[u8]) -> bool {
+        match self.0.len() {
+            0 => bytes.is_empty(),
+            1 => &*self.0[0] == bytes,
+            _ => false,
+        }
+    }
+}
+
+impl PartialEq<str> for Raw {
+    fn eq(&self, s: &str) -> bool {
+        self == s.as_bytes()
+    }
+}
+
+impl PartialEq<[Vec<u8>]> for Raw {
+    fn eq(&self, lines: &[Vec<u8>]) -> bool {
+        self.0.len() == lines.len() && self.iter().zip(lines).all(|(a, b)| a == &**b)
+    }
+}
+
+impl PartialEq<[&[u8]]> for Raw {
+    fn eq(&self, lines: &[&[u8]]) -> bool {
+        self.0.len() == lines.len() && self.iter().zip(lines).all(|(a, b)| a == *b)
+    }
+}
+
+impl From<Vec<Vec<u8>>> for Raw {
+    fn from(lines: Vec<Vec<u8>>) -> Raw {
+        Raw(lines.into_iter().map(Bytes::from).collect())
+    }
+}
+
+impl From<Vec<u8>> for Raw {
+    fn from(line: Vec<u8>) -> Raw {
+        Raw(vec![Bytes::from(line)])
+    }
+}
+
+impl From<String> for Raw {
+    fn from(line: String) -> Raw {
+        Raw::from(line.into_bytes())
+    }
+}
+
+impl<'a> From<&'a [u8]> for Raw {
+    fn from(line: &'a [u8]) -> Raw {
+        Raw(vec![Bytes::from(line)])
+    }
+}
+
+impl<'a> From<&'a str> for Raw {
+    fn from(line: &'a str) -> Raw {
+        Raw::from(line.as_bytes())
+    }
+}
+
+impl From<Bytes> for Raw {
+    fn from(line: Bytes) -> Raw {
+        Raw(vec![line])
+    }
+}
+
+/// An iterator over the lines of a `Raw`
+#[derive(Debug)]
+pub struct RawLines<'a>(slice::Iter<'a, Bytes>);
+
+impl<'a> Iterator for RawLines<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        self.0.next().map(|line| &**line)
+    }
+}
+
+// A typed value, kept alongside the raw lines it was parsed from or is written as
+trait Typed: Any + Send + Sync {
+    fn clone_box(&self) -> Box<dyn Typed>;
+    fn line(&self) -> String;
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<H: Header> Typed for H {
+    fn clone_box(&self) -> Box<dyn Typed> {
+        Box::new(self.clone())
+    }
+
+    fn line(&self) -> String {
+        self.to_string()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+impl Clone for Box<dyn Typed> {
+    fn clone(&self) -> Box<dyn Typed> {
+        self.clone_box()
+    }
+}
+
+// The value of a header field.  At least one of `raw` and `typed` is set; each is made from
+// the other when first needed.  A failure to parse is kept as a typed value of `None`.
+#[derive(Clone)]
+struct Item {
+    raw: OnceLock<Raw>,
+    typed: OnceLock<Option<Box<dyn Typed>>>,
+}
+
+impl Item {
+    fn new_raw(raw: Raw) -> Item {
+        Item { raw: OnceLock::from(raw), typed: OnceLock::new() }
+    }
+
+    fn new_typed<H: Header>(value: H) -> Item {
+        let typed: Box<dyn Typed> = Box::new(value);
+        Item { raw: OnceLock::new(), typed: OnceLock::from(Some(typed)) }
+    }
+
+    fn raw(&self) -> &Raw {
+        self.raw.get_or_init(|| {
+            let typed = self.typed.get().and_then(|typed| typed.as_ref())
+                .expect("a header without a raw value has a typed one");
+            Raw::from(replace_newlines(&typed.line()).into_owned())
+        })
+    }
+
+    fn raw_mut(&mut self) -> &mut Raw {
+        self.raw();
+        self.typed = OnceLock::new();
+        self.raw.get_mut().unwrap()
+    }
+
+    fn typed<H: Header>(&self) -> Option<&H> {
+        let typed = self.typed.get_or_init(|| {
+            let raw = self.raw.get().expect("a header without a typed value has a raw one");
+            H::parse_header(raw).map(|value| Box::new(value) as Box<dyn Typed>)
+        });
+        typed.as_ref().and_then(|typed| typed.as_any().downcast_ref())
+    }
+
+    fn typed_mut<H: Header>(&mut self) -> Option<&mut H> {
+        if self.typed::<H>().is_none() {
+            return None;
+        }
+        // The raw value is written again from the typed one, which may now be changed
+        self.raw = OnceLock::new();
+        self.typed.get_mut()
+            .and_then(|typed| typed.as_mut())
+            .and_then(|typed| typed.as_any_mut().downcast_mut())
+    }
+
+    // Write each line as `value`, or as `name: value\r\n` if `name` is given
+    fn write_lines(&self, name: Option<&str>, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, line) in self.raw().iter().enumerate() {
+            let line = try!(str::from_utf8(line).map_err(|_| fmt::Error));
+            match name {
+                Some(name) => try!(write!(f, "{}: {}\r\n", name, replace_newlines(line))),
+                None if i > 0 => try!(write!(f, ", {}", line)),
+                None => try!(f.write_str(line)),
+            }
+        }
+        Ok(())
+    }
+}
+
+// Replace line breaks with spaces, so that a value cannot begin another header
+fn replace_newlines(value: &str) -> Cow<'_, str> {
+    if value.contains(['\r', '\n']) {
+        Cow::Owned(value.replace(['\r', '\n'], " "))
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+/// A map of header fields, in the order they were first set.  Names are matched ignoring
+/// case.
+#[derive(Clone, Default)]
+pub struct Headers {
+    data: Vec<(Cow<'static, str>, Item)>,
+}
+
+impl Headers {
+    /// Creates a new, empty headers map.
+    pub fn new() -> Headers {
+        Headers { data: Vec::new() }
+    }
+
+    /// Set a header field to the corresponding value.
+    ///
+    /// The field is determined by the type of the value being set.
+    pub fn set<H: Header>(&mut self, value: H) {
+        self.insert(Cow::Borrowed(H::header_name()), Item::new_typed(value));
+    }
+
+    /// Get a reference to the header field's value, if it exists.
+    pub fn get<H: Header>(&self) -> Option<&H> {
+        self.item(H::header_name()).and_then(Item::typed::<H>)
+    }
+
+    /// Get a mutable reference to the header field's value, if it exists.
+    pub fn get_mut<H: Header>(&mut self) -> Option<&mut H> {
+        self.item_mut(H::header_name()).and_then(Item::typed_mut::<H>)
+    }
+
+    /// Returns a boolean of whether a certain header is in the map.
+    pub fn has<H: Header>(&self) -> bool {
+        self.item(H::header_name()).is_some()
+    }
+
+    /// Removes a header from the map, if one existed.
+    /// Returns the header, if one has been removed and could be parsed.
+    pub fn remove<H: Header>(&mut self) -> Option<H> {
+        self.position(H::header_name())
+            .and_then(|pos| self.data.remove(pos).1.typed::<H>().cloned())
+    }
+
+    /// Returns an iterator over the header fields.
+    pub fn iter(&self) -> HeadersItems<'_> {
+        HeadersItems { inner: self.data.iter() }
+    }
+
+    /// Returns the number of headers in the map.
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Remove all headers from the map.
+    pub fn clear(&mut self) {
+        self.data.clear()
+    }
+
+    /// Access the raw value of a header.
+    pub fn get_raw(&self, name: &str) -> Option<&Raw> {
+        self.item(name).map(Item::raw)
+    }
+
+    /// Set the raw value of a header, bypassing any typed headers.
+    pub fn set_raw<K: Into<Cow<'static, str>>, V: Into<Raw>>(&mut self, name: K, value: V) {
+        self.insert(name.into(), Item::new_raw(value.into()));
+    }
+
+    /// Append a value to raw value of this header.
+    ///
+    /// If a header already contains a value, this will add another line to it.
+    ///
+    /// If a header does not exist for this name, a new one will be created with
+    /// the value.
+    pub fn append_raw<K: Into<Cow<'static, str>>, V: Into<Raw>>(&mut self, name: K, value: V) {
+        let name = name.into();
+        let value = value.into();
+        if let Some(item) = self.item_mut(&name) {
+            item.raw_mut().push(value);
+            return;
+        }
+        self.data.push((name, Item::new_raw(value)));
+    }
+
+    /// Remove a header by name.
+    pub fn remove_raw(&mut self, name: &str) {
+        if let Some(pos) = self.position(name) {
+            self.data.remove(pos);
+        }
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.data.iter().position(|&(ref n, _)| n.eq_ignore_ascii_case(name))
+    }
+
+    fn item(&self, name: &str) -> Option<&Item> {
+        self.position(name).map(|pos| &self.data[pos].1)
+    }
+
+    fn item_mut(&mut self, name: &str) -> Option<&mut Item> {
+        match self.position(name) {
+            Some(pos) => Some(&mut self.data[pos].1),
+            None => None,
+        }
+    }
+
+    // Replace the field named `name` where it is, or add it at the end
+    fn insert(&mut self, name: Cow<'static, str>, item: Item) {
+        match self.position(&name) {
+            Some(pos) => self.data[pos] = (name, item),
+            None => self.data.push((name, item)),
+        }
+    }
+}
+
+impl PartialEq for Headers {
+    fn eq(&self, other: &Headers) -> bool {
+        self.len() == other.len() && self.iter().all(|header| {
+            other.get_raw(header.name()) == Some(header.raw())
+        })
+    }
+}
+
+impl fmt::Display for Headers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for header in self.iter() {
+            try!(fmt::Display::fmt(&header, f));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Headers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map()
+            .entries(self.iter().map(|header| (header.name(), header.value_string())))
+            .finish()
+    }
+}
+
+impl<'a> Extend<HeaderView<'a>> for Headers {
+    fn extend<I: IntoIterator<Item=HeaderView<'a>>>(&mut self, iter: I) {
+        for header in iter {
+            self.insert(header.0.clone(), header.1.clone());
+        }
+    }
+}
+
+impl<'a> Extend<(&'a str, Bytes)> for Headers {
+    fn extend<I: IntoIterator<Item=(&'a str, Bytes)>>(&mut self, iter: I) {
+        for (name, value) in iter {
+            self.append_raw(name.to_owned(), value);
+        }
+    }
+}
+
+impl<'a> FromIterator<HeaderView<'a>> for Headers {
+    fn from_iter<I: IntoIterator<Item=HeaderView<'a>>>(iter: I) -> Headers {
+        let mut headers = Headers::new();
+        headers.extend(iter);
+        headers
+    }
+}
+
+/// An `Iterator` over the fields in a `Headers` map.
+pub struct HeadersItems<'a> {
+    inner: slice::Iter<'a, (Cow<'static, str>, Item)>,
+}
+
+impl<'a> Iterator for HeadersItems<'a> {
+    type Item = HeaderView<'a>;
+
+    fn next(&mut self) -> Option<HeaderView<'a>> {
+        self.inner.next().map(|&(ref name, ref item)| HeaderView(name, item))
+    }
+}
+
+/// Returned with the `HeadersItems` iterator.
+pub struct HeaderView<'a>(&'a Cow<'static, str>, &'a Item);
+
+impl<'a> HeaderView<'a> {
+    /// Check if a HeaderView is a certain Header.
+    pub fn is<H: Header>(&self) -> bool {
+        self.0.eq_ignore_ascii_case(H::header_name())
+    }
+
+    /// Get the Header name as a slice.
+    pub fn name(&self) -> &'a str {
+        self.0
+    }
+
+    /// Cast the value to a certain Header type.
+    pub fn value<H: Header>(&self) -> Option<&'a H> {
+        self.1.typed::<H>()
+    }
+
+    /// Get just the header value as a String.
+    ///
+    /// This will join multiple values of this header with a `, `.
+    pub fn value_string(&self) -> String {
+        struct Value<'a>(&'a Item);
+
+        impl<'a> fmt::Display for Value<'a> {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                self.0.write_lines(None, f)
+            }
+        }
+
+        Value(self.1).to_string()
+    }
+
+    /// Access the raw value of the header.
+    pub fn raw(&self) -> &Raw {
+        self.1.raw()
+    }
+}
+
+impl<'a> fmt::Display for HeaderView<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.1.write_lines(Some(self.0), f)
+    }
+}
+
+impl<'a> fmt::Debug for HeaderView<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+// The value of a header that must be exactly one non-empty line, trimmed
+fn one_line<T: FromStr>(raw: &Raw) -> Option<T> {
+    raw.one()
+        .filter(|line| ! line.is_empty())
+        .and_then(|line| str::from_utf8(line).ok())
+        .and_then(|line| line.trim().parse().ok())
+}
+
+macro_rules! newtype_header {
+    ($(#[$attr:meta])* $name:ident, $header_name:expr, $inner:ty) => {
+        $(#[$attr])*
+        #[derive(Clone, Debug, PartialEq)]
+        pub struct $name(pub $inner);
+
+        impl Header for $name {
+            fn header_name() -> &'static str {
+                $header_name
+            }
+        }
+
+        impl Deref for $name {
+            type Target = $inner;
+
+            fn deref(&self) -> &$inner {
+                &self.0
+            }
+        }
+
+        impl DerefMut for $name {
+            fn deref_mut(&mut self) -> &mut $inner {
+                &mut self.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                fmt::Display::fmt(&self.0, f)
+            }
+        }
+    }
+}
+
+newtype_header!(
+    /// `Content-Type` header, the media type of a body
+    ContentType, "Content-Type", Mime);
+
+impl sealed::Sealed for ContentType {
+    fn parse_header(raw: &Raw) -> Option<ContentType> {
+        one_line(raw).map(ContentType)
+    }
+}
+
+newtype_header!(
+    /// `Content-Length` header, the length of a body in bytes
+    ContentLength, "Content-Length", u64);
+
+impl sealed::Sealed for ContentLength {
+    // Repeated lines are accepted if they are all the same
+    fn parse_header(raw: &Raw) -> Option<ContentLength> {
+        let mut lengths = raw.iter()
+            .map(|line| str::from_utf8(line).ok().and_then(|line| line.trim().parse().ok()));
+        let first: Option<u64> = match lengths.next() {
+            Some(length) => length,
+            None => return None,
+        };
+        if lengths.all(|length| length == first) { first.map(ContentLength) } else { None }
+    }
+}
+
+newtype_header!(
+    /// `Date` header, when a message was made
+    Date, "Date", HttpDate);
+
+impl sealed::Sealed for Date {
+    fn parse_header(raw: &Raw) -> Option<Date> {
+        one_line(raw).map(Date)
+    }
+}
+
+/// `Content-Disposition` header, how a part is to be presented, and the name of its form
+/// field or file
+#[derive(Clone, Debug, PartialEq)]
+pub struct ContentDisposition {
+    /// The disposition
+    pub disposition: DispositionType,
+    /// Disposition parameters
+    pub parameters: Vec<DispositionParam>,
+}
+
+/// The disposition of a `ContentDisposition`
+#[derive(Clone, Debug, PartialEq)]
+pub enum DispositionType {
+    /// Displayed as part of a page or message
+    Inline,
+    /// Kept apart, usually for saving as a file
+    Attachment,
+    /// Another disposition, such as `form-data`
+    Ext(String),
+}
+
+/// A parameter of a `ContentDisposition`
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum DispositionParam {
+    /// A parameter with its name and value, unquoted
+    Ext(String, String),
+}
+
+impl Header for ContentDisposition {
+    fn header_name() -> &'static str {
+        "Content-Disposition"
+    }
+}
+
+impl sealed::Sealed for ContentDisposition {
+    fn parse_header(raw: &Raw) -> Option<ContentDisposition> {
+        let value: String = match one_line(raw) {
+            Some(value) => value,
+            None => return None,
+        };
+        let mut sections = value.split(';');
+        let disposition = sections.next().unwrap_or("").trim();
+        let disposition = if disposition.eq_ignore_ascii_case("inline") {
+            DispositionType::Inline
+        } else if disposition.eq_ignore_ascii_case("attachment") {
+            DispositionType::Attachment
+        } else {
+            DispositionType::Ext(disposition.to_owned())
+        };
+
+        let mut parameters = Vec::new();
+        for section in sections {
+            let mut parts = section.splitn(2, '=');
+            let key = parts.next().unwrap_or("").trim();
+            let value = match parts.next() {
+                Some(value) => value.trim(),
+                None => return None,
+            };
+            parameters.push(DispositionParam::Ext(key.to_owned(),
+                                                  value.trim_matches('"').to_owned()));
+        }
+        Some(ContentDisposition { disposition, parameters })
+    }
+}
+
+impl fmt::Display for ContentDisposition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.disposition {
+            DispositionType::Inline => try!(f.write_str("inline")),
+            DispositionType::Attachment => try!(f.write_str("attachment")),
+            DispositionType::Ext(ref s) => try!(f.write_str(s)),
+        }
+        for param in &self.parameters {
+            match *param {
+                DispositionParam::Ext(ref k, ref v) => try!(write!(f, "; {}=\"{}\"", k, v)),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A character set, as named by IANA
+#[derive(Clone, Debug, PartialEq)]
+#[allow(non_camel_case_types)]
+pub enum Charset {
+    /// US ASCII
+    Us_Ascii,
+    /// ISO-8859-1
+    Iso_8859_1,
+    /// ISO-8859-2
+    Iso_8859_2,
+    /// ISO-8859-3
+    Iso_8859_3,
+    /// ISO-8859-4
+    Iso_8859_4,
+    /// ISO-8859-5
+    Iso_8859_5,
+    /// ISO-8859-6
+    Iso_8859_6,
+    /// ISO-8859-7
+    Iso_8859_7,
+    /// ISO-8859-8
+    Iso_8859_8,
+    /// ISO-8859-9
+    Iso_8859_9,
+    /// ISO-8859-10
+    Iso_8859_10,
+    /// Shift_JIS
+    Shift_Jis,
+    /// EUC-JP
+    Euc_Jp,
+    /// ISO-2022-KR
+    Iso_2022_Kr,
+    /// EUC-KR
+    Euc_Kr,
+    /// ISO-2022-JP
+    Iso_2022_Jp,
+    /// ISO-2022-JP-2
+    Iso_2022_Jp_2,
+    /// ISO-8859-6-E
+    Iso_8859_6_E,
+    /// ISO-8859-6-I
+    Iso_8859_6_I,
+    /// ISO-8859-8-E
+    Iso_8859_8_E,
+    /// ISO-8859-8-I
+    Iso_8859_8_I,
+    /// GB2312
+    Gb2312,
+    /// Big5
+    Big5,
+    /// KOI8-R
+    Koi8_R,
+    /// Any other character set, by name
+    Ext(String),
+}
+
+// Each character set with its name, as written and (ignoring case) read
+const CHARSET_NAMES: [(Charset, &'static str); 24] = [
+    (Charset::Us_Ascii, "US-ASCII"),
+    (Charset::Iso_8859_1, "ISO-8859-1"),
+    (Charset::Iso_8859_2, "ISO-8859-2"),
+    (Charset::Iso_8859_3, "ISO-8859-3"),
+    (Charset::Iso_8859_4, "ISO-8859-4"),
+    (Charset::Iso_8859_5, "ISO-8859-5"),
+    (Charset::Iso_8859_6, "ISO-8859-6"),
+    (Charset::Iso_8859_7, "ISO-8859-7"),
+    (Charset::Iso_8859_8, "ISO-8859-8"),
+    (Charset::Iso_8859_9, "ISO-8859-9"),
+    (Charset::Iso_8859_10, "ISO-8859-10"),
+    (Charset::Shift_Jis, "Shift-JIS"),
+    (Charset::Euc_Jp, "EUC-JP"),
+    (Charset::Iso_2022_Kr, "ISO-2022-KR"),
+    (Charset::Euc_Kr, "EUC-KR"),
+    (Charset::Iso_2022_Jp, "ISO-2022-JP"),
+    (Charset::Iso_2022_Jp_2, "ISO-2022-JP-2"),
+    (Charset::Iso_8859_6_E, "ISO-8859-6-E"),
+    (Charset::Iso_8859_6_I, "ISO-8859-6-I"),
+    (Charset::Iso_8859_8_E, "ISO-8859-8-E"),
+    (Charset::Iso_8859_8_I, "ISO-8859-8-I"),
+    (Charset::Gb2312, "GB2312"),
+    (Charset::Big5, "5"),
+    (Charset::Koi8_R, "KOI8-R"),
+];
+
+impl fmt::Display for Charset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Charset::Ext(ref name) = *self {
+            return f.write_str(name);
+        }
+        let &(_, name) = CHARSET_NAMES.iter().find(|&&(ref c, _)| c == self).unwrap();
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Charset {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Charset, Error> {
+        Ok(CHARSET_NAMES.iter()
+           .find(|&&(_, name)| name.eq_ignore_ascii_case(s))
+           .map_or_else(|| Charset::Ext(s.to_ascii_uppercase()), |&(ref c, _)| c.clone()))
+    }
+}
+
+/// A time as written in HTTP and email headers, such as `Sun, 06 Nov 1994 08:49:37 GMT`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct HttpDate(SystemTime);
+
+const DAYS: [&'static str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTHS: [&'static str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug",
+                                    "Sep", "Oct", "Nov", "Dec"];
+
+impl fmt::Display for HttpDate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let secs = match self.0.duration_since(UNIX_EPOCH) {
+            Ok(since) => since.as_secs() as i64,
+            Err(before) => -(before.duration().as_secs() as i64),
+        };
+        let (days, secs) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+        let (year, month, day) = civil_from_days(days);
+        write!(f, "{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT", DAYS[days.rem_euclid(7) as usize],
+               day, MONTHS[month as usize - 1], year, secs / 3600, secs / 60 % 60, secs % 60)
+    }
+}
+
+impl FromStr for HttpDate {
+    type Err = Error;
+
+    // Only IMF-fixdate, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`
+    fn from_str(s: &str) -> Result<HttpDate, Error> {
+        let malformed = || Error::MalformedHeader("Date".to_owned());
+        let fields: Vec<&str> = s.split([' ', ':']).collect();
+        if fields.len() != 8 || ! fields[0].ends_with(',') || fields[7] != "GMT" {
+            return Err(malformed());
+        }
+        let number = |field: &str| field.parse::<u32>().map_err(|_| malformed());
+        let day = try!(number(fields[1]));
+        let month = try!(MONTHS.iter().position(|&m| m == fields[2]).ok_or_else(malformed));
+        let year = try!(number(fields[3]));
+        let (hour, minute, second) = (try!(number(fields[4])), try!(number(fields[5])),
+                                      try!(number(fields[6])));
+        if ! (1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+            return Err(malformed());
+        }
+        let days = days_from_civil(year as i64, month as u32 + 1, day);
+        let secs = days * 86400 + (hour * 3600 + minute * 60 + second) as i64;
+        Ok(HttpDate(if secs >= 0 {
+            UNIX_EPOCH + Duration::from_secs(secs as u64)
+        } else {
+            UNIX_EPOCH - Duration::from_secs((-secs) as u64)
+        }))
+    }
+}
+
+impl From<SystemTime> for HttpDate {
+    fn from(time: SystemTime) -> HttpDate {
+        HttpDate(time)
+    }
+}
+
+impl From<HttpDate> for SystemTime {
+    fn from(date: HttpDate) -> SystemTime {
+        date.0
+    }
+}
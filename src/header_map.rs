@@ -0,0 +1,100 @@
+// Copyright 2016 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Parsing and writing multipart bodies whose top-level headers are an `http::HeaderMap`,
+//! as used by most HTTP stacks, rather than `header::Headers` (requires the `http` feature)
+//!
+//! The headers of the `Node`s themselves remain `header::Headers`; `to_header_map()` converts
+//! them where needed.
+
+use std::borrow::Cow;
+use std::io::{Read, Write};
+
+use http::header::{HeaderMap, HeaderName, HeaderValue};
+use header::Headers;
+
+use super::{Error, Node, ParseOptions};
+use part_headers::PartHeaders;
+
+/// Convert a `HeaderMap` to `header::Headers`, keeping every value of each header
+pub fn to_headers(map: &HeaderMap) -> Headers {
+    let mut headers = Headers::new();
+    for (name, value) in map.iter() {
+        headers.append_raw(name.as_str().to_owned(), value.as_bytes().to_vec());
+    }
+    headers
+}
+
+impl PartHeaders for HeaderMap {
+    fn header_pairs(&self) -> Vec<(String, Vec<u8>)> {
+        self.iter().map(|(name, value)| (name.as_str().to_owned(), value.as_bytes().to_vec()))
+            .collect()
+    }
+
+    fn raw_value(&self, name: &str) -> Option<Vec<u8>> {
+        let mut values = self.get_all(name).iter();
+        match (values.next(), values.next()) {
+            (Some(value), None) => Some(value.as_bytes().to_vec()),
+            _ => None,
+        }
+    }
+
+    fn to_headers(&self) -> Cow<'_, Headers> {
+        Cow::Owned(to_headers(self))
+    }
+}
+
+/// Convert `header::Headers` to a `HeaderMap`, keeping every value of each header.  Fails
+/// with `Error::InvalidHeader` if a name or value is not valid in a `HeaderMap`, such as one
+/// containing a line break.
+pub fn to_header_map(headers: &Headers) -> Result<HeaderMap, Error> {
+    let mut map = HeaderMap::new();
+    for header in headers.iter() {
+        let invalid = || Error::InvalidHeader(header.name().to_owned());
+        let name = try!(HeaderName::from_bytes(header.name().as_bytes())
+                        .map_err(|_| invalid()));
+        for line in header.raw().iter() {
+            map.append(name.clone(), try!(HeaderValue::from_bytes(line).map_err(|_| invalid())));
+        }
+    }
+    Ok(map)
+}
+
+/// Parse a MIME `multipart/*` body from the `stream`, as `read_multipart_body()` does,
+/// taking the top-level headers (which must carry the `Content-Type`) from a `HeaderMap`.
+pub fn read_multipart_body<S: Read>(
+    stream: &mut S,
+    headers: &HeaderMap,
+    always_use_files: bool)
+    -> Result<Vec<Node>, Error>
+{
+    super::read_multipart_body(stream, headers, always_use_files)
+}
+
+/// Parse a MIME `multipart/*` body from the `stream`, as `read_multipart_body_with_options()`
+/// does, taking the top-level headers from a `HeaderMap`.
+pub fn read_multipart_body_with_options<S: Read>(
+    stream: &mut S,
+    headers: &HeaderMap,
+    options: &ParseOptions)
+    -> Result<Vec<Node>, Error>
+{
+    super::read_multipart_body_with_options(stream, headers, options)
+}
+
+/// Stream a complete multipart entity, as `write_multipart_with_headers()` does, taking the
+/// top-level headers from a `HeaderMap`.
+/// Returns the number of bytes written, or an error.
+pub fn write_multipart_with_headers<S: Write>(
+    stream: &mut S,
+    headers: &HeaderMap,
+    boundary: &Vec<u8>,
+    nodes: &Vec<Node>)
+    -> Result<usize, Error>
+{
+    super::write_multipart_with_headers(stream, headers, boundary, nodes)
+}
@@ -6,51 +6,295 @@
 // copied, modified, or distributed except according to those terms.
 
 extern crate httparse;
+#[cfg(feature = "hyper")]
 extern crate hyper;
 #[macro_use]
 extern crate mime;
+#[cfg(any(feature = "tempdir", test))]
 extern crate tempdir;
 extern crate textnonce;
+extern crate rand;
 #[macro_use]
 extern crate log;
+#[cfg(feature = "encoding")]
 extern crate encoding;
-extern crate buf_read_ext;
 extern crate bytes;
+extern crate futures;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
+#[cfg(feature = "reqwest")]
+extern crate reqwest;
+#[cfg(feature = "axum")]
+extern crate axum;
+#[cfg(feature = "axum")]
+extern crate http_body;
+#[cfg(any(feature = "axum", feature = "warp"))]
+extern crate tokio;
+#[cfg(feature = "warp")]
+extern crate warp;
+#[cfg(feature = "warp")]
+extern crate futures_core;
+#[cfg(feature = "async-std")]
+extern crate async_std;
+#[cfg(feature = "sniff")]
+extern crate infer;
+#[cfg(feature = "flate2")]
+extern crate flate2;
+#[cfg(feature = "http")]
+extern crate http;
+#[cfg(target_os = "linux")]
+extern crate libc;
+#[cfg(feature = "rayon")]
+extern crate rayon;
+#[cfg(feature = "md5")]
+extern crate md5;
 
 pub mod error;
+pub mod transfer_encoding;
+pub mod reader;
+pub mod borrowed;
+pub mod form_data;
+pub mod byteranges;
+pub mod related;
+pub mod signed;
+pub mod replace;
+#[cfg(feature = "hyper")]
+pub mod server;
+#[cfg(feature = "hyper")]
+pub mod client;
+pub mod builder;
+pub mod stream;
+pub mod chunked;
+pub mod storage;
+pub mod visit;
+pub mod parser;
+pub mod email;
+pub mod mhtml;
+pub mod report;
+pub mod batch;
+#[cfg(feature = "serde")]
+pub mod de;
+#[cfg(feature = "serde")]
+mod persist;
+#[cfg(feature = "reqwest")]
+pub mod reqwest_form;
+#[cfg(any(feature = "axum", feature = "warp"))]
+mod body_reader;
+#[cfg(feature = "axum")]
+pub mod axum_extract;
+#[cfg(feature = "warp")]
+pub mod warp_filter;
+#[cfg(feature = "async-std")]
+pub mod async_std_io;
+#[cfg(feature = "sniff")]
+pub mod sniff;
+#[cfg(feature = "flate2")]
+pub mod compression;
+#[cfg(feature = "http")]
+pub mod header_map;
+pub mod part_headers;
+#[cfg(feature = "object-store")]
+pub mod object_store;
+#[cfg(feature = "s3")]
+pub mod s3;
+#[cfg(feature = "s3")]
+mod sha256;
+#[cfg(not(feature = "hyper"))]
+pub mod header;
+#[cfg(any(feature = "s3", not(feature = "hyper")))]
+mod date;
+mod params;
+mod scan;
 
 #[cfg(test)]
 mod mock;
 #[cfg(test)]
 mod tests;
 
-pub use error::Error;
+pub use error::{Error, ErrorPosition};
+#[cfg(feature = "serde")]
+pub use de::from_multipart;
+#[cfg(feature = "hyper")]
+pub use hyper::header;
+#[cfg(feature = "hyper")]
+pub use server::read_multipart_request;
+pub use visit::map_nodes;
+#[cfg(feature = "rayon")]
+pub use visit::process_parts_parallel;
+pub use parser::Parser;
 
 use std::fs::File;
 use std::io::{BufRead, BufReader, Read, Write};
 use std::path::{Path, PathBuf};
 use std::borrow::Cow;
+use std::fmt;
+use std::cell::{Cell, RefCell};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::ops::Drop;
+use bytes::Bytes;
+#[cfg(feature = "encoding")]
 use encoding::{all, Encoding, DecoderTrap};
-use hyper::header::{ContentType, Headers, ContentDisposition, DispositionParam,
-                    DispositionType, Charset};
+use header::{ContentLength, ContentType, Headers, Charset};
+#[cfg(any(feature = "tempdir", test))]
 use tempdir::TempDir;
 use textnonce::TextNonce;
-use buf_read_ext::BufReadExt;
 use mime::Mime;
+use params::Params;
+use part_headers::PartHeaders;
+use storage::{PartStorage, StoredBody, TempFileStorage};
+use form_data::{form_data_disposition, DuplicateFields};
+#[cfg(feature = "sniff")]
+use sniff::ContentTypePolicy;
+#[cfg(feature = "flate2")]
+use compression::{ContentCoding, CONTENT_ENCODING};
+use scan::{find, stream_until_token};
+use transfer_encoding::{TransferEncoding, Decoder, Encoder, EncodingPolicy, Classifier,
+                        CONTENT_TRANSFER_ENCODING};
+
+/// Header fields in their original order, with repeated fields kept separately, as
+/// opposed to `Headers` which combines them.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HeaderFields {
+    /// The (name, value) pairs.  Names are as given; values have surrounding whitespace
+    /// removed, and folded lines joined with a single space.
+    pub fields: Vec<(String, Vec<u8>)>,
+}
+impl HeaderFields {
+    pub fn new() -> HeaderFields {
+        HeaderFields { fields: Vec::new() }
+    }
+
+    /// Parse a raw header block, up to the empty line ending it if present.  Lines without
+    /// a colon are ignored.
+    pub fn parse(raw: &[u8]) -> HeaderFields {
+        let mut fields: Vec<(String, Vec<u8>)> = Vec::new();
+        for line in raw.split(|&b| b == b'\n') {
+            let line = if line.ends_with(b"\r") { &line[..line.len() - 1] } else { line };
+            if line.is_empty() {
+                break;
+            }
+            if line[0] == b' ' || line[0] == b'\t' {
+                // A continuation of the previous field
+                if let Some(&mut (_, ref mut value)) = fields.last_mut() {
+                    value.push(b' ');
+                    value.extend(trim(line).iter().cloned());
+                }
+                continue;
+            }
+            if let Some(colon) = line.iter().position(|&b| b == b':') {
+                let name = String::from_utf8_lossy(trim(&line[..colon])).into_owned();
+                fields.push((name, trim(&line[colon + 1..]).to_vec()));
+            }
+        }
+        HeaderFields { fields: fields }
+    }
+
+    /// The header fields of `Headers`, in its order.  Repeated fields survive only as far as
+    /// `Headers` keeps them.
+    pub fn from_headers(headers: &Headers) -> HeaderFields {
+        let mut fields: Vec<(String, Vec<u8>)> = Vec::new();
+        for view in headers.iter() {
+            for value in view.raw() {
+                fields.push((view.name().to_owned(), value.to_vec()));
+            }
+        }
+        HeaderFields { fields: fields }
+    }
+
+    /// The value of the first field with this name, compared case-insensitively
+    pub fn get(&self, name: &str) -> Option<&[u8]> {
+        self.fields.iter()
+            .find(|&&(ref n, _)| n.eq_ignore_ascii_case(name))
+            .map(|&(_, ref v)| &v[..])
+    }
+
+    /// The values of all fields with this name, compared case-insensitively, in order
+    pub fn get_all(&self, name: &str) -> Vec<&[u8]> {
+        self.fields.iter()
+            .filter(|&&(ref n, _)| n.eq_ignore_ascii_case(name))
+            .map(|&(_, ref v)| &v[..])
+            .collect()
+    }
+
+    /// Append a field
+    pub fn push(&mut self, name: &str, value: &[u8]) {
+        self.fields.push((name.to_owned(), value.to_vec()));
+    }
+}
+
+fn trim(bytes: &[u8]) -> &[u8] {
+    let is_space = |b: &u8| *b == b' ' || *b == b'\t';
+    let start = bytes.iter().take_while(|b| is_space(b)).count();
+    let end = bytes.len() - bytes[start..].iter().rev().take_while(|b| is_space(b)).count();
+    &bytes[start..end]
+}
 
 /// A multipart part which is not a file (stored in memory)
 #[derive(Clone, Debug, PartialEq)]
 pub struct Part {
     pub headers: Headers,
-    pub body: Vec<u8>,
+    /// The body, shared between clones of the part rather than copied
+    pub body: Bytes,
+    /// The header block exactly as it was parsed, including line terminators and the empty
+    /// line ending it.  `None` for parts which were not parsed.
+    pub raw_headers: Option<Vec<u8>>,
 }
 impl Part {
+    /// A part holding `body`, with a `Content-Type` of `content_type` and no other headers
+    pub fn from_bytes(content_type: Mime, body: Vec<u8>) -> Part {
+        let mut headers = Headers::new();
+        headers.set(ContentType(content_type));
+        Part {
+            headers: headers,
+            body: Bytes::from(body),
+            raw_headers: None,
+        }
+    }
+
     /// Mime content-type specified in the header
     pub fn content_type(&self) -> Option<Mime> {
         let ct: Option<&ContentType> = self.headers.get();
         ct.map(|ref ct| ct.0.clone())
     }
+
+    /// Name of the form field, from the `name` parameter of the `Content-Disposition`.
+    /// Returns `Ok<None>` if there is no such parameter.  RFC 2231 / RFC 5987 extended
+    /// (`name*=UTF-8''...`) and continued (`name*0*=...`) parameters are understood, and
+    /// preferred over a plain `name` parameter.
+    pub fn name(&self) -> Result<Option<String>, Error> {
+        disposition_param(&self.headers, "name")
+    }
+
+    /// The body decoded as text, according to the `charset` parameter of the
+    /// `Content-Type`, or as UTF-8 if there is none.  Fails with `Error::Decoding` if the
+    /// charset is not supported or the body is not valid in it.
+    pub fn text(&self) -> Result<String, Error> {
+        let charset = self.content_type().and_then(|ct| {
+            ct.get_param(mime::CHARSET).map(|cs| cs.as_str().to_owned())
+        });
+        match charset {
+            Some(ref cs) if ! cs.eq_ignore_ascii_case("utf-8") => {
+                let charset: Charset = try!(cs.parse().map_err(|_| {
+                    Error::Decoding("Invalid charset".into())
+                }));
+                charset_decode(&charset, &self.body).map_err(Error::Decoding)
+            },
+            _ => Ok(try!(String::from_utf8(self.body.to_vec()))),
+        }
+    }
+
+    /// The header fields in their original order, including repeated fields.  These come
+    /// from `raw_headers` if set, else from `headers`.
+    pub fn header_fields(&self) -> HeaderFields {
+        match self.raw_headers {
+            Some(ref raw) => HeaderFields::parse(raw),
+            None => HeaderFields::from_headers(&self.headers),
+        }
+    }
 }
 
 /// A file that is to be inserted into a `multipart/*` or alternatively an uploaded file that
@@ -64,8 +308,19 @@ pub struct FilePart {
     /// Optionally, the size of the file.  This is filled when multiparts are parsed, but is
     /// not necessary when they are generated.
     pub size: Option<usize>,
-    // The temporary directory the upload was put into, saved for the Drop trait
-    tempdir: Option<PathBuf>,
+    /// The header block exactly as it was parsed, including line terminators and the empty
+    /// line ending it.  `None` for parts which were not parsed.
+    pub raw_headers: Option<Vec<u8>>,
+    /// The type detected from the content of the file, which may differ from its
+    /// `Content-Type`.  This is only filled when multiparts are parsed with
+    /// `ParseOptions::sniff_files` (requires the `sniff` feature), and is `None` if the
+    /// content is of no type recognised.
+    pub detected_type: Option<Mime>,
+    // The temporary file the upload was put into, shared between clones and deleted once
+    // the last of them drops
+    tempfile: Option<TempFile>,
+    // The file itself if it has no name, kept open for as long as it is needed
+    unlinked: Option<UnlinkedFile>,
 }
 impl FilePart {
     pub fn new(headers: Headers, path: &Path) -> FilePart
@@ -74,39 +329,140 @@ impl FilePart {
             headers: headers,
             path: path.to_owned(),
             size: None,
-            tempdir: None,
+            raw_headers: None,
+            detected_type: None,
+            tempfile: None,
+            unlinked: None,
         }
     }
 
-    /// If you do not want the file on disk to be deleted when Self drops, call this
-    /// function.  It will become your responsability to clean up.
+    /// If you do not want the file on disk to be deleted when Self (and every clone of it)
+    /// drops, call this function.  It will become your responsability to clean up.
     pub fn do_not_delete_on_drop(&mut self) {
-        self.tempdir = None;
+        if let Some(tempfile) = self.tempfile.take() {
+            tempfile.0.keep.store(true, Ordering::SeqCst);
+        }
+    }
+
+    /// Open the file for reading, from its start
+    pub fn open(&self) -> Result<File, Error> {
+        Ok(try!(File::open(&self.path)))
+    }
+
+    /// Read the content of the file into memory.  If `max` is given, fails with
+    /// `Error::BodyTooLong` (having read no more than a byte beyond it) if the file is
+    /// longer than `max` bytes.
+    pub fn bytes(&self, max: Option<u64>) -> Result<Vec<u8>, Error> {
+        let mut bytes: Vec<u8> = Vec::new();
+        let limit = max.map_or(u64::max_value(), |max| max.saturating_add(1));
+        try!(try!(self.open()).take(limit).read_to_end(&mut bytes));
+        if max.map_or(false, |max| bytes.len() as u64 > max) {
+            return Err(Error::BodyTooLong);
+        }
+        Ok(bytes)
+    }
+
+    /// Move the file to `path`, which it then belongs at, and will not be deleted from when
+    /// Self drops.  The file is renamed where possible, and otherwise (as across
+    /// filesystems, or for a file without a name) copied next to `path` and renamed into
+    /// place, so that `path` never holds a partial file.  The temporary directory of the
+    /// file is removed.  Returns the new path, which `path` is also set to.
+    pub fn persist<P: AsRef<Path>>(&mut self, path: P) -> Result<PathBuf, Error> {
+        let path = path.as_ref().to_owned();
+        let renamed = self.unlinked.is_none() && ::std::fs::rename(&self.path, &path).is_ok();
+        if ! renamed {
+            try!(copy_into_place(&self.path, &path));
+            if self.unlinked.is_none() {
+                let _ = ::std::fs::remove_file(&self.path);
+            }
+        }
+        if let Some(tempfile) = self.tempfile.take() {
+            tempfile.0.keep.store(true, Ordering::SeqCst);
+        }
+        self.unlinked = None;
+        self.path = path.clone();
+        Ok(path)
     }
 
     /// Create a new temporary FilePart (when created this way, the file will be
-    /// deleted once the FilePart object goes out of scope).
+    /// deleted once the FilePart object, and every clone of it, goes out of scope).
     pub fn create(headers: Headers) -> Result<FilePart, Error> {
         // Setup a file to capture the contents.
-        let mut path = try!(TempDir::new("mime_multipart")).into_path();
-        let tempdir = Some(path.clone());
-        path.push(TextNonce::sized_urlsafe(32).unwrap().into_string());
-        Ok(FilePart {
+        Ok(FilePart::create_in(headers, Arc::new(try!(TempDirectory::new()))))
+    }
+
+    // Create a new temporary FilePart in the temporary directory given, which is removed
+    // once it is left empty
+    fn create_in(headers: Headers, dir: Arc<TempDirectory>) -> FilePart {
+        let path = dir.0.join(TextNonce::sized_urlsafe(32).unwrap().into_string());
+        FilePart {
             headers: headers,
-            path: path,
+            path: path.clone(),
             size: None,
-            tempdir: tempdir,
-        })
+            raw_headers: None,
+            detected_type: None,
+            tempfile: Some(TempFile(Arc::new(TempPath {
+                file: path,
+                _dir: dir,
+                keep: AtomicBool::new(false),
+            }))),
+            unlinked: None,
+        }
+    }
+
+    /// Create a new temporary FilePart whose file has no name in any directory, so that it
+    /// can be reached only through this process, and is reclaimed by the kernel once the
+    /// last `FilePart` referring to it is dropped, even if the process crashes.  It is
+    /// opened through `path`, which refers to the open file (under `/proc/self/fd`).
+    ///
+    /// This is only possible on Linux, using `O_TMPFILE` where the filesystem supports it,
+    /// and otherwise removing the file as soon as it is created.  Elsewhere, this is the
+    /// same as `create()`.
+    pub fn create_unlinked(headers: Headers) -> Result<FilePart, Error> {
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::unix::io::AsRawFd;
+
+            let file = try!(open_unlinked());
+            return Ok(FilePart {
+                headers: headers,
+                path: PathBuf::from(format!("/proc/self/fd/{}", file.as_raw_fd())),
+                size: None,
+                raw_headers: None,
+                detected_type: None,
+                tempfile: None,
+                unlinked: Some(UnlinkedFile(Arc::new(file))),
+            });
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            FilePart::create(headers)
+        }
     }
 
     /// Filename that was specified when the file was uploaded.  Returns `Ok<None>` if there
-    /// was no content-disposition header supplied.
+    /// was no content-disposition header supplied.  RFC 2231 / RFC 5987 extended
+    /// (`filename*=UTF-8''...`) and continued (`filename*0*=...`) parameters are understood,
+    /// and preferred over a plain `filename` parameter.
     pub fn filename(&self) -> Result<Option<String>, Error> {
-        let cd: Option<&ContentDisposition> = self.headers.get();
-        match cd {
-            Some(cd) => get_content_disposition_filename(cd),
-            None => Ok(None),
-        }
+        disposition_param(&self.headers, "filename")
+    }
+
+    /// The filename given by the client, made safe to use as the name of a file on disk:
+    /// any directory components are stripped, as are control characters (NUL included) and
+    /// leading and trailing dots and spaces.  Characters not allowed in Windows filenames
+    /// are replaced with `_`, as is done ahead of names reserved by Windows (such as `CON`
+    /// or `lpt1.txt`), and the name is cut to at most 255 bytes.  Returns `Ok<None>` if
+    /// there is no filename, or nothing of it is left.
+    pub fn safe_filename(&self) -> Result<Option<String>, Error> {
+        Ok(try!(self.filename()).and_then(|filename| sanitize_filename(&filename)))
+    }
+
+    /// Name of the form field, from the `name` parameter of the `Content-Disposition`.
+    /// Returns `Ok<None>` if there is no such parameter.  Extended parameters are understood
+    /// as for `filename()`.
+    pub fn name(&self) -> Result<Option<String>, Error> {
+        disposition_param(&self.headers, "name")
     }
 
     /// Mime content-type specified in the header
@@ -114,19 +470,183 @@ impl FilePart {
         let ct: Option<&ContentType> = self.headers.get();
         ct.map(|ref ct| ct.0.clone())
     }
+
+    /// The header fields in their original order, including repeated fields.  These come
+    /// from `raw_headers` if set, else from `headers`.
+    pub fn header_fields(&self) -> HeaderFields {
+        match self.raw_headers {
+            Some(ref raw) => HeaderFields::parse(raw),
+            None => HeaderFields::from_headers(&self.headers),
+        }
+    }
+}
+
+// The temporary file of a `FilePart`.  Clones share the file, which is deleted once the last
+// of them drops, unless it is to be kept.
+#[derive(Clone, Debug)]
+struct TempFile(Arc<TempPath>);
+impl PartialEq for TempFile {
+    fn eq(&self, other: &TempFile) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+#[derive(Debug)]
+struct TempPath {
+    file: PathBuf,
+    // Held so that the directory outlives the file
+    _dir: Arc<TempDirectory>,
+    keep: AtomicBool,
+}
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        if ! self.keep.load(Ordering::SeqCst) {
+            let _ = ::std::fs::remove_file(&self.file);
+        }
+    }
+}
+
+// A temporary directory shared by the files of a parse, removed once the last of them
+// drops, unless files kept are left in it
+#[derive(Debug, PartialEq)]
+struct TempDirectory(PathBuf);
+impl TempDirectory {
+    #[cfg(feature = "tempdir")]
+    fn new() -> Result<TempDirectory, Error> {
+        Ok(TempDirectory(try!(TempDir::new("mime_multipart")).into_path()))
+    }
+
+    // Without the tempdir crate, named as it would name it
+    #[cfg(not(feature = "tempdir"))]
+    fn new() -> Result<TempDirectory, Error> {
+        let name = format!("mime_multipart.{}", TextNonce::sized_urlsafe(16).unwrap());
+        let path = ::std::env::temp_dir().join(name);
+        try!(::std::fs::create_dir(&path));
+        Ok(TempDirectory(path))
+    }
 }
-impl Drop for FilePart {
+impl Drop for TempDirectory {
     fn drop(&mut self) {
-        if self.tempdir.is_some() {
-            let _ = ::std::fs::remove_file(&self.path);
-            let _ = ::std::fs::remove_dir(&self.tempdir.as_ref().unwrap());
+        let _ = ::std::fs::remove_dir(&self.0);
+    }
+}
+
+// Copy a file to `to` by way of a temporary file beside it, renamed over `to` once complete
+fn copy_into_place(from: &Path, to: &Path) -> Result<(), Error> {
+    let name = format!(".{}.tmp", TextNonce::sized_urlsafe(32).unwrap().into_string());
+    let temp = to.with_file_name(name);
+    if let Err(e) = ::std::fs::copy(from, &temp).and_then(|_| ::std::fs::rename(&temp, to)) {
+        let _ = ::std::fs::remove_file(&temp);
+        return Err(Error::Io(e));
+    }
+    Ok(())
+}
+
+// Make a filename given by a client safe to use on disk, as `FilePart::safe_filename()`
+// describes
+fn sanitize_filename(filename: &str) -> Option<String> {
+    // Only the last path component, whichever the separator
+    let name = filename.rsplit(|c| c == '/' || c == '\\').next().unwrap_or("");
+
+    let name: String = name.chars()
+        .filter(|c| ! c.is_control())
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '|' | '?' | '*' => '_',
+            c => c,
+        })
+        .collect();
+    let name = name.trim_matches(|c| c == '.' || c == ' ');
+    if name.is_empty() {
+        return None;
+    }
+
+    // Device names are reserved by Windows, with or without an extension
+    const RESERVED: &'static [&'static str] = &[
+        "CON", "PRN", "AUX", "NUL",
+        "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+        "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+    ];
+    let stem = name.split('.').next().unwrap_or("").trim_end();
+    let mut safe = String::with_capacity(name.len() + 1);
+    if RESERVED.iter().any(|r| r.eq_ignore_ascii_case(stem)) {
+        safe.push('_');
+    }
+    safe.push_str(name);
+
+    // Most filesystems allow no more than 255 bytes
+    if safe.len() > 255 {
+        let mut end = 255;
+        while ! safe.is_char_boundary(end) {
+            end -= 1;
         }
+        safe.truncate(end);
+    }
+    Some(safe)
+}
+
+// Open a new temporary file with no name, for `FilePart::create_unlinked()`
+#[cfg(target_os = "linux")]
+fn open_unlinked() -> Result<File, Error> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let mut options = ::std::fs::OpenOptions::new();
+    options.read(true).write(true).mode(0o600);
+    match options.clone().custom_flags(libc::O_TMPFILE).open(::std::env::temp_dir()) {
+        Ok(file) => Ok(file),
+        Err(_) => {
+            // Unsupported by the filesystem, so create a file and remove it straight away
+            let dir = try!(TempDirectory::new());
+            let path = dir.0.join(TextNonce::sized_urlsafe(32).unwrap().into_string());
+            let file = try!(options.create_new(true).open(&path));
+            try!(::std::fs::remove_file(&path));
+            Ok(file)
+        },
+    }
+}
+
+// The open file of a `FilePart` without a name.  Clones share the file.
+#[derive(Clone, Debug)]
+struct UnlinkedFile(Arc<File>);
+impl PartialEq for UnlinkedFile {
+    fn eq(&self, other: &UnlinkedFile) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// A part which could not be parsed, kept in place of it under `ParseOptions::recover`
+#[derive(Clone, Debug)]
+pub struct InvalidPart {
+    /// The headers of the part, if they could be parsed, and otherwise empty
+    pub headers: Headers,
+    /// The part exactly as it was read: its header block and body, up to the line terminator
+    /// preceding the next boundary.  If the line of the boundary itself was malformed, what
+    /// followed the boundary on that line comes first.  Parts which exceeded the
+    /// `disk_quota` have only their header block, as their body was too large to keep.
+    pub raw: Vec<u8>,
+    /// Why the part could not be parsed, shared as errors cannot be cloned
+    pub error: Arc<Error>,
+}
+impl PartialEq for InvalidPart {
+    // Errors cannot be compared, so are taken as equal if they read the same
+    fn eq(&self, other: &InvalidPart) -> bool {
+        self.headers == other.headers && self.raw == other.raw
+            && (Arc::ptr_eq(&self.error, &other.error)
+                || self.error.to_string() == other.error.to_string())
     }
 }
 
 /// A multipart part which could be either a file, in memory, or another multipart
 /// container containing nested parts.
-#[derive(Clone, Debug)]
+///
+/// With the `serde` feature, nodes (as well as `Part`s and `FilePart`s) may be serialized
+/// and deserialized, storing file parts by their path.  The files of parsed parts are
+/// deleted when the nodes are dropped, so call `FilePart::do_not_delete_on_drop()` on any
+/// which are to be taken up later.  Invalid parts, and files without a name on disk (under
+/// `ParseOptions::unlinked_files`), cannot be serialized.
+///
+/// Nodes compare equal (with `==`) if they are alike in every field, so file parts must
+/// have the same path; `content_eq()` compares them by the content of their files instead.
+#[derive(Clone, Debug, PartialEq)]
 pub enum Node {
     /// A part in memory
     Part(Part),
@@ -134,6 +654,583 @@ pub enum Node {
     File(FilePart),
     /// A container of nested multipart parts
     Multipart((Headers, Vec<Node>)),
+    /// An encapsulated `message/rfc822`, parsed when `ParseOptions::parse_messages` is set:
+    /// the headers of the part holding it, and the message itself.  The message is a
+    /// `Node::Multipart` if it is itself multipart, and otherwise a `Node::Part` or
+    /// `Node::File` with the message headers and body.
+    Message((Headers, Box<Node>)),
+    /// A malformed part, skipped under `ParseOptions::recover`
+    Invalid(InvalidPart),
+}
+impl Node {
+    /// A form field named `name` holding the text `value`, with a `Content-Disposition` of
+    /// `form-data` and no `Content-Type` (so `text/plain`)
+    pub fn text(name: &str, value: &str) -> Node {
+        let mut headers = Headers::new();
+        headers.set_raw("Content-Disposition", form_data_disposition(name, None));
+        Node::Part(Part {
+            headers: headers,
+            body: Bytes::from(value.as_bytes()),
+            raw_headers: None,
+        })
+    }
+
+    /// A form field named `name` holding the file at `path`, which is read when the node
+    /// is written.  It has a `Content-Disposition` of `form-data` with the last component of
+    /// `path` as the filename, and a `Content-Type` of `application/octet-stream`.
+    pub fn file<P: AsRef<Path>>(name: &str, path: P) -> Node {
+        let path = path.as_ref();
+        let filename = path.file_name().map(|f| f.to_string_lossy().into_owned());
+        let mut headers = Headers::new();
+        headers.set_raw("Content-Disposition",
+                        form_data_disposition(name, filename.as_ref().map(|f| &**f)));
+        headers.set(ContentType(mime::APPLICATION_OCTET_STREAM));
+        Node::File(FilePart::new(headers, path))
+    }
+
+    /// The headers of the part or nested multipart
+    pub fn headers(&self) -> &Headers {
+        match *self {
+            Node::Part(ref part) => &part.headers,
+            Node::File(ref filepart) => &filepart.headers,
+            Node::Multipart((ref headers, _)) => headers,
+            Node::Message((ref headers, _)) => headers,
+            Node::Invalid(ref invalid) => &invalid.headers,
+        }
+    }
+
+    /// Mime content-type specified in the header of the part or nested multipart
+    pub fn content_type(&self) -> Option<Mime> {
+        let ct: Option<&ContentType> = self.headers().get();
+        ct.map(|ref ct| ct.0.clone())
+    }
+
+    /// Name of the form field, from the `name` parameter of the `Content-Disposition`.
+    /// Returns `Ok<None>` if there is no such parameter.  Extended parameters are understood
+    /// as for `Part::name()`.
+    pub fn name(&self) -> Result<Option<String>, Error> {
+        disposition_param(self.headers(), "name")
+    }
+
+    /// Filename from the `filename` parameter of the `Content-Disposition`, as for
+    /// `FilePart::filename()`, whichever kind of node this is.  Returns `Ok<None>` if there
+    /// is no such parameter.
+    pub fn filename(&self) -> Result<Option<String>, Error> {
+        disposition_param(self.headers(), "filename")
+    }
+
+    /// The length of the body in bytes, as `write_multipart()` would write it: that of a
+    /// part in memory, of the file of a file part (from its metadata), of the body of a
+    /// nested multipart or message, or of all that was read of an invalid part.  Fails if
+    /// the metadata of a file cannot be read, or a nested multipart has no boundary.
+    pub fn body_len(&self) -> Result<u64, Error> {
+        Ok(match *self {
+            Node::Part(ref part) => part.body.len() as u64,
+            Node::File(ref filepart) => try!(::std::fs::metadata(&filepart.path)).len(),
+            Node::Multipart((ref headers, ref subnodes)) => {
+                let boundary = try!(get_multipart_boundary(headers));
+                try!(multipart_len(&boundary[2..], subnodes))
+            },
+            Node::Message((_, ref message)) => try!(entity_len(message)),
+            Node::Invalid(ref invalid) => invalid.raw.len() as u64,
+        })
+    }
+
+    /// Whether two nodes have the same headers and content, comparing the bodies of parts
+    /// whether they are held in memory or in files, and the files of file parts by their
+    /// content rather than their path.  Nested multiparts and messages are compared node
+    /// by node.  Fails if a file cannot be read.
+    pub fn content_eq(&self, other: &Node) -> Result<bool, Error> {
+        if self.headers() != other.headers() {
+            return Ok(false);
+        }
+        match (self, other) {
+            (&Node::Multipart((_, ref nodes)), &Node::Multipart((_, ref others))) => {
+                if nodes.len() != others.len() {
+                    return Ok(false);
+                }
+                for (node, other) in nodes.iter().zip(others.iter()) {
+                    if ! try!(node.content_eq(other)) {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            },
+            (&Node::Message((_, ref message)), &Node::Message((_, ref other))) => {
+                message.content_eq(other)
+            },
+            (&Node::Invalid(ref invalid), &Node::Invalid(ref other)) => Ok(invalid == other),
+            _ => match (try!(open_content(self)), try!(open_content(other))) {
+                (Some(body), Some(other)) => Ok(try!(same_content(body, other))),
+                _ => Ok(false),
+            },
+        }
+    }
+}
+
+// Open the body of a part, held in memory or in a file, to be read.  `None` for other nodes.
+fn open_content<'a>(node: &'a Node) -> Result<Option<Box<dyn Read + 'a>>, Error> {
+    Ok(match *node {
+        Node::Part(ref part) => Some(Box::new(&part.body[..])),
+        Node::File(ref filepart) => Some(Box::new(try!(File::open(&filepart.path)))),
+        _ => None,
+    })
+}
+
+// Whether two streams hold the same bytes, reading both in step
+fn same_content<A: Read, B: Read>(a: A, b: B) -> ::std::io::Result<bool> {
+    let mut a = BufReader::new(a);
+    let mut b = BufReader::new(b);
+    loop {
+        let count = {
+            let (x, y) = (try!(a.fill_buf()), try!(b.fill_buf()));
+            let count = ::std::cmp::min(x.len(), y.len());
+            if count == 0 {
+                return Ok(x.is_empty() && y.is_empty());
+            }
+            if x[..count] != y[..count] {
+                return Ok(false);
+            }
+            count
+        };
+        a.consume(count);
+        b.consume(count);
+    }
+}
+
+/// Walk the nodes depth-first, in the order they would be written, yielding each with its
+/// depth: 0 for the nodes given, 1 for those nested within them, and so on.  A
+/// `Node::Multipart` is yielded before the nodes within it, and a `Node::Message` before
+/// the message it holds.
+pub fn walk(nodes: &[Node]) -> Walk<'_> {
+    Walk { stack: vec![(nodes.iter(), 0)] }
+}
+
+/// The nodes with the field name `name` (the `name` parameter of their
+/// `Content-Disposition`), among the nodes given and all those nested within them, in the
+/// order `walk()` visits them
+pub fn find_by_name<'a>(nodes: &'a [Node], name: &str) -> Vec<&'a Node> {
+    walk(nodes)
+        .filter(|&(node, _)| node.name().ok().and_then(|n| n) == Some(name.to_owned()))
+        .map(|(node, _)| node)
+        .collect()
+}
+
+/// The first node with the `Content-ID` given, among the nodes given and all those nested
+/// within them.  The ID may be a `cid:` URL (RFC 2392), with or without angle brackets, or
+/// bare, as for `Related::get()`.
+pub fn find_by_content_id<'a>(nodes: &'a [Node], id: &str) -> Option<&'a Node> {
+    let id = related::parse_content_id(id);
+    walk(nodes)
+        .find(|&(node, _)| related::content_id(node.headers()) == Some(id.clone()))
+        .map(|(node, _)| node)
+}
+
+/// The nodes whose `Content-Type` is of the type given, among the nodes given and all those
+/// nested within them, in the order `walk()` visits them.  Parameters are ignored, and a
+/// type or subtype of `*` matches any, so that `image/*` finds all images.
+pub fn find_by_type<'a>(nodes: &'a [Node], mime: &Mime) -> Vec<&'a Node> {
+    walk(nodes)
+        .filter(|&(node, _)| node.content_type().map_or(false, |ct| type_matches(&ct, mime)))
+        .map(|(node, _)| node)
+        .collect()
+}
+
+/// The alternative to show of a `multipart/alternative` node, given the types which can be
+/// shown: the last alternative of one of those types, as the alternatives are in order of
+/// increasing faithfulness to the original (RFC 2046).  Types are matched as by
+/// `find_by_type()`.  A `multipart/related` alternative, such as an HTML document with its
+/// images, is also taken to be of the type of its root part.  Returns `None` if no
+/// alternative can be shown, or the node is not a multipart.
+pub fn best_alternative<'a>(node: &'a Node, accept: &[Mime]) -> Option<&'a Node> {
+    let alternatives = match *node {
+        Node::Multipart((_, ref subnodes)) => subnodes,
+        _ => return None,
+    };
+    let shown = |ct: &Mime| accept.iter().any(|mime| type_matches(ct, mime));
+    alternatives.iter().rev().find(|alternative| {
+        // Parts without a Content-Type are plain text
+        let ct = alternative.content_type().unwrap_or(mime::TEXT_PLAIN);
+        if shown(&ct) {
+            return true;
+        }
+        if ct.type_() == mime::MULTIPART && ct.subtype() == "related" {
+            let root = related::Related::from_node(alternative)
+                .and_then(|related| related.ok())
+                .and_then(|related| related.root)
+                .and_then(|root| root.content_type());
+            return root.map_or(false, |root| shown(&root));
+        }
+        false
+    })
+}
+
+// Whether a content type is of the type given, in which a type or subtype of `*` matches any
+fn type_matches(ct: &Mime, mime: &Mime) -> bool {
+    (mime.type_() == mime::STAR || ct.type_() == mime.type_())
+        && (mime.subtype() == mime::STAR || ct.subtype() == mime.subtype())
+}
+
+/// The iterator returned by `walk()`
+pub struct Walk<'a> {
+    // The nodes left at each level being walked, with their depth
+    stack: Vec<(::std::slice::Iter<'a, Node>, usize)>,
+}
+
+impl<'a> Iterator for Walk<'a> {
+    type Item = (&'a Node, usize);
+
+    fn next(&mut self) -> Option<(&'a Node, usize)> {
+        loop {
+            let (node, depth) = match self.stack.last_mut() {
+                Some(&mut (ref mut nodes, depth)) => (nodes.next(), depth),
+                None => return None,
+            };
+            let node = match node {
+                Some(node) => node,
+                None => {
+                    self.stack.pop();
+                    continue;
+                },
+            };
+            match *node {
+                Node::Multipart((_, ref nodes)) => self.stack.push((nodes.iter(), depth + 1)),
+                Node::Message((_, ref message)) => {
+                    self.stack.push((::std::slice::from_ref(&**message).iter(), depth + 1))
+                },
+                _ => { },
+            }
+            return Some((node, depth));
+        }
+    }
+}
+
+/// A parsed `multipart/*` body, including the RFC 2046 preamble and epilogue
+#[derive(Clone, Debug, Default)]
+pub struct MultipartBody {
+    /// The bytes preceding the first boundary, excluding the line terminator which belongs
+    /// to that boundary
+    pub preamble: Vec<u8>,
+    /// The parts
+    pub nodes: Vec<Node>,
+    /// The bytes following the closing boundary, excluding the line terminator directly
+    /// after it
+    pub epilogue: Vec<u8>,
+    /// The exact bytes around the nodes as they were parsed, under
+    /// `ParseOptions::keep_framing`, from which `write_multipart_full()` reproduces the body
+    pub framing: Option<Framing>,
+}
+
+/// The bytes of a parsed multipart body besides its preamble, epilogue and part bodies, kept
+/// under `ParseOptions::keep_framing` so that the body can be written out again byte for
+/// byte.  There is an entry in `delimiters`, `headers` and `nested` for each node.
+#[derive(Clone, Debug, Default)]
+pub struct Framing {
+    /// The bytes before each node: the line terminator ending the body before it (or the
+    /// preamble), the boundary, any transport padding and the line terminator after it
+    pub delimiters: Vec<Vec<u8>>,
+    /// The header block of each node exactly as read, blank line included.  For a
+    /// `Node::Message`, this is the header block of the part holding it followed by that
+    /// of the message.  Empty for a `Node::Invalid`, whose bytes are all in `raw`, and
+    /// should be emptied for a node whose headers are changed, to write them instead.
+    pub headers: Vec<Vec<u8>>,
+    /// The preamble, epilogue and framing of each nested multipart (within a
+    /// `Node::Multipart`, or a `Node::Message` holding one), its nodes being in the node
+    pub nested: Vec<Option<MultipartBody>>,
+    /// The closing delimiter: the line terminator ending the last body, the boundary, the
+    /// `--` after it and any line terminator following
+    pub close: Vec<u8>,
+}
+
+impl Framing {
+    // Forget the last node, which was skipped
+    fn drop_last(&mut self) {
+        self.delimiters.pop();
+        self.headers.pop();
+        self.nested.pop();
+    }
+
+    // Whether there is an entry for each of the nodes
+    fn fits(&self, nodes: &[Node]) -> bool {
+        self.delimiters.len() == nodes.len() && self.headers.len() == nodes.len()
+            && self.nested.len() == nodes.len()
+    }
+}
+
+/// Where the bodies of parsed parts are stored
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Storage {
+    /// Parts with a `ContentDisposition` header set to `Attachment` or otherwise containing
+    /// a `Filename` parameter are streamed to files, all other parts are kept in memory.
+    Auto,
+    /// All parts are streamed to files.
+    Files,
+    /// All parts are kept in memory, even those with a filename.  Nothing is ever written
+    /// to disk.
+    Memory,
+    /// Parts smaller than this many bytes are kept in memory, regardless of their headers.
+    /// Larger parts are spilled to files as soon as they reach this size.
+    Threshold(usize),
+}
+
+/// What to do with the body of a part, as decided by a `ParseOptions::storage_decision`
+/// callback
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StorageDecision {
+    /// Keep the part in memory, as a `Node::Part`
+    Memory,
+    /// Stream the part to a file, as a `Node::File`
+    File,
+    /// Read past the part without storing it.  No node is produced for it.
+    Skip,
+}
+
+/// What to do with a part, as decided by a `ParseOptions::inspect` callback
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Disposition {
+    /// Read the part as usual
+    Accept,
+    /// Read past the part without storing it, as for `StorageDecision::Skip`.  No node is
+    /// produced for it.
+    Skip,
+    /// Abandon the parse with `Error::PartRejected`
+    Abort,
+}
+
+/// The progress of a parse, as reported to a `ParseOptions::progress` callback
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ProgressEvent {
+    /// The number of bytes read from the stream so far
+    pub bytes_read: u64,
+    /// The number of parts read completely so far, counting the parts within nested
+    /// multiparts but not the nested multiparts themselves
+    pub parts_completed: usize,
+}
+
+/// Statistics of a parse, as reported to a `ParseOptions::stats` callback once it ends
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ParseStats {
+    /// The number of parts read completely, counting the parts within nested multiparts
+    /// but not the nested multiparts themselves, as for `ProgressEvent::parts_completed`
+    pub parts: usize,
+    /// The number of those parts stored in files
+    pub file_parts: usize,
+    /// The number of bytes read from the stream
+    pub bytes_read: u64,
+    /// The number of bytes written to files
+    pub bytes_written: u64,
+    /// The most multiparts nested within one another, counting the body itself, so that a
+    /// body without nested multiparts has a depth of 1
+    pub max_depth: usize,
+}
+
+/// Options controlling how a `multipart/*` body is parsed.  Clones share the callbacks and
+/// `part_storage` of the original, and options may be shared between threads.
+#[derive(Clone)]
+pub struct ParseOptions {
+    /// Where part bodies are stored.  Defaults to `Storage::Auto`.
+    pub storage: Storage,
+    /// If set, this is called with the headers of each (non-multipart) part to decide
+    /// where its body goes, taking precedence over `storage`.  Defaults to `None`.
+    pub storage_decision: Option<Arc<dyn Fn(&Headers) -> StorageDecision + Send + Sync>>,
+    /// Whether to decode part bodies with a `Content-Transfer-Encoding` of `base64` or
+    /// `quoted-printable`.  Decoded parts have their `Content-Transfer-Encoding` header
+    /// removed, as it no longer applies.  Defaults to `false`.
+    pub decode_transfer_encoding: bool,
+    /// Whether to tolerate malformed bodies as produced by some real-world clients: stray
+    /// characters after boundaries, and bodies which end without a closing boundary or
+    /// within an epilogue.  When the body ends prematurely, the parts read so far are
+    /// returned, including a final part cut short by the end of the body.  Defaults to
+    /// `false`.
+    pub lenient: bool,
+    /// Whether to reject input which does not strictly conform to RFC 2046 as used over
+    /// HTTP: boundaries longer than 70 characters or containing invalid characters, and
+    /// bare LF line terminators.  Defaults to `false`.
+    pub strict: bool,
+    /// Whether to give errors the position at which parsing failed, wrapping them in an
+    /// `Error::At` with the byte offset in the stream, the index of the part being read and
+    /// its headers.  Otherwise errors are returned as they are, so that they may be matched
+    /// directly.  Defaults to `false`.
+    pub error_positions: bool,
+    /// If set, this is called as parsing progresses: whenever more bytes have been read
+    /// from the stream, and whenever a part has been read completely.  As parses on other
+    /// threads may call it at once, any state it keeps must be behind a `Mutex` or atomics.
+    /// Defaults to `None`.
+    pub progress: Option<Arc<dyn Fn(ProgressEvent) + Send + Sync>>,
+    /// If set, this is called once a parse ends, whether or not it succeeded, with the
+    /// statistics of what was read, e.g. to be recorded as metrics.  Defaults to `None`.
+    pub stats: Option<Arc<dyn Fn(ParseStats) + Send + Sync>>,
+    /// If set, this is called with the headers of each part before its body is read,
+    /// nested multiparts included, to decide whether the part is read, skipped, or the
+    /// whole parse abandoned, e.g. to enforce which fields a user may submit.  It is called
+    /// before `storage_decision`.  Defaults to `None`.
+    pub inspect: Option<Arc<dyn Fn(&Headers) -> Disposition + Send + Sync>>,
+    /// If set, this flag is checked before every read from the stream, and once it is true
+    /// the parse is abandoned with `Error::Cancelled`.  Another thread may set it, e.g. when
+    /// the client disconnects or a deadline passes.  Any files already written are deleted
+    /// as their `FilePart`s are dropped.  Defaults to `None`.
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// The most header fields a part may have, beyond which parsing fails with
+    /// `Error::TooManyHeaders`.  If `None`, any number is accepted.  Defaults to
+    /// `None`.
+    pub max_part_headers: Option<usize>,
+    /// The most header fields the top-level headers may have, when they are read from the
+    /// stream.  If `None`, any number is accepted.  Defaults to `None`.
+    pub max_main_headers: Option<usize>,
+    /// Whether to give parts without a `Content-Type` header the default of RFC 2046:
+    /// `message/rfc822` within a `multipart/digest`, and `text/plain; charset=us-ascii`
+    /// otherwise.  Defaults to `false`.
+    pub default_content_types: bool,
+    /// Whether to parse the headers of parts with a `Content-Type` of `message/rfc822`, such
+    /// as emails forwarded as attachments, producing a `Node::Message`.  If the message is
+    /// itself multipart, its parts are parsed too.  Defaults to `false`.
+    pub parse_messages: bool,
+    /// Whether to skip parts which are malformed and carry on with the next part, rather
+    /// than failing.  Each part skipped is kept as a `Node::Invalid`, holding its bytes as
+    /// read and the error.  Parts with a malformed boundary line or header block, too many
+    /// header fields, or files which would exceed the `disk_quota`, and nested multiparts
+    /// without a usable boundary, are skipped this way; a body which ends prematurely still
+    /// fails (unless `lenient`).  Defaults to `false`.
+    pub recover: bool,
+    /// Whether to keep the exact bytes around the nodes, as `MultipartBody::framing`, so that
+    /// `write_multipart_full()` can reproduce the body byte for byte: boundary lines with
+    /// their padding and line terminators, header blocks as read, and the preamble and
+    /// epilogue of nested multiparts.  Only the `_full` functions return them.  Bodies are
+    /// only reproduced if not decoded (see `decode_transfer_encoding`), and parts skipped
+    /// are left out.  Defaults to `false`.
+    pub keep_framing: bool,
+    /// How `FormData::read()` treats fields with the same name as an earlier one.  Defaults
+    /// to `DuplicateFields::KeepAll`.
+    pub duplicate_fields: DuplicateFields,
+    /// Whether to reject the parts of a `multipart/form-data` without a
+    /// `Content-Disposition` of `form-data` giving the field name, as RFC 7578 requires,
+    /// with `Error::NoFieldName`.  Otherwise such parts are read like any other, and left out
+    /// of `FormData`.  Defaults to `false`.
+    pub require_field_names: bool,
+    /// The most bytes which may be written to files in all, across the parts of a parse,
+    /// beyond which parsing fails with `Error::DiskQuotaExceeded`.  Any files already
+    /// written are deleted as their `FilePart`s are dropped.  Under `recover`, a part whose
+    /// file would exceed it is kept as a `Node::Invalid` instead, and parsing carries on.
+    /// If `None`, there is no limit.  Defaults to `None`.
+    pub disk_quota: Option<u64>,
+    /// Whether the files of parts should have no name in any directory, as made by
+    /// `FilePart::create_unlinked()`, so that they are reclaimed even if the process
+    /// crashes.  Only on Linux; elsewhere ordinary temporary files are used.  Defaults to
+    /// `false`.
+    pub unlinked_files: bool,
+    /// If set, the bodies of file parts are stored here, e.g. in an object store, rather
+    /// than in temporary files (as by `TempFileStorage`).  Detecting the type of files under
+    /// `sniff_files` or `content_type_policy` reads them at their `path`, so needs them on
+    /// local disk.  Defaults to `None`.
+    pub part_storage: Option<Arc<dyn PartStorage>>,
+    /// The capacity in bytes of the buffer the stream is read through.  A larger buffer
+    /// makes for fewer reads, and fewer boundary searches, when parts are large (such as
+    /// 256 KiB for files of many megabytes).  Taken as 1 if zero.  Defaults to 4096.
+    pub buffer_size: usize,
+    /// Whether to detect the type of each file part from the leading bytes of its content,
+    /// recording it as `FilePart::detected_type`.  Defaults to `false`.
+    #[cfg(feature = "sniff")]
+    pub sniff_files: bool,
+    /// If set, the type detected from the content of each file part, and of each part with a
+    /// filename kept in memory, is checked against its `Content-Type` and against the types
+    /// this accepts, as `ContentTypePolicy` describes, failing the parse if the part is
+    /// rejected.  The detected type of file parts is recorded as for `sniff_files`.
+    /// Defaults to `None`.
+    #[cfg(feature = "sniff")]
+    pub content_type_policy: Option<ContentTypePolicy>,
+    /// Whether to check the body of each part with a `Content-MD5` header (RFC 1864) against
+    /// it as the body is read, failing with `Error::ContentMd5Mismatch` if they differ.  The
+    /// digest is of the body without its `Content-Transfer-Encoding`, so bodies which have
+    /// one and are not decoded (see `decode_transfer_encoding`) are not checked.  Defaults
+    /// to `false`.
+    #[cfg(feature = "md5")]
+    pub verify_content_md5: bool,
+}
+impl Default for ParseOptions {
+    fn default() -> ParseOptions {
+        ParseOptions {
+            storage: Storage::Auto,
+            storage_decision: None,
+            decode_transfer_encoding: false,
+            lenient: false,
+            strict: false,
+            error_positions: false,
+            progress: None,
+            stats: None,
+            inspect: None,
+            cancel: None,
+            max_part_headers: None,
+            max_main_headers: None,
+            default_content_types: false,
+            parse_messages: false,
+            recover: false,
+            keep_framing: false,
+            duplicate_fields: DuplicateFields::KeepAll,
+            require_field_names: false,
+            disk_quota: None,
+            unlinked_files: false,
+            part_storage: None,
+            buffer_size: 4096,
+            #[cfg(feature = "sniff")]
+            sniff_files: false,
+            #[cfg(feature = "sniff")]
+            content_type_policy: None,
+            #[cfg(feature = "md5")]
+            verify_content_md5: false,
+        }
+    }
+}
+impl fmt::Debug for ParseOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut debug = f.debug_struct("ParseOptions");
+        debug.field("storage", &self.storage)
+            .field("storage_decision", &self.storage_decision.as_ref().map(|_| Opaque))
+            .field("decode_transfer_encoding", &self.decode_transfer_encoding)
+            .field("lenient", &self.lenient)
+            .field("strict", &self.strict)
+            .field("error_positions", &self.error_positions)
+            .field("progress", &self.progress.as_ref().map(|_| Opaque))
+            .field("stats", &self.stats.as_ref().map(|_| Opaque))
+            .field("inspect", &self.inspect.as_ref().map(|_| Opaque))
+            .field("cancel", &self.cancel)
+            .field("max_part_headers", &self.max_part_headers)
+            .field("max_main_headers", &self.max_main_headers)
+            .field("default_content_types", &self.default_content_types)
+            .field("parse_messages", &self.parse_messages)
+            .field("recover", &self.recover)
+            .field("keep_framing", &self.keep_framing)
+            .field("duplicate_fields", &self.duplicate_fields)
+            .field("require_field_names", &self.require_field_names)
+            .field("disk_quota", &self.disk_quota)
+            .field("unlinked_files", &self.unlinked_files)
+            .field("part_storage", &self.part_storage.as_ref().map(|_| Opaque))
+            .field("buffer_size", &self.buffer_size);
+        #[cfg(feature = "sniff")]
+        debug.field("sniff_files", &self.sniff_files)
+            .field("content_type_policy", &self.content_type_policy);
+        #[cfg(feature = "md5")]
+        debug.field("verify_content_md5", &self.verify_content_md5);
+        debug.finish()
+    }
+}
+
+// Stands in for a callback or trait object in `Debug` output
+struct Opaque;
+
+impl fmt::Debug for Opaque {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("..")
+    }
+}
+
+impl ParseOptions {
+    fn from_always_use_files(always_use_files: bool) -> ParseOptions {
+        let mut options = ParseOptions::default();
+        if always_use_files {
+            options.storage = Storage::Files;
+        }
+        options
+    }
 }
 
 /// Parse a MIME `multipart/*` from a `Read`able stream into a `Vec` of `Node`s, streaming
@@ -151,34 +1248,62 @@ pub fn read_multipart<S: Read>(
     always_use_files: bool)
     -> Result<Vec<Node>, Error>
 {
-    let mut nodes: Vec<Node> = Vec::new();
-    let mut reader = BufReader::with_capacity(4096, stream);
+    read_multipart_with_options(stream,
+                                &ParseOptions::from_always_use_files(always_use_files))
+}
+
+/// Parse a MIME `multipart/*` from a `Read`able stream into a `Vec` of `Node`s, as
+/// `read_multipart()` does, but under the control of the `ParseOptions` given.
+///
+/// It is presumed that the headers are still in the stream.  If you have them separately,
+/// use `read_multipart_body_with_options()` instead.
+pub fn read_multipart_with_options<S: Read>(
+    stream: &mut S,
+    options: &ParseOptions)
+    -> Result<Vec<Node>, Error>
+{
+    with_context(stream, options, |reader, context| {
+        let headers = try!(read_main_headers(reader, context.options.max_main_headers));
+        let mut body = MultipartBody::default();
+        try!(inner(reader, &headers, context, None, &mut body));
+        Ok(body.nodes)
+    })
+}
+
+/// Parse a MIME `multipart/*` from a `Read`able stream into a `MultipartBody`, which
+/// besides the `Node`s also holds the preamble and epilogue.  The stream is read to its end
+/// in order to capture the epilogue.
+///
+/// It is presumed that the headers are still in the stream.  If you have them separately,
+/// use `read_multipart_body_full()` instead.
+pub fn read_multipart_full<S: Read>(
+    stream: &mut S,
+    options: &ParseOptions)
+    -> Result<MultipartBody, Error>
+{
+    with_context(stream, options, |reader, context| {
+        let headers = try!(read_main_headers(reader, context.options.max_main_headers));
+        let mut body = MultipartBody::default();
+        try!(inner(reader, &headers, context, None, &mut body));
+        try!(read_epilogue(reader, &mut body));
+        Ok(body)
+    })
+}
 
+// Read and parse the top-level headers from the stream
+fn read_main_headers<R: BufRead>(reader: &mut R, max_headers: Option<usize>)
+                                 -> Result<Headers, Error>
+{
     let mut buf: Vec<u8> = Vec::new();
-    let mut header_memory = [httparse::EMPTY_HEADER; 64];
 
-    let (_, found) = try!(reader.stream_until_token(b"\r\n\r\n", &mut buf));
+    let (_, found) = try!(stream_until_token(reader, b"\r\n\r\n", &mut buf));
     if ! found { return Err(Error::EofInMainHeaders); }
 
     // Keep the CRLFCRLF as httparse will expect it
     buf.extend(b"\r\n\r\n".iter().cloned());
 
     // Parse the headers
-    {
-    let headers = try!(match httparse::parse_headers(&buf, &mut header_memory) {
-        Ok(httparse::Status::Complete((_, raw_headers))) => {
-            let mut headers = Headers::new();
-            use ::bytes::Bytes as Bs;
-            headers.extend(raw_headers.iter().map(|rh| (rh.name, Bs::from(rh.value))));
-            Ok(headers)
-        },
-        Ok(httparse::Status::Partial) => Err(Error::PartialHeaders),
-        Err(err) => Err(From::from(err)),
-    });
-
-    try!(inner(&mut reader, &headers, &mut nodes, always_use_files));
-    }
-    Ok(nodes)
+    parse_header_block(&buf, max_headers)
 }
 
 /// Parse a MIME `multipart/*` from a `Read`able stream into a `Vec` of `Node`s, streaming
@@ -191,90 +1316,600 @@ pub fn read_multipart<S: Read>(
 ///
 /// It is presumed that you have the `Headers` already and the stream starts at the body.
 /// If the headers are still in the stream, use `parse_multipart()` instead.
-pub fn read_multipart_body<S: Read>(
+pub fn read_multipart_body<S: Read, H: PartHeaders + ?Sized>(
     stream: &mut S,
-    headers: &Headers,
+    headers: &H,
     always_use_files: bool)
     -> Result<Vec<Node>, Error>
 {
-    let mut reader = BufReader::with_capacity(4096, stream);
-    let mut nodes: Vec<Node> = Vec::new();
-    try!(inner(&mut reader, headers, &mut nodes, always_use_files));
-    Ok(nodes)
+    read_multipart_body_with_options(stream, headers,
+                                     &ParseOptions::from_always_use_files(always_use_files))
 }
 
-fn inner<R: BufRead>(
-    reader: &mut R,
-    headers: &Headers,
-    nodes: &mut Vec<Node>,
-    always_use_files: bool)
-    -> Result<(), Error>
+/// Parse a MIME `multipart/*` body from a `Read`able stream into a `Vec` of `Node`s, as
+/// `read_multipart_body()` does, but under the control of the `ParseOptions` given.
+///
+/// It is presumed that you have the `Headers` already and the stream starts at the body.
+/// If the headers are still in the stream, use `read_multipart_with_options()` instead.
+pub fn read_multipart_body_with_options<S: Read, H: PartHeaders + ?Sized>(
+    stream: &mut S,
+    headers: &H,
+    options: &ParseOptions)
+    -> Result<Vec<Node>, Error>
 {
-    let mut buf: Vec<u8> = Vec::new();
+    with_context(stream, options, |reader, context| {
+        let mut body = MultipartBody::default();
+        try!(inner(reader, headers, context, None, &mut body));
+        Ok(body.nodes)
+    })
+}
 
-    let boundary = try!(get_multipart_boundary(headers));
+/// Parse a MIME `multipart/*` body from a `Read`able stream into a `MultipartBody`, which
+/// besides the `Node`s also holds the preamble and epilogue.  The stream is read to its end
+/// in order to capture the epilogue.
+///
+/// It is presumed that you have the `Headers` already and the stream starts at the body.
+/// If the headers are still in the stream, use `read_multipart_full()` instead.
+pub fn read_multipart_body_full<S: Read, H: PartHeaders + ?Sized>(
+    stream: &mut S,
+    headers: &H,
+    options: &ParseOptions)
+    -> Result<MultipartBody, Error>
+{
+    with_context(stream, options, |reader, context| {
+        let mut body = MultipartBody::default();
+        try!(inner(reader, headers, context, None, &mut body));
+        try!(read_epilogue(reader, &mut body));
+        Ok(body)
+    })
+}
 
-    // Read past the initial boundary
-    let (_, found) = try!(reader.stream_until_token(&boundary, &mut buf));
-    if ! found { return Err(Error::EofBeforeFirstBoundary); }
+/// The result of a parse which keeps what it could: the nodes read before any error
+/// occurred, and the error if one did
+#[derive(Debug)]
+pub struct ParseOutcome {
+    /// The nodes read completely.  A nested multipart in which the error occurred is
+    /// included, holding the nodes read from it.
+    pub nodes: Vec<Node>,
+    /// The error which ended the parse, if any
+    pub error: Option<Error>,
+}
+impl ParseOutcome {
+    /// Convert into a `Result`, discarding the nodes if there was an error
+    pub fn into_result(self) -> Result<Vec<Node>, Error> {
+        match self.error {
+            Some(error) => Err(error),
+            None => Ok(self.nodes),
+        }
+    }
+}
 
-    // Define the boundary, including the line terminator preceding it.
-    // Use their first line terminator to determine whether to use CRLF or LF.
-    let (lt, ltlt, lt_boundary) = {
-        let peeker = try!(reader.fill_buf());
-        if peeker.len() > 1 && &peeker[..2]==b"\r\n" {
-            let mut output = Vec::with_capacity(2 + boundary.len());
-            output.push(b'\r');
-            output.push(b'\n');
-            output.extend(boundary.clone());
-            (vec![b'\r', b'\n'], vec![b'\r', b'\n', b'\r', b'\n'], output)
-        }
-        else if peeker.len() > 0 && peeker[0]==b'\n' {
-            let mut output = Vec::with_capacity(1 + boundary.len());
-            output.push(b'\n');
-            output.extend(boundary.clone());
-            (vec![b'\n'], vec![b'\n', b'\n'], output)
-        }
-        else {
-            return Err(Error::NoCrLfAfterBoundary);
+/// Parse a MIME `multipart/*` from a `Read`able stream, as `read_multipart_with_options()`
+/// does, but keeping the nodes read before any error occurs.
+///
+/// It is presumed that the headers are still in the stream.  If you have them separately,
+/// use `read_multipart_body_salvage()` instead.
+pub fn read_multipart_salvage<S: Read>(
+    stream: &mut S,
+    options: &ParseOptions)
+    -> ParseOutcome
+{
+    let mut body = MultipartBody::default();
+    let result = with_context(stream, options, |reader, context| {
+        let headers = try!(read_main_headers(reader, context.options.max_main_headers));
+        inner(reader, &headers, context, None, &mut body)
+    });
+    ParseOutcome {
+        nodes: body.nodes,
+        error: result.err(),
+    }
+}
+
+/// Parse a MIME `multipart/*` body from a `Read`able stream, as
+/// `read_multipart_body_with_options()` does, but keeping the nodes read before any error
+/// occurs.
+///
+/// It is presumed that you have the `Headers` already and the stream starts at the body.
+/// If the headers are still in the stream, use `read_multipart_salvage()` instead.
+pub fn read_multipart_body_salvage<S: Read, H: PartHeaders + ?Sized>(
+    stream: &mut S,
+    headers: &H,
+    options: &ParseOptions)
+    -> ParseOutcome
+{
+    let mut body = MultipartBody::default();
+    let result = with_context(stream, options, |reader, context| {
+        inner(reader, headers, context, None, &mut body)
+    });
+    ParseOutcome {
+        nodes: body.nodes,
+        error: result.err(),
+    }
+}
+
+// The state of a single parse, shared between the reader and the parser
+struct ParseContext<'a> {
+    options: &'a ParseOptions,
+    bytes_read: Cell<u64>,
+    parts_completed: Cell<usize>,
+    cancelled: Cell<bool>,
+    // The bytes written to files so far, counted against `ParseOptions::disk_quota`
+    disk_written: Cell<u64>,
+    quota_exceeded: Cell<bool>,
+    // For `ParseStats`: the parts stored in files, and the depth of nesting (current and
+    // deepest)
+    file_parts: Cell<usize>,
+    depth: Cell<usize>,
+    max_depth: Cell<usize>,
+    // Where file parts go without `ParseOptions::part_storage`: one temporary directory for
+    // the whole parse
+    temp_storage: TempFileStorage,
+    // The headers of the part being read, to be given with any error
+    current_part: RefCell<Option<Vec<(String, Vec<u8>)>>>,
+    // Whether a CR preceded the last delimiter found, for `ParseOptions::keep_framing`
+    delimiter_cr: Cell<bool>,
+    // Buffers reused from part to part, and from parse to parse by a `Parser`
+    scratch: RefCell<Scratch>,
+}
+impl<'a> ParseContext<'a> {
+    fn report_progress(&self) {
+        if let Some(ref progress) = self.options.progress {
+            progress(ProgressEvent {
+                bytes_read: self.bytes_read.get(),
+                parts_completed: self.parts_completed.get(),
+            });
+        }
+    }
+
+    // Check the cancellation flag, remembering if it was set so that the I/O error used to
+    // abort the parse can be told apart from a real one.
+    fn check_cancelled(&self) -> ::std::io::Result<()> {
+        if let Some(ref cancel) = self.options.cancel {
+            if cancel.load(Ordering::SeqCst) {
+                self.cancelled.set(true);
+                return Err(::std::io::Error::new(::std::io::ErrorKind::Other,
+                                                 "parse cancelled"));
+            }
+        }
+        Ok(())
+    }
+
+    fn inspect(&self, headers: &Headers) -> Disposition {
+        match self.options.inspect {
+            Some(ref inspect) => inspect(headers),
+            None => Disposition::Accept,
+        }
+    }
+
+    fn stats(&self) -> ParseStats {
+        ParseStats {
+            parts: self.parts_completed.get(),
+            file_parts: self.file_parts.get(),
+            bytes_read: self.bytes_read.get(),
+            bytes_written: self.disk_written.get(),
+            max_depth: self.max_depth.get(),
         }
+    }
+
+    fn part_completed(&self) {
+        *self.current_part.borrow_mut() = None;
+        self.parts_completed.set(self.parts_completed.get() + 1);
+        self.report_progress();
+    }
+}
+
+// A reader which counts the bytes read through it into the parse context
+struct CountingReader<'a, S: Read + 'a> {
+    inner: &'a mut S,
+    context: &'a ParseContext<'a>,
+}
+impl<'a, S: Read> Read for CountingReader<'a, S> {
+    fn read(&mut self, buf: &mut [u8]) -> ::std::io::Result<usize> {
+        try!(self.context.check_cancelled());
+        let count = try!(self.inner.read(buf));
+        if count > 0 {
+            self.context.bytes_read.set(self.context.bytes_read.get() + count as u64);
+            self.context.report_progress();
+        }
+        Ok(count)
+    }
+}
+
+// A buffered reader, as `BufReader`, over a buffer which is given back once done with so
+// that it can be reused
+struct ReadBuffer<R: Read> {
+    inner: R,
+    buf: Vec<u8>,
+    pos: usize,
+    filled: usize,
+}
+impl<R: Read> ReadBuffer<R> {
+    fn new(inner: R, mut buf: Vec<u8>, capacity: usize) -> ReadBuffer<R> {
+        buf.resize(capacity, 0);
+        ReadBuffer {
+            inner: inner,
+            buf: buf,
+            pos: 0,
+            filled: 0,
+        }
+    }
+
+    // The bytes read ahead of the parser
+    fn buffer(&self) -> &[u8] {
+        &self.buf[self.pos..self.filled]
+    }
+
+    fn into_buf(self) -> Vec<u8> {
+        self.buf
+    }
+}
+impl<R: Read> Read for ReadBuffer<R> {
+    fn read(&mut self, out: &mut [u8]) -> ::std::io::Result<usize> {
+        // Large reads bypass the buffer when it is empty
+        if self.pos == self.filled && out.len() >= self.buf.len() {
+            return self.inner.read(out);
+        }
+        let count = {
+            let available = try!(self.fill_buf());
+            let count = ::std::cmp::min(available.len(), out.len());
+            out[..count].copy_from_slice(&available[..count]);
+            count
+        };
+        self.consume(count);
+        Ok(count)
+    }
+}
+impl<R: Read> BufRead for ReadBuffer<R> {
+    fn fill_buf(&mut self) -> ::std::io::Result<&[u8]> {
+        if self.pos == self.filled {
+            self.filled = try!(self.inner.read(&mut self.buf));
+            self.pos = 0;
+        }
+        Ok(&self.buf[self.pos..self.filled])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.pos = ::std::cmp::min(self.pos + amount, self.filled);
+    }
+}
+
+// Buffers kept from one parse to the next by a `Parser`, rather than allocated afresh
+#[derive(Debug, Default)]
+struct Scratch {
+    // The buffer the stream is read through
+    read: Vec<u8>,
+    // Headers being parsed, with their line terminators normalized
+    headers: Vec<u8>,
+}
+
+// Run `parse` on a buffered reader over the stream, with buffers allocated for the parse
+fn with_context<S, T, F>(stream: &mut S, options: &ParseOptions, parse: F)
+                         -> Result<T, Error>
+    where S: Read,
+          F: FnOnce(&mut ReadBuffer<CountingReader<S>>, &ParseContext) -> Result<T, Error>
+{
+    with_context_reusing(stream, options, &mut Scratch::default(), parse)
+}
+
+// Run `parse` on a buffered reader over the stream, using the buffers of `scratch`, which
+// are put back afterwards.  Under `ParseOptions::error_positions`, errors are given the
+// position in the stream at which they occurred, and the headers of the part being read, as
+// an `Error::At`.  The stats callback is called once it is done.
+fn with_context_reusing<S, T, F>(stream: &mut S, options: &ParseOptions,
+                                 scratch: &mut Scratch, parse: F)
+                                 -> Result<T, Error>
+    where S: Read,
+          F: FnOnce(&mut ReadBuffer<CountingReader<S>>, &ParseContext) -> Result<T, Error>
+{
+    let buf = ::std::mem::replace(&mut scratch.read, Vec::new());
+    let (result, stats) = {
+        let context = ParseContext {
+            options: options,
+            bytes_read: Cell::new(0),
+            parts_completed: Cell::new(0),
+            cancelled: Cell::new(false),
+            disk_written: Cell::new(0),
+            quota_exceeded: Cell::new(false),
+            file_parts: Cell::new(0),
+            depth: Cell::new(1),
+            max_depth: Cell::new(1),
+            temp_storage: TempFileStorage::new(options.unlinked_files),
+            current_part: RefCell::new(None),
+            delimiter_cr: Cell::new(false),
+            scratch: RefCell::new(::std::mem::replace(scratch, Scratch::default())),
+        };
+        let result = {
+            let counting = CountingReader { inner: stream, context: &context };
+            let capacity = ::std::cmp::max(options.buffer_size, 1);
+            let mut reader = ReadBuffer::new(counting, buf, capacity);
+            let result = parse(&mut reader, &context).map_err(|error| {
+                let error = if context.quota_exceeded.get() {
+                    Error::DiskQuotaExceeded
+                } else {
+                    error
+                };
+                if ! options.error_positions {
+                    return error;
+                }
+                // Where the parser had got to, not counting what was read ahead
+                let offset = context.bytes_read.get() - reader.buffer().len() as u64;
+                Error::At(Box::new(ErrorPosition {
+                    offset: offset,
+                    part: context.parts_completed.get(),
+                    headers: context.current_part.borrow_mut().take(),
+                    error: error,
+                }))
+            });
+            context.scratch.borrow_mut().read = reader.into_buf();
+            result
+        };
+        let result = if context.cancelled.get() { Err(Error::Cancelled) } else { result };
+        let stats = context.stats();
+        *scratch = context.scratch.into_inner();
+        (result, stats)
     };
+    if let Some(ref callback) = options.stats {
+        callback(stats);
+    }
+    result
+}
+
+// Consume any spaces and tabs at the front of the reader
+fn skip_whitespace<R: BufRead>(reader: &mut R) -> Result<(), Error> {
+    skip_whitespace_into(reader, &mut Vec::new())
+}
 
+// Consume any spaces and tabs at the front of the reader, keeping them in `skipped`
+fn skip_whitespace_into<R: BufRead>(reader: &mut R, skipped: &mut Vec<u8>)
+                                    -> Result<(), Error>
+{
     loop {
-        // If the next two lookahead characters are '--', parsing is finished.
-        {
+        let (count, more) = {
             let peeker = try!(reader.fill_buf());
-            if peeker.len() >= 2 && &peeker[..2] == b"--" {
-                return Ok(());
+            let count = peeker.iter().take_while(|&&b| b == b' ' || b == b'\t').count();
+            skipped.extend_from_slice(&peeker[..count]);
+            (count, count > 0 && count == peeker.len())
+        };
+        reader.consume(count);
+        if ! more { return Ok(()); }
+    }
+}
+
+// Read the remainder of the stream, following the closing boundary, as the epilogue of the
+// body.  The line terminator left out of it goes to the end of the closing delimiter kept in
+// the framing, if any.
+fn read_epilogue<R: BufRead>(reader: &mut R, body: &mut MultipartBody) -> Result<(), Error> {
+    try!(reader.read_to_end(&mut body.epilogue));
+    let terminator = strip_leading_line_terminator(&mut body.epilogue);
+    if let Some(ref mut framing) = body.framing {
+        framing.close.extend_from_slice(terminator);
+    }
+    Ok(())
+}
+
+// Returns the line terminator stripped, if any
+fn strip_leading_line_terminator(bytes: &mut Vec<u8>) -> &'static [u8] {
+    if bytes.starts_with(b"\r\n") {
+        bytes.drain(..2);
+        b"\r\n"
+    } else if bytes.starts_with(b"\n") {
+        bytes.drain(..1);
+        b"\n"
+    } else {
+        b""
+    }
+}
+
+// Returns the line terminator stripped, if any
+fn strip_trailing_line_terminator(bytes: &mut Vec<u8>) -> &'static [u8] {
+    if bytes.ends_with(b"\r\n") {
+        let len = bytes.len() - 2;
+        bytes.truncate(len);
+        b"\r\n"
+    } else if bytes.ends_with(b"\n") {
+        let len = bytes.len() - 1;
+        bytes.truncate(len);
+        b"\n"
+    } else {
+        b""
+    }
+}
+
+// Parse the body of a `multipart/*`.  For a nested multipart, `outer_delimiter` is the
+// delimiter of the enclosing multipart, up to which the nested epilogue extends.  At the top
+// level the reader is left just past the closing boundary, and the epilogue is not read.
+// What is parsed goes into `body` as it is read, so that it is kept should an error occur.
+fn inner<R: BufRead, H: PartHeaders + ?Sized>(
+    reader: &mut R,
+    headers: &H,
+    context: &ParseContext,
+    outer_delimiter: Option<&[u8]>,
+    body: &mut MultipartBody)
+    -> Result<(), Error>
+{
+    let options = context.options;
+    let mut buf: Vec<u8> = Vec::new();
+
+    let boundary = try!(multipart_boundary(headers, options));
+    let parent_type = match headers.content_type() {
+        Some(mime) => mime,
+        None => return Err(Error::NoRequestContentType),
+    };
+
+    // Read past the initial boundary, keeping the preamble
+    let (_, found) = try!(stream_until_token(reader, &boundary, &mut buf));
+    if ! found { return Err(Error::EofBeforeFirstBoundary); }
+    body.preamble = ::std::mem::replace(&mut buf, Vec::new());
+    let terminator = strip_trailing_line_terminator(&mut body.preamble);
+
+    // Under `keep_framing`, the bytes around the nodes, and those of the delimiter being read
+    if options.keep_framing {
+        body.framing = Some(Framing::default());
+    }
+    let mut pending: Vec<u8> = Vec::new();
+    pending.extend_from_slice(terminator);
+    pending.extend_from_slice(&boundary);
+    let mut first = true;
+
+    // The delimiter ending each part body: the boundary preceded by a line terminator.  Only
+    // the LF is searched for; a CR preceding it is stripped from the body.  This way each
+    // boundary may use either CRLF or LF, regardless of the others.
+    let mut delimiter = Vec::with_capacity(1 + boundary.len());
+    delimiter.push(b'\n');
+    delimiter.extend(boundary.iter().cloned());
+
+    // Set if the body ended prematurely, which is only tolerated when lenient
+    let mut eof = false;
+
+    loop {
+        // The delimiter just found, ending the body of the previous part
+        if ! first {
+            pending.truncate(0);
+            if context.delimiter_cr.get() {
+                pending.push(b'\r');
             }
+            pending.extend_from_slice(&delimiter);
         }
+        first = false;
 
-        // Read the line terminator after the boundary
-        let (_, found) = try!(reader.stream_until_token(&lt, &mut buf));
-        if ! found { return Err(Error::NoCrLfAfterBoundary); }
+        if options.lenient {
+            try!(skip_whitespace_into(reader, &mut pending));
+        }
 
-        // Read the headers (which end in 2 line terminators)
-        buf.truncate(0); // start fresh
-        let (_, found) = try!(reader.stream_until_token(&ltlt, &mut buf));
-        if ! found { return Err(Error::EofInPartHeaders); }
-
-        // Keep the 2 line terminators as httparse will expect it
-        buf.extend(ltlt.iter().cloned());
-
-        // Parse the headers
-        let part_headers = {
-            let mut header_memory = [httparse::EMPTY_HEADER; 4];
-            try!(match httparse::parse_headers(&buf, &mut header_memory) {
-                Ok(httparse::Status::Complete((_, raw_headers))) => {
-                    let mut headers = Headers::new();
-                    use ::bytes::Bytes as Bs;
-                    headers.extend(raw_headers.iter().map(|rh| (rh.name, Bs::from(rh.value))));
-                    Ok(headers)
-                },
-                Ok(httparse::Status::Partial) => Err(Error::PartialHeaders),
-                Err(err) => Err(From::from(err)),
-            })
+        // If the next two characters are '--', parsing is finished.  They may straddle the
+        // end of the buffer, in which case the first is consumed to look at the second.
+        let (closing, dash) = {
+            let peeker = try!(reader.fill_buf());
+            if peeker.is_empty() && options.lenient {
+                eof = true;
+                break;
+            }
+            (peeker.len() >= 2 && &peeker[..2] == b"--", peeker == b"-")
+        };
+        if closing {
+            reader.consume(2);
+            break;
+        }
+        buf.truncate(0);
+        if dash {
+            reader.consume(1);
+            if try!(reader.fill_buf()).first() == Some(&b'-') {
+                reader.consume(1);
+                break;
+            }
+            buf.push(b'-');
+        }
+
+        // Read the line terminator after the boundary, allowing for transport padding
+        let (_, found) = try!(stream_until_token(reader, b"\n", &mut buf));
+        if ! found {
+            if options.lenient { eof = true; break; }
+            return Err(Error::NoCrLfAfterBoundary);
+        }
+        let malformed = if ! buf.ends_with(b"\r") && options.strict {
+            Some(Error::BareLineFeed)
+        } else if ! options.lenient
+            && buf.iter().any(|&b| b != b' ' && b != b'\t' && b != b'\r')
+        {
+            Some(Error::NoCrLfAfterBoundary)
+        } else {
+            None
+        };
+        if let Some(ref mut framing) = body.framing {
+            if malformed.is_none() {
+                pending.extend_from_slice(&buf);
+                pending.push(b'\n');
+            }
+            framing.delimiters.push(::std::mem::replace(&mut pending, Vec::new()));
+            framing.headers.push(Vec::new());
+            framing.nested.push(None);
+        }
+        if let Some(error) = malformed {
+            if ! options.recover {
+                return Err(error);
+            }
+            buf.push(b'\n');
+            let raw = ::std::mem::replace(&mut buf, Vec::new());
+            if ! try!(skip_invalid_part(reader, &delimiter, Headers::new(), raw, error, body,
+                                        context)) {
+                eof = true;
+                break;
+            }
+            continue;
+        }
+
+        // Read and parse the headers
+        let mut raw_headers = match try!(read_header_block(reader)) {
+            Some(raw_headers) => raw_headers,
+            None => {
+                if options.lenient { eof = true; break; }
+                return Err(Error::EofInPartHeaders);
+            },
         };
+        let mut part_headers = match parse_part_headers(&raw_headers, context) {
+            Ok(part_headers) => part_headers,
+            Err(error) => {
+                if ! options.recover {
+                    return Err(error);
+                }
+                if ! try!(skip_invalid_part(reader, &delimiter, Headers::new(), raw_headers,
+                                            error, body, context)) {
+                    eof = true;
+                    break;
+                }
+                continue;
+            },
+        };
+        if options.default_content_types && ! part_headers.has::<ContentType>() {
+            part_headers.set(ContentType(default_content_type(&parent_type)));
+        }
+        *context.current_part.borrow_mut() = Some(part_headers.header_pairs());
+        let mut framing_headers = if body.framing.is_some() { raw_headers.clone() }
+                                  else { Vec::new() };
+        if options.require_field_names && parent_type.subtype() == mime::FORM_DATA
+            && ! is_form_field(&part_headers)
+        {
+            if ! options.recover {
+                return Err(Error::NoFieldName);
+            }
+            if ! try!(skip_invalid_part(reader, &delimiter, part_headers, raw_headers,
+                                        Error::NoFieldName, body, context)) {
+                eof = true;
+                break;
+            }
+            continue;
+        }
+
+        // Check for an encapsulated message, whose own headers follow.  From here on the
+        // message is handled as the part, and is wrapped in a `Node::Message` once read.
+        let mut message: Option<Headers> = None;
+        if options.parse_messages && is_message(&part_headers) {
+            match try!(read_part_headers(reader, context)) {
+                Some((message_headers, message_raw_headers)) => {
+                    message = Some(::std::mem::replace(&mut part_headers, message_headers));
+                    if body.framing.is_some() {
+                        framing_headers.extend_from_slice(&message_raw_headers);
+                    }
+                    raw_headers = message_raw_headers;
+                },
+                None => {
+                    if options.lenient { eof = true; break; }
+                    return Err(Error::EofInPartHeaders);
+                },
+            }
+        }
+
+        match context.inspect(&part_headers) {
+            Disposition::Accept => { },
+            Disposition::Skip => {
+                if ! try!(skip_part(reader, &delimiter, body, context)) {
+                    eof = true;
+                    break;
+                }
+                continue;
+            },
+            Disposition::Abort => return Err(Error::PartRejected),
+        }
 
         // Check for a nested multipart
         let nested = {
@@ -284,66 +1919,743 @@ fn inner<R: BufRead>(
             } else {
                 false
             }
-        };
-        if nested {
-            // Recurse:
-            let mut inner_nodes: Vec<Node> = Vec::new();
-            try!(inner(reader, &part_headers, &mut inner_nodes, always_use_files));
-            nodes.push(Node::Multipart((part_headers, inner_nodes)));
-            continue;
+        };
+        if nested && options.recover {
+            if let Err(error) = multipart_boundary(&part_headers, options) {
+                if ! try!(skip_invalid_part(reader, &delimiter, part_headers, raw_headers, error,
+                                            body, context)) {
+                    eof = true;
+                    break;
+                }
+                continue;
+            }
+        }
+        if let Some(ref mut framing) = body.framing {
+            *framing.headers.last_mut().unwrap() = framing_headers;
+        }
+        if nested {
+            // Recurse:
+            let mut nested = MultipartBody::default();
+            let depth = context.depth.get() + 1;
+            context.depth.set(depth);
+            context.max_depth.set(::std::cmp::max(depth, context.max_depth.get()));
+            let result = inner(reader, &part_headers, context, Some(&delimiter), &mut nested);
+            context.depth.set(depth - 1);
+            let nodes = ::std::mem::replace(&mut nested.nodes, Vec::new());
+            body.nodes.push(encapsulate(message, Node::Multipart((part_headers, nodes))));
+            if let Some(ref mut framing) = body.framing {
+                *framing.nested.last_mut().unwrap() = Some(nested);
+            }
+            try!(result);
+            continue;
+        }
+
+        let (node, found) = try!(read_part_body(reader, &delimiter, part_headers, raw_headers,
+                                                message, &mut buf, body, context));
+        if let Some(node) = node {
+            body.nodes.push(node);
+        }
+        if ! found {
+            eof = true;
+            break;
+        }
+    }
+
+    if ! eof {
+        // The "--" ending the closing boundary has been read
+        pending.extend_from_slice(b"--");
+
+        if let Some(outer_delimiter) = outer_delimiter {
+            let (_, found) = try!(stream_part_body(reader, outer_delimiter, None,
+                                                   &mut body.epilogue, &context.delimiter_cr,
+                                                   options));
+            if ! found && ! options.lenient { return Err(Error::EofInPart); }
+            pending.extend_from_slice(strip_leading_line_terminator(&mut body.epilogue));
+        }
+    }
+    if let Some(ref mut framing) = body.framing {
+        framing.close = pending;
+    }
+
+    Ok(())
+}
+
+// Read the header lines of a part, up to an empty line, and parse them.  Returns the headers
+// along with the lines exactly as read, or `None` if the stream ends first.
+fn read_part_headers<R: BufRead>(reader: &mut R, context: &ParseContext)
+                                 -> Result<Option<(Headers, Vec<u8>)>, Error>
+{
+    match try!(read_header_block(reader)) {
+        Some(raw_headers) => {
+            let headers = try!(parse_part_headers(&raw_headers, context));
+            Ok(Some((headers, raw_headers)))
+        },
+        None => Ok(None),
+    }
+}
+
+// Read the header lines of a part exactly as they are, up to and including an empty line.
+// Returns `None` if the stream ends first.
+fn read_header_block<R: BufRead>(reader: &mut R) -> Result<Option<Vec<u8>>, Error> {
+    // Lines are read straight into the block, which is kept by the part
+    let mut raw_headers: Vec<u8> = Vec::new();
+    loop {
+        let start = raw_headers.len();
+        let (_, found) = try!(stream_until_token(reader, b"\n", &mut raw_headers));
+        if ! found {
+            return Ok(None);
+        }
+        let empty = raw_headers[start..] == b""[..] || raw_headers[start..] == b"\r"[..];
+        raw_headers.push(b'\n');
+        if empty {
+            return Ok(Some(raw_headers));
+        }
+    }
+}
+
+// Parse a header block as read by `read_header_block()`
+fn parse_part_headers(raw_headers: &[u8], context: &ParseContext) -> Result<Headers, Error> {
+    let options = context.options;
+    // Line terminators are normalized to CRLF as httparse expects.
+    let buf = &mut context.scratch.borrow_mut().headers;
+    buf.truncate(0);
+    let mut lines = raw_headers.split(|&b| b == b'\n');
+    // Nothing follows the final LF
+    lines.next_back();
+    for line in lines {
+        let line = if line.ends_with(b"\r") {
+            &line[..line.len() - 1]
+        } else if options.strict {
+            return Err(Error::BareLineFeed);
+        } else {
+            line
+        };
+        if line.is_empty() {
+            break;
+        }
+        buf.extend(line.iter().cloned());
+        buf.extend(b"\r\n".iter().cloned());
+    }
+    buf.extend(b"\r\n".iter().cloned());
+
+    parse_header_block(&buf, options.max_part_headers)
+}
+
+// Read the body of a part which is not multipart, up to and past the delimiter, or to the
+// end of the stream if the delimiter is empty.  The body is stored as the options decide,
+// and its node returned, wrapped in a `Node::Message` if `message` holds the headers of an
+// encapsulating message, or `None` if it was skipped.  Also returns whether the delimiter
+// was found; if not, the body ended first, which is only tolerated when lenient.
+fn read_part_body<R: BufRead>(
+    reader: &mut R,
+    delimiter: &[u8],
+    mut headers: Headers,
+    raw_headers: Vec<u8>,
+    message: Option<Headers>,
+    buf: &mut Vec<u8>,
+    body: &mut MultipartBody,
+    context: &ParseContext)
+    -> Result<(Option<Node>, bool), Error>
+{
+    let options = context.options;
+    let storage = match options.storage_decision {
+        Some(ref decide) => match decide(&headers) {
+            StorageDecision::Memory => Storage::Memory,
+            StorageDecision::File => Storage::Files,
+            StorageDecision::Skip => {
+                let found = try!(skip_part(reader, delimiter, body, context));
+                return Ok((None, found));
+            },
+        },
+        None => options.storage,
+    };
+
+    // Determine whether the body needs decoding, in which case the
+    // Content-Transfer-Encoding header no longer applies to the stored body.
+    let decode = if options.decode_transfer_encoding {
+        TransferEncoding::from_headers(&headers).and_then(|e| {
+            if e.is_encoded() { Some(e) } else { None }
+        })
+    } else {
+        None
+    };
+    if decode.is_some() {
+        headers.remove_raw(CONTENT_TRANSFER_ENCODING);
+    }
+
+    let is_file = match storage {
+        Storage::Files => true,
+        Storage::Memory => false,
+        Storage::Auto => is_file_part(&headers),
+        Storage::Threshold(threshold) => {
+            // Buffer in memory, spilling to a file once the threshold is reached.
+            let mut spill = SpillWriter::new(threshold, &headers, context);
+            let (size, found) = {
+                let mut check = Md5Check::new(&mut spill, &headers, options);
+                let (size, found) = try!(stream_part_body(reader, delimiter, decode,
+                                                          &mut check, &context.delimiter_cr,
+                                                          options));
+                if found { try!(check.finish()); }
+                (size, found)
+            };
+            if ! found && ! options.lenient {
+                return Err(if spill.spilled() { Error::EofInFile } else { Error::EofInPart });
+            }
+            if spill.over_quota() {
+                context.part_completed();
+                return Ok((Some(over_quota_part(headers, raw_headers)), found));
+            }
+            let mut node = try!(spill.into_node(headers, raw_headers, size));
+            match node {
+                Node::File(ref mut filepart) => {
+                    try!(sniff_file(filepart, options));
+                    context.file_parts.set(context.file_parts.get() + 1);
+                },
+                Node::Part(ref part) => try!(sniff_part(part, options)),
+                _ => { },
+            }
+            context.part_completed();
+            return Ok((Some(encapsulate(message, node)), found));
+        },
+    };
+    if is_file {
+        // Setup a file to capture the contents.
+        let mut file = try!(DiskWriter::create(&headers, context));
+
+        // Stream out the file.
+        let (size, found) = {
+            let mut check = Md5Check::new(&mut file, &headers, options);
+            let (size, found) = try!(stream_part_body(reader, delimiter, decode, &mut check,
+                                                      &context.delimiter_cr, options));
+            if found { try!(check.finish()); }
+            (size, found)
+        };
+        if ! found && ! options.lenient {
+            return Err(Error::EofInFile);
+        }
+        if file.over_quota {
+            context.part_completed();
+            return Ok((Some(over_quota_part(headers, raw_headers)), found));
+        }
+        let mut filepart = try!(file.finalize(headers));
+        filepart.raw_headers = Some(raw_headers);
+        filepart.size = Some(size);
+        try!(sniff_file(&mut filepart, options));
+        context.file_parts.set(context.file_parts.get() + 1);
+        context.part_completed();
+        Ok((Some(encapsulate(message, Node::File(filepart))), found))
+    } else {
+        buf.truncate(0); // start fresh
+        let found = {
+            let mut check = Md5Check::new(&mut *buf, &headers, options);
+            let (_, found) = try!(stream_part_body(reader, delimiter, decode, &mut check,
+                                                   &context.delimiter_cr, options));
+            if found { try!(check.finish()); }
+            found
+        };
+        if ! found && ! options.lenient {
+            return Err(Error::EofInPart);
+        }
+
+        let part = Part {
+            headers: headers,
+            body: Bytes::from(::std::mem::replace(buf, Vec::new())),
+            raw_headers: Some(raw_headers),
+        };
+        try!(sniff_part(&part, options));
+        context.part_completed();
+        Ok((Some(encapsulate(message, Node::Part(part))), found))
+    }
+}
+
+// Read past a part without storing it, as decided by `ParseOptions::inspect` or
+// `ParseOptions::storage_decision`, so that no node is produced for it.  Returns false if
+// the body ends first, which is only tolerated when lenient.
+fn skip_part<R: BufRead>(reader: &mut R, delimiter: &[u8], body: &mut MultipartBody,
+                         context: &ParseContext)
+                         -> Result<bool, Error>
+{
+    let (_, found) = try!(stream_part_body(reader, delimiter, None, &mut ::std::io::sink(),
+                                           &context.delimiter_cr, context.options));
+    if ! found && ! context.options.lenient {
+        return Err(Error::EofInPart);
+    }
+    if let Some(ref mut framing) = body.framing {
+        framing.drop_last();
+    }
+    context.part_completed();
+    Ok(found)
+}
+
+// The node kept in place of a part whose file would have exceeded `ParseOptions::disk_quota`,
+// under `ParseOptions::recover`.  Its body has been read past, and its file deleted.
+fn over_quota_part(headers: Headers, raw_headers: Vec<u8>) -> Node {
+    Node::Invalid(InvalidPart {
+        headers: headers,
+        raw: raw_headers,
+        error: Arc::new(Error::DiskQuotaExceeded),
+    })
+}
+
+// Skip the rest of a malformed part, up to and past the delimiter, and keep it as a
+// `Node::Invalid` with the bytes already read of it in `raw`.  Returns false if the body
+// ends first, which is only tolerated when lenient.
+fn skip_invalid_part<R: BufRead>(
+    reader: &mut R,
+    delimiter: &[u8],
+    headers: Headers,
+    mut raw: Vec<u8>,
+    error: Error,
+    body: &mut MultipartBody,
+    context: &ParseContext)
+    -> Result<bool, Error>
+{
+    let (_, found) = try!(stream_until_token(reader, delimiter, &mut raw));
+    if ! found && ! context.options.lenient {
+        return Err(Error::EofInPart);
+    }
+    context.delimiter_cr.set(found && raw.ends_with(b"\r"));
+    if found && raw.ends_with(b"\r") {
+        raw.pop();
+    }
+    body.nodes.push(Node::Invalid(InvalidPart {
+        headers: headers,
+        raw: raw,
+        error: Arc::new(error),
+    }));
+    context.part_completed();
+    Ok(found)
+}
+
+// The boundary of a multipart, validated if parsing strictly
+fn multipart_boundary<H: PartHeaders + ?Sized>(headers: &H, options: &ParseOptions)
+                                               -> Result<Vec<u8>, Error>
+{
+    let boundary = try!(get_multipart_boundary(headers));
+    if options.strict {
+        try!(validate_boundary(&boundary[2..]));
+    }
+    Ok(boundary)
+}
+
+// Whether the headers are those of an encapsulated message
+fn is_message(headers: &Headers) -> bool {
+    match headers.get::<ContentType>() {
+        Some(ct) => ct.type_() == mime::MESSAGE && ct.subtype() == "rfc822",
+        None => false,
+    }
+}
+
+// Wrap the node read for an encapsulated message, given the headers of the part holding it
+fn encapsulate(message: Option<Headers>, node: Node) -> Node {
+    match message {
+        Some(headers) => Node::Message((headers, Box::new(node))),
+        None => node,
+    }
+}
+
+// Parse a block of header lines, terminated by an empty line, all with CRLF.  Room is made
+// for as many headers as there are lines, up to `max_headers`.
+fn parse_header_block(buf: &[u8], max_headers: Option<usize>) -> Result<Headers, Error> {
+    let lines = buf.iter().filter(|&&b| b == b'\n').count();
+    let capacity = match max_headers {
+        Some(max) => ::std::cmp::min(max, lines),
+        None => lines,
+    };
+    let mut header_memory = vec![httparse::EMPTY_HEADER; capacity];
+    match httparse::parse_headers(buf, &mut header_memory) {
+        Ok(httparse::Status::Complete((_, raw_headers))) => {
+            let mut headers = Headers::new();
+            headers.extend(raw_headers.iter().map(|rh| (rh.name, Bytes::from(rh.value))));
+            Ok(headers)
+        },
+        Ok(httparse::Status::Partial) => Err(Error::PartialHeaders),
+        Err(httparse::Error::TooManyHeaders) if capacity < lines => {
+            Err(Error::TooManyHeaders { max: capacity })
+        },
+        Err(err) => Err(From::from(err)),
+    }
+}
+
+// Stream a part body up to (and past) the delimiter into `out`, decoding it according to
+// `decode` if given.  Returns the number of bytes written to `out`, and whether the delimiter
+// was found.
+fn stream_part_body<R: BufRead, W: Write>(
+    reader: &mut R,
+    delimiter: &[u8],
+    decode: Option<TransferEncoding>,
+    out: &mut W,
+    cr: &Cell<bool>,
+    options: &ParseOptions)
+    -> Result<(usize, bool), Error>
+{
+    match decode {
+        Some(encoding) => {
+            let mut decoder = Decoder::new(out, encoding);
+            let (_, found) = try!(stream_until_delimiter(reader, delimiter, &mut decoder, cr,
+                                                         options));
+            let (_, written) = try!(decoder.finish());
+            Ok((written, found))
+        },
+        None => stream_until_delimiter(reader, delimiter, out, cr, options),
+    }
+}
+
+// Stream bytes up to (and past) the delimiter into `out`, leaving out the CR of a CRLF
+// preceding the delimiter, or all of them to the end of the stream if the delimiter is
+// empty.  Returns the number of bytes written to `out`, and whether the delimiter was found
+// (as it always is if empty); `cr` is set to whether a CR was left out.
+fn stream_until_delimiter<R: BufRead, W: Write>(
+    reader: &mut R,
+    delimiter: &[u8],
+    out: &mut W,
+    cr: &Cell<bool>,
+    options: &ParseOptions)
+    -> Result<(usize, bool), Error>
+{
+    if delimiter.is_empty() {
+        // The body runs to the end of the stream
+        cr.set(false);
+        let read = try!(::std::io::copy(reader, out));
+        return Ok((read as usize, true));
+    }
+    let mut holdback = CrHoldback { inner: out, held: false };
+    let (read, found) = try!(stream_until_token(reader, delimiter, &mut holdback));
+    cr.set(found && holdback.held);
+    if holdback.held {
+        if found {
+            return Ok((read - 1, true));
+        }
+        // Without a delimiter, the CR is part of the content
+        try!(holdback.inner.write_all(b"\r"));
+    } else if found && options.strict {
+        return Err(Error::BareLineFeed);
+    }
+    Ok((read, found))
+}
+
+// A writer which holds back a trailing CR until it is known not to be the last byte
+// written, as it may belong to the CRLF preceding a delimiter.
+struct CrHoldback<'a, W: Write + 'a> {
+    inner: &'a mut W,
+    held: bool,
+}
+impl<'a, W: Write> Write for CrHoldback<'a, W> {
+    fn write(&mut self, data: &[u8]) -> ::std::io::Result<usize> {
+        if data.is_empty() {
+            return Ok(0);
+        }
+        if self.held {
+            try!(self.inner.write_all(b"\r"));
+            self.held = false;
+        }
+        if data[data.len() - 1] == b'\r' {
+            try!(self.inner.write_all(&data[..data.len() - 1]));
+            self.held = true;
+        } else {
+            try!(self.inner.write_all(data));
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> ::std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// The decoded value of a `Content-Disposition` parameter
+fn disposition_param(headers: &Headers, name: &str) -> Result<Option<String>, Error> {
+    match try!(Params::from_header(headers, "Content-Disposition")) {
+        Some(params) => params.get(name),
+        None => Ok(None),
+    }
+}
+
+// Whether a part should be streamed to a file, judging from its headers alone: that is,
+// if it has a `ContentDisposition` of `Attachment` or with a `Filename` parameter.
+fn is_file_part(headers: &Headers) -> bool {
+    match Params::from_header(headers, "Content-Disposition") {
+        Ok(Some(cd)) => cd.value.eq_ignore_ascii_case("attachment") || cd.contains("filename"),
+        _ => false,
+    }
+}
+
+// Whether the headers are those of a form field: with a `Content-Disposition` of `form-data`
+// and a `name` parameter
+fn is_form_field(headers: &Headers) -> bool {
+    match Params::from_header(headers, "Content-Disposition") {
+        Ok(Some(cd)) => cd.value.eq_ignore_ascii_case("form-data")
+            && disposition_param(headers, "name").ok().map_or(false, |name| name.is_some()),
+        _ => false,
+    }
+}
+
+// A writer to the file of a part, in `ParseOptions::part_storage`, which counts the bytes
+// written against `ParseOptions::disk_quota`.  The body is deleted if the writer is dropped
+// before it is finalized.
+struct DiskWriter<'a> {
+    body: Option<Box<dyn StoredBody>>,
+    context: &'a ParseContext<'a>,
+    // Whether the quota was reached under `ParseOptions::recover`, after which the rest of
+    // the body is discarded rather than failing the parse
+    over_quota: bool,
+}
+impl<'a> DiskWriter<'a> {
+    fn create(headers: &Headers, context: &'a ParseContext<'a>) -> Result<DiskWriter<'a>, Error> {
+        let options = context.options;
+        let body = match options.part_storage {
+            Some(ref storage) => try!(storage.create(headers)),
+            None => try!(context.temp_storage.create(headers)),
+        };
+        Ok(DiskWriter {
+            body: Some(body),
+            context: context,
+            over_quota: false,
+        })
+    }
+
+    fn finalize(mut self, headers: Headers) -> Result<FilePart, Error> {
+        self.body.take().unwrap().finalize(headers)
+    }
+}
+impl<'a> Drop for DiskWriter<'a> {
+    fn drop(&mut self) {
+        if let Some(body) = self.body.take() {
+            body.delete();
+        }
+    }
+}
+impl<'a> Write for DiskWriter<'a> {
+    fn write(&mut self, data: &[u8]) -> ::std::io::Result<usize> {
+        if self.over_quota {
+            return Ok(data.len());
+        }
+        let written = self.context.disk_written.get();
+        if let Some(quota) = self.context.options.disk_quota {
+            if written + data.len() as u64 > quota {
+                if self.context.options.recover {
+                    self.over_quota = true;
+                    return Ok(data.len());
+                }
+                self.context.quota_exceeded.set(true);
+                return Err(::std::io::Error::new(::std::io::ErrorKind::Other,
+                                                 "disk quota exceeded"));
+            }
+        }
+        let count = try!(self.body.as_mut().unwrap().write(data));
+        self.context.disk_written.set(written + count as u64);
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> ::std::io::Result<()> {
+        self.body.as_mut().unwrap().flush()
+    }
+}
+
+// Record the type detected from the content of a file part, under
+// `ParseOptions::sniff_files`, and check it under `ParseOptions::content_type_policy`
+#[cfg(feature = "sniff")]
+fn sniff_file(filepart: &mut FilePart, options: &ParseOptions) -> Result<(), Error> {
+    if options.sniff_files || options.content_type_policy.is_some() {
+        filepart.detected_type = try!(sniff::detect_file(&filepart.path));
+    }
+    if let Some(ref policy) = options.content_type_policy {
+        try!(policy.check(filepart.content_type().as_ref(), filepart.detected_type.as_ref()));
+    }
+    Ok(())
+}
+#[cfg(not(feature = "sniff"))]
+fn sniff_file(_filepart: &mut FilePart, _options: &ParseOptions) -> Result<(), Error> {
+    Ok(())
+}
+
+// Check a part kept in memory under `ParseOptions::content_type_policy` if it has a filename,
+// as it would have been had it been stored in a file
+#[cfg(feature = "sniff")]
+fn sniff_part(part: &Part, options: &ParseOptions) -> Result<(), Error> {
+    if let Some(ref policy) = options.content_type_policy {
+        if is_file_part(&part.headers) {
+            let detected = sniff::detect(&part.body);
+            try!(policy.check(part.content_type().as_ref(), detected.as_ref()));
+        }
+    }
+    Ok(())
+}
+#[cfg(not(feature = "sniff"))]
+fn sniff_part(_part: &Part, _options: &ParseOptions) -> Result<(), Error> {
+    Ok(())
+}
+
+// A writer which passes a part body on as it is written, checking it against the Content-MD5
+// of the part under `ParseOptions::verify_content_md5`
+struct Md5Check<'a, W: Write + 'a> {
+    inner: &'a mut W,
+    // The digest being computed, and the one expected
+    #[cfg(feature = "md5")]
+    digest: Option<(md5::Md5, Vec<u8>)>,
+}
+
+impl<'a, W: Write> Md5Check<'a, W> {
+    #[cfg(feature = "md5")]
+    fn new(inner: &'a mut W, headers: &Headers, options: &ParseOptions) -> Md5Check<'a, W> {
+        use md5::Digest;
+
+        // The digest is of the decoded body, so cannot be checked if it is still encoded
+        let encoded = TransferEncoding::from_headers(headers).map_or(false, |e| e.is_encoded());
+        let expected = if options.verify_content_md5 && ! encoded {
+            headers.get_raw(CONTENT_MD5).and_then(|raw| raw.one()).and_then(|value| {
+                transfer_encoding::decode(trim(value), TransferEncoding::Base64).ok()
+            })
+        } else {
+            None
+        };
+        Md5Check {
+            inner: inner,
+            digest: expected.map(|expected| (md5::Md5::new(), expected)),
+        }
+    }
+
+    #[cfg(not(feature = "md5"))]
+    fn new(inner: &'a mut W, _headers: &Headers, _options: &ParseOptions) -> Md5Check<'a, W> {
+        Md5Check { inner: inner }
+    }
+
+    // Check the digest of the whole body
+    #[cfg(feature = "md5")]
+    fn finish(self) -> Result<(), Error> {
+        use md5::Digest;
+
+        if let Some((digest, expected)) = self.digest {
+            if digest.finalize()[..] != expected[..] {
+                return Err(Error::ContentMd5Mismatch);
+            }
         }
+        Ok(())
+    }
 
-        let is_file = always_use_files || {
-            let cd: Option<&ContentDisposition> = part_headers.get();
-            if cd.is_some() {
-                if cd.unwrap().disposition == DispositionType::Attachment {
-                    true
-                } else {
-                    cd.unwrap().parameters.iter().any(|x| match x {
-                        &DispositionParam::Filename(_,_,_) => true,
-                        _ => false
-                    })
-                }
-            } else {
-                false
+    #[cfg(not(feature = "md5"))]
+    fn finish(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl<'a, W: Write> Write for Md5Check<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> ::std::io::Result<usize> {
+        let count = try!(self.inner.write(buf));
+        #[cfg(feature = "md5")]
+        {
+            use md5::Digest;
+            if let Some((ref mut digest, _)) = self.digest {
+                digest.update(&buf[..count]);
             }
-        };
-        if is_file {
-            // Setup a file to capture the contents.
-            let mut filepart = try!(FilePart::create(part_headers));
-            let mut file = try!(File::create(filepart.path.clone()));
+        }
+        Ok(count)
+    }
 
-            // Stream out the file.
-            let (read, found) = try!(reader.stream_until_token(&lt_boundary, &mut file));
-            if ! found { return Err(Error::EofInFile); }
-            filepart.size = Some(read);
+    fn flush(&mut self) -> ::std::io::Result<()> {
+        self.inner.flush()
+    }
+}
 
-            // TODO: Handle Content-Transfer-Encoding.  RFC 7578 section 4.7 deprecated
-            // this, and the authors state "Currently, no deployed implementations that
-            // send such bodies have been discovered", so this is very low priority.
+/// The name of the `Content-MD5` header
+pub const CONTENT_MD5: &'static str = "Content-MD5";
+
+// A writer which buffers a part body in memory until it reaches `threshold` bytes, at
+// which point the buffered bytes are moved into a temporary file and the remainder of the
+// body is streamed there.
+struct SpillWriter<'a> {
+    threshold: usize,
+    // The headers of the part, for the storage the body spills to
+    headers: Headers,
+    buf: Vec<u8>,
+    file: Option<DiskWriter<'a>>,
+    context: &'a ParseContext<'a>,
+}
+impl<'a> SpillWriter<'a> {
+    fn new(threshold: usize, headers: &Headers, context: &'a ParseContext<'a>)
+           -> SpillWriter<'a>
+    {
+        SpillWriter {
+            threshold: threshold,
+            headers: headers.clone(),
+            buf: Vec::new(),
+            file: None,
+            context: context,
+        }
+    }
 
-            nodes.push(Node::File(filepart));
-        } else {
-            buf.truncate(0); // start fresh
-            let (_, found) = try!(reader.stream_until_token(&lt_boundary, &mut buf));
-            if ! found { return Err(Error::EofInPart); }
+    fn spilled(&self) -> bool {
+        self.file.is_some()
+    }
+
+    fn over_quota(&self) -> bool {
+        self.file.as_ref().map_or(false, |file| file.over_quota)
+    }
+
+    fn spill(&mut self) -> ::std::io::Result<()> {
+        let mut file = match DiskWriter::create(&self.headers, self.context) {
+            Ok(file) => file,
+            Err(Error::Io(err)) => return Err(err),
+            Err(err) => return Err(::std::io::Error::new(::std::io::ErrorKind::Other,
+                                                         format!("{}", err))),
+        };
+        try!(file.write_all(&self.buf));
+        self.buf = Vec::new();
+        self.file = Some(file);
+        Ok(())
+    }
+
+    // Finish the part, producing a `Node::File` if it was spilled or a `Node::Part` if not.
+    fn into_node(mut self, headers: Headers, raw_headers: Vec<u8>, size: usize)
+                 -> Result<Node, Error>
+    {
+        if ! self.spilled() && self.buf.len() >= self.threshold {
+            try!(self.spill());
+        }
+        match self.file {
+            Some(file) => {
+                let mut filepart = try!(file.finalize(headers));
+                filepart.raw_headers = Some(raw_headers);
+                filepart.size = Some(size);
+                Ok(Node::File(filepart))
+            },
+            None => Ok(Node::Part(Part {
+                headers: headers,
+                body: Bytes::from(self.buf),
+                raw_headers: Some(raw_headers),
+            })),
+        }
+    }
+}
+impl<'a> Write for SpillWriter<'a> {
+    fn write(&mut self, data: &[u8]) -> ::std::io::Result<usize> {
+        if ! self.spilled() && self.buf.len() + data.len() >= self.threshold {
+            try!(self.spill());
+        }
+        match self.file {
+            Some(ref mut file) => file.write(data),
+            None => self.buf.write(data),
+        }
+    }
 
-            nodes.push(Node::Part(Part {
-                headers: part_headers,
-                body: buf.clone(),
-            }));
+    fn flush(&mut self) -> ::std::io::Result<()> {
+        match self.file {
+            Some(ref mut file) => file.flush(),
+            None => Ok(()),
         }
     }
 }
 
-/// Get the `multipart/*` boundary string from `hyper::Headers`
-pub fn get_multipart_boundary(headers: &Headers) -> Result<Vec<u8>, Error> {
+/// Get the `multipart/*` boundary string from `hyper::Headers`, or other `PartHeaders`
+pub fn get_multipart_boundary<H: PartHeaders + ?Sized>(headers: &H) -> Result<Vec<u8>, Error> {
     // Verify that the request is 'Content-Type: multipart/*'.
-    let ct: &ContentType = match headers.get() {
-        Some(ct) => ct,
+    let mime = match headers.content_type() {
+        Some(mime) => mime,
+        None if headers.raw_value("Content-Type").is_some() => {
+            return Err(Error::MalformedHeader("Content-Type".to_owned()));
+        },
         None => return Err(Error::NoRequestContentType),
     };
-    let &ContentType(ref mime) = ct;
 
     if mime.type_() != ::mime::MULTIPART {
         return Err(Error::NotMultipart);
@@ -360,25 +2672,35 @@ pub fn get_multipart_boundary(headers: &Headers) -> Result<Vec<u8>, Error> {
     Err(Error::BoundaryNotSpecified)
 }
 
-#[inline]
-fn get_content_disposition_filename(cd: &ContentDisposition) -> Result<Option<String>, Error> {
-    if let Some(&DispositionParam::Filename(ref charset, _, ref bytes)) =
-        cd.parameters.iter().find(|&x| match *x {
-            DispositionParam::Filename(_,_,_) => true,
-            _ => false,
-        })
-    {
-        match charset_decode(charset, bytes) {
-            Ok(filename) => Ok(Some(filename)),
-            Err(e) => Err(Error::Decoding(e)),
-        }
+/// The content type of a part without a `Content-Type` header within a multipart of the
+/// given type, per RFC 2046: `message/rfc822` within a `multipart/digest`, and
+/// `text/plain; charset=us-ascii` otherwise.
+pub fn default_content_type(multipart: &Mime) -> Mime {
+    if multipart.subtype() == "digest" {
+        "message/rfc822".parse().unwrap()
     } else {
-        Ok(None)
+        "text/plain; charset=us-ascii".parse().unwrap()
     }
 }
 
-// This decodes bytes encoded according to a hyper::header::Charset encoding, using the
+/// Check that a boundary (without the leading `--`) conforms to RFC 2046: 1 to 70
+/// characters from the permitted set, not ending with a space.
+pub fn validate_boundary(boundary: &[u8]) -> Result<(), Error> {
+    if boundary.is_empty() || boundary.len() > 70 || boundary.ends_with(b" ") {
+        return Err(Error::InvalidBoundary);
+    }
+    let valid = boundary.iter().all(|&b| match b {
+        b'0'..=b'9' | b'a'..=b'z' | b'A'..=b'Z' => true,
+        b'\'' | b'(' | b')' | b'+' | b'_' | b',' | b'-' | b'.' | b'/' | b':' | b'=' | b'?'
+            | b' ' => true,
+        _ => false,
+    });
+    if valid { Ok(()) } else { Err(Error::InvalidBoundary) }
+}
+
+// This decodes bytes encoded according to a header::Charset encoding, using the
 // rust-encoding crate.  Only supports encodings defined in both crates.
+#[cfg(feature = "encoding")]
 fn charset_decode(charset: &Charset, bytes: &[u8]) -> Result<String, Cow<'static, str>> {
     Ok(match *charset {
         Charset::Us_Ascii => try!(all::ASCII.decode(bytes, DecoderTrap::Strict)),
@@ -412,15 +2734,87 @@ fn charset_decode(charset: &Charset, bytes: &[u8]) -> Result<String, Cow<'static
     })
 }
 
+// Without the encoding feature, only UTF-8, US-ASCII and ISO-8859-1 (whose bytes are the
+// first 256 code points) are decoded.
+#[cfg(not(feature = "encoding"))]
+fn charset_decode(charset: &Charset, bytes: &[u8]) -> Result<String, Cow<'static, str>> {
+    match *charset {
+        Charset::Us_Ascii if bytes.is_ascii() => {
+            Ok(String::from_utf8(bytes.to_vec()).unwrap())
+        },
+        Charset::Us_Ascii => Err("Invalid US-ASCII".into()),
+        Charset::Iso_8859_1 => Ok(bytes.iter().map(|&b| b as char).collect()),
+        Charset::Ext(ref s) if s.eq_ignore_ascii_case("UTF-8") => {
+            String::from_utf8(bytes.to_vec()).map_err(|_| "Invalid UTF-8".into())
+        },
+        ref charset => Err(format!("{} is not supported without the encoding feature",
+                                   charset).into()),
+    }
+}
+
 /// Generate a valid multipart boundary, statistically unlikely to be found within
 /// the content of the parts.
 pub fn generate_boundary() -> Vec<u8> {
     TextNonce::sized(68).unwrap().into_string().into_bytes()
 }
 
+/// How a boundary is made by `generate_boundary_with()`
+#[derive(Clone, Debug, PartialEq)]
+pub struct BoundaryOptions {
+    /// Fixed text to begin the boundary with, as some servers require (such as
+    /// `----WebKitFormBoundary`).  Defaults to none.
+    pub prefix: String,
+    /// The number of characters chosen at random to follow the prefix.  Defaults to 32.
+    pub length: usize,
+    /// The characters to choose from, each equally likely.  Defaults to the ASCII letters
+    /// and digits.
+    pub alphabet: Vec<u8>,
+    /// If set, the random characters are drawn from a generator seeded with this, so that
+    /// the same boundary is made every time, e.g. for comparing output against a known
+    /// body in tests.  Defaults to `None`, drawing from the thread's generator.
+    pub seed: Option<u64>,
+}
+impl Default for BoundaryOptions {
+    fn default() -> BoundaryOptions {
+        BoundaryOptions {
+            prefix: String::new(),
+            length: 32,
+            alphabet: b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789".to_vec(),
+            seed: None,
+        }
+    }
+}
+
+/// Generate a multipart boundary as the `BoundaryOptions` given describe.  Fails with
+/// `Error::InvalidBoundary` if the alphabet is empty, or the boundary made would not
+/// conform to RFC 2046, as checked by `validate_boundary()` (which limits the prefix and
+/// random characters to 70 in all).
+pub fn generate_boundary_with(options: &BoundaryOptions) -> Result<Vec<u8>, Error> {
+    use rand::{Rng, RngCore, SeedableRng};
+    use rand::rngs::StdRng;
+
+    if options.alphabet.is_empty() {
+        return Err(Error::InvalidBoundary);
+    }
+    let mut rng: Box<dyn RngCore> = match options.seed {
+        Some(seed) => Box::new(StdRng::seed_from_u64(seed)),
+        None => Box::new(rand::thread_rng()),
+    };
+    let mut boundary = options.prefix.clone().into_bytes();
+    for _ in 0..options.length {
+        boundary.push(options.alphabet[rng.gen_range(0, options.alphabet.len())]);
+    }
+    try!(validate_boundary(&boundary));
+    Ok(boundary)
+}
+
 // Convenience method, like write_all(), but returns the count of bytes written.
 trait WriteAllCount {
     fn write_all_count(&mut self, buf: &[u8]) -> ::std::io::Result<usize>;
+
+    // Write all of the buffers given, in as few calls to write_vectored() as the stream
+    // allows, and return the count of bytes written.
+    fn write_all_vectored_count(&mut self, bufs: &[&[u8]]) -> ::std::io::Result<usize>;
 }
 impl<T: Write> WriteAllCount for T {
     fn write_all_count(&mut self, buf: &[u8]) -> ::std::io::Result<usize>
@@ -428,6 +2822,109 @@ impl<T: Write> WriteAllCount for T {
         try!(self.write_all(buf));
         Ok(buf.len())
     }
+
+    fn write_all_vectored_count(&mut self, bufs: &[&[u8]]) -> ::std::io::Result<usize>
+    {
+        use std::io::{ErrorKind, IoSlice};
+
+        let mut slices: Vec<IoSlice> = bufs.iter().map(|buf| IoSlice::new(buf)).collect();
+        let mut slices = &mut slices[..];
+        // Skip any empty buffers at the start, which would be taken as the end
+        IoSlice::advance_slices(&mut slices, 0);
+        while ! slices.is_empty() {
+            match self.write_vectored(slices) {
+                Ok(0) => return Err(::std::io::Error::new(ErrorKind::WriteZero,
+                                                          "failed to write whole buffer")),
+                Ok(count) => IoSlice::advance_slices(&mut slices, count),
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {},
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(bufs.iter().map(|buf| buf.len()).sum())
+    }
+}
+
+/// Options controlling how a `multipart/*` body is written
+#[derive(Clone, Debug)]
+pub struct WriteOptions {
+    /// How part bodies are given a `Content-Transfer-Encoding`.  Defaults to
+    /// `EncodingPolicy::Verbatim`.
+    pub transfer_encoding: EncodingPolicy,
+    /// Whether to give each part (but not nested multiparts) a `Content-Length` header with
+    /// the length of its body as written, after any transfer encoding, as some receivers
+    /// require.  Defaults to `false`.
+    pub part_content_length: bool,
+    /// If set, the body of each part (but not nested multiparts) without a
+    /// `Content-Encoding` is compressed with this coding as it is written, and given a
+    /// `Content-Encoding` header naming it.  Any `Content-Transfer-Encoding` applies to the
+    /// compressed body.  Defaults to `None`.
+    #[cfg(feature = "flate2")]
+    pub content_encoding: Option<ContentCoding>,
+    /// The size of the chunks written by `write_multipart_chunked_with_options()`, but for
+    /// the last.  Defaults to 16 KiB.
+    pub chunk_size: usize,
+    /// Whether to write the headers of each part and nested multipart in order of their
+    /// names, compared without regard to case, rather than in the order they were set.
+    /// Headers added in writing, such as `Content-Length`, then fall into place too, so that
+    /// the output does not depend on how the nodes were built.  Defaults to `false`.
+    pub sort_headers: bool,
+    /// If set, each part (but not nested multiparts) without the header this names is given
+    /// it, with the digest of its body as written (after any compression, but before any
+    /// transfer encoding, as for `Content-MD5` under RFC 1864), for receivers which require
+    /// per-part integrity headers.  Defaults to `None`.
+    pub part_digest: Option<PartDigest>,
+}
+impl Default for WriteOptions {
+    fn default() -> WriteOptions {
+        WriteOptions {
+            transfer_encoding: EncodingPolicy::Verbatim,
+            part_content_length: false,
+            #[cfg(feature = "flate2")]
+            content_encoding: None,
+            chunk_size: 16384,
+            sort_headers: false,
+            part_digest: None,
+        }
+    }
+}
+
+/// A digest header given to each part under `WriteOptions::part_digest`
+#[derive(Clone, Debug)]
+pub struct PartDigest {
+    /// The name of the header, such as `Content-MD5`
+    pub name: Cow<'static, str>,
+    /// Compute the value of the header from the body of a part, which it reads to the end
+    pub digest: fn(&mut dyn Read) -> ::std::io::Result<String>,
+}
+impl PartDigest {
+    /// A digest header with this name, whose value is computed by `digest`
+    pub fn new<N>(name: N, digest: fn(&mut dyn Read) -> ::std::io::Result<String>)
+                  -> PartDigest
+        where N: Into<Cow<'static, str>>
+    {
+        PartDigest {
+            name: name.into(),
+            digest: digest,
+        }
+    }
+
+    /// A `Content-MD5` header (RFC 1864): the base64 encoded MD5 digest of the body, as
+    /// checked under `ParseOptions::verify_content_md5`
+    #[cfg(feature = "md5")]
+    pub fn content_md5() -> PartDigest {
+        PartDigest::new(CONTENT_MD5, content_md5)
+    }
+}
+
+// The value of a Content-MD5 header for a body
+#[cfg(feature = "md5")]
+fn content_md5(body: &mut dyn Read) -> ::std::io::Result<String> {
+    use md5::Digest;
+
+    let mut digest = md5::Md5::new();
+    try!(::std::io::copy(body, &mut digest));
+    let encoded = try!(transfer_encoding::encode(&digest.finalize(), TransferEncoding::Base64));
+    Ok(String::from_utf8_lossy(trim(&encoded)).into_owned())
 }
 
 /// Stream a multipart body to the output `stream` given, made up of the `parts`
@@ -439,79 +2936,420 @@ pub fn write_multipart<S: Write>(
     boundary: &Vec<u8>,
     nodes: &Vec<Node>)
     -> Result<usize, Error>
+{
+    write_multipart_with_options(stream, boundary, nodes, &WriteOptions::default())
+}
+
+/// Stream a complete multipart entity to the output `stream` given: the top-level `headers`,
+/// a blank line, and the body made up of the `nodes` given, as `write_multipart()` writes
+/// it.  The `Content-Type` written carries `boundary`: it is that of `headers` if that is a
+/// `multipart/*` type, with any boundary parameter replaced, and `multipart/mixed`
+/// otherwise.
+/// Returns the number of bytes written, or an error.
+pub fn write_multipart_with_headers<S: Write, H: PartHeaders + ?Sized>(
+    stream: &mut S,
+    headers: &H,
+    boundary: &Vec<u8>,
+    nodes: &Vec<Node>)
+    -> Result<usize, Error>
+{
+    let content_type = match headers.content_type() {
+        Some(ref mime) if mime.type_() == mime::MULTIPART => mime.clone(),
+        _ => "multipart/mixed".parse().unwrap(),
+    };
+    let mut headers = headers.to_headers().into_owned();
+    headers.set(ContentType(try!(with_boundary(&content_type, boundary))));
+
+    let mut count: usize = 0;
+    count += try!(write_headers(stream, &headers, &WriteOptions::default()));
+    count += try!(write_multipart(stream, boundary, nodes));
+    Ok(count)
+}
+
+// A `multipart/*` type with its boundary parameter set to `boundary`, keeping any other
+// parameters
+fn with_boundary(content_type: &Mime, boundary: &[u8]) -> Result<Mime, Error> {
+    let mut text = format!("{}/{}", content_type.type_(), content_type.subtype());
+    for (attr, value) in content_type.params() {
+        if attr != mime::BOUNDARY {
+            text.push_str(&format!("; {}=\"{}\"", attr, value));
+        }
+    }
+    text.push_str(&format!("; boundary=\"{}\"", String::from_utf8_lossy(boundary)));
+    text.parse().map_err(|_| Error::InvalidBoundary)
+}
+
+/// Stream a multipart body to the output `stream` given, as `write_multipart()` does, but
+/// under the control of the `WriteOptions` given.
+/// Returns the number of bytes written, or an error.
+///
+/// With the `zero-copy` feature, the content of a `Node::File` written as it is (without a
+/// transfer encoding or compression) is copied on Linux by the kernel, with
+/// `copy_file_range` or `sendfile`, when `stream` is a `File`, `TcpStream` or `UnixStream`.
+pub fn write_multipart_with_options<S: Write>(
+    stream: &mut S,
+    boundary: &Vec<u8>,
+    nodes: &Vec<Node>,
+    options: &WriteOptions)
+    -> Result<usize, Error>
+{
+    let mut count: usize = 0;
+
+    for (i, node) in nodes.iter().enumerate() {
+        // write a boundary, with the line terminator ending the part before it
+        let start: &[u8] = if i == 0 { b"--" } else { b"\r\n--" };
+        count += try!(stream.write_all_vectored_count(&[start, &boundary, b"\r\n"]));
+
+        count += try!(write_entity(stream, node, options));
+    }
+
+    // write a final boundary, with the line terminator ending the last part
+    let start: &[u8] = if nodes.is_empty() { b"--" } else { b"\r\n--" };
+    count += try!(stream.write_all_vectored_count(&[start, &boundary, b"--"]));
+
+    Ok(count)
+}
+
+// Write a node as an entity: its headers, a blank line and its content.  Returns the number
+// of bytes written.
+fn write_entity<S: Write>(stream: &mut S, node: &Node, options: &WriteOptions)
+                          -> Result<usize, Error>
 {
     let mut count: usize = 0;
 
+    match node {
+        &Node::Part(ref part) => {
+            let body: &[u8] = &part.body;
+            count += try!(write_part(stream, &part.headers,
+                                     Box::new(move || Ok(Box::new(body) as Box<dyn Read>)),
+                                     Some(body.len() as u64), None, options));
+        },
+        &Node::File(ref filepart) => {
+            let path = &filepart.path;
+            let open = move || Ok(Box::new(try!(File::open(path))) as Box<dyn Read>);
+            let size = try!(::std::fs::metadata(path)).len();
+            count += try!(write_part(stream, &filepart.headers, Box::new(open), Some(size),
+                                     Some(path), options));
+        },
+        &Node::Multipart((ref headers, ref subnodes)) => {
+            // Get boundary, without the leading "--" which is written before it
+            let boundary = try!(get_multipart_boundary(headers))[2..].to_vec();
+
+            // write the headers and the blank line
+            count += try!(write_headers(stream, headers, options));
+
+            // Recurse
+            count += try!(write_multipart_with_options(stream, &boundary, &subnodes, options));
+        },
+        &Node::Message((ref headers, ref message)) => {
+            // write the headers and the blank line
+            count += try!(write_headers(stream, headers, options));
+
+            // Write the message, headers and all
+            count += try!(write_entity(stream, message, options));
+        },
+        &Node::Invalid(ref invalid) => {
+            // Write the part as it was read
+            count += try!(stream.write_all_count(&invalid.raw));
+        },
+    }
+
+    Ok(count)
+}
+
+// Write a header block, including the blank line ending it, sorted by name under
+// `WriteOptions::sort_headers`.  Fails before writing anything if a header name or value
+// contains a CR or LF.  Returns the number of bytes written.
+fn write_headers<S: Write>(stream: &mut S, headers: &Headers, options: &WriteOptions)
+                           -> Result<usize, Error>
+{
+    try!(check_headers(headers));
+    let mut views: Vec<_> = headers.iter().collect();
+    if options.sort_headers {
+        views.sort_by_key(|header| header.name().to_ascii_lowercase());
+    }
+    // The header lines and the blank line are written together
+    let values: Vec<String> = views.iter().map(|header| header.value_string()).collect();
+    let mut bufs: Vec<&[u8]> = Vec::with_capacity(views.len() * 4 + 1);
+    for (header, value) in views.iter().zip(values.iter()) {
+        bufs.push(header.name().as_bytes());
+        bufs.push(b": ");
+        bufs.push(value.as_bytes());
+        bufs.push(b"\r\n");
+    }
+    bufs.push(b"\r\n");
+    Ok(try!(stream.write_all_vectored_count(&bufs)))
+}
+
+// Check that no header name or value contains a CR or LF, which would let the rest of it
+// be read as further headers, or as a boundary.  Values are checked as set, since hyper
+// quietly turns line breaks into spaces when formatting them.
+fn check_headers(headers: &Headers) -> Result<(), Error> {
+    let breaks = |text: &[u8]| text.iter().any(|&b| b == b'\r' || b == b'\n');
+    for header in headers.iter() {
+        if breaks(header.name().as_bytes()) || header.raw().iter().any(|line| breaks(line)) {
+            return Err(Error::InvalidHeader(header.name().to_owned()));
+        }
+    }
+    Ok(())
+}
+
+/// The number of bytes `write_multipart()` writes for the `nodes` given, found without
+/// writing them, so that a `Content-Length` may be set before the body is sent.  The sizes
+/// of files are taken from their metadata, rather than by reading them.
+pub fn multipart_len(boundary: &[u8], nodes: &[Node]) -> Result<u64, Error> {
+    let mut len: u64 = 0;
     for node in nodes {
-        // write a boundary
-        count += try!(stream.write_all_count(b"--"));
-        count += try!(stream.write_all_count(&boundary));
-        count += try!(stream.write_all_count(b"\r\n"));
-
-        match node {
-            &Node::Part(ref part) => {
-                // write the part's headers
-                for header in part.headers.iter() {
-                    count += try!(stream.write_all_count(header.name().as_bytes()));
-                    count += try!(stream.write_all_count(b": "));
-                    count += try!(stream.write_all_count(header.value_string().as_bytes()));
-                    count += try!(stream.write_all_count(b"\r\n"));
-                }
+        // boundary, entity and line terminator
+        len += 2 + boundary.len() as u64 + 2;
+        len += try!(entity_len(node));
+        len += 2;
+    }
+    // final boundary
+    len += 2 + boundary.len() as u64 + 2;
+    Ok(len)
+}
 
-                // write the blank line
-                count += try!(stream.write_all_count(b"\r\n"));
+// The number of bytes `write_entity()` writes for a node, without any transfer encoding
+fn entity_len(node: &Node) -> Result<u64, Error> {
+    Ok(match *node {
+        // Written as it was read, headers and all
+        Node::Invalid(ref invalid) => invalid.raw.len() as u64,
+        _ => headers_len(node.headers()) + try!(node.body_len()),
+    })
+}
 
-                // Write the part's content
-                count += try!(stream.write_all_count(&part.body));
-            },
-            &Node::File(ref filepart) => {
-                // write the part's headers
-                for header in filepart.headers.iter() {
-                    count += try!(stream.write_all_count(header.name().as_bytes()));
-                    count += try!(stream.write_all_count(b": "));
-                    count += try!(stream.write_all_count(header.value_string().as_bytes()));
-                    count += try!(stream.write_all_count(b"\r\n"));
-                }
+// The number of bytes written for a header block, including the blank line ending it
+fn headers_len(headers: &Headers) -> u64 {
+    headers.iter().map(|header| {
+        (header.name().len() + 2 + header.value_string().len() + 2) as u64
+    }).sum::<u64>() + 2
+}
 
-                // write the blank line
-                count += try!(stream.write_all_count(b"\r\n"));
+// Opens the body of a part to be read from the start, as many times as is needed
+type BodySource<'a> = Box<dyn Fn() -> Result<Box<dyn Read + 'a>, Error> + 'a>;
 
-                // Write out the files's content
-                let mut file = try!(File::open(&filepart.path));
-                count += try!(::std::io::copy(&mut file, stream)) as usize;
-            },
-            &Node::Multipart((ref headers, ref subnodes)) => {
-                // Get boundary
-                let boundary = try!(get_multipart_boundary(headers));
+// Write a part: its headers, a blank line and its body, which is `size` bytes if known.
+// Returns the number of bytes written.
+fn write_part<'a, S: Write>(stream: &mut S, headers: &'a Headers, open: BodySource<'a>,
+                            size: Option<u64>, file: Option<&Path>, options: &WriteOptions)
+                            -> Result<usize, Error>
+{
+    let mut count: usize = 0;
 
-                // write the multipart headers
-                for header in headers.iter() {
-                    count += try!(stream.write_all_count(header.name().as_bytes()));
-                    count += try!(stream.write_all_count(b": "));
-                    count += try!(stream.write_all_count(header.value_string().as_bytes()));
-                    count += try!(stream.write_all_count(b"\r\n"));
-                }
+    let (headers, open, size) = compress_part(Cow::Borrowed(headers), open, size, options);
+    // The size of a body is only lost when it is compressed, and the file no longer what
+    // is written
+    let file = if size.is_some() { file } else { None };
+    let encoding = match options.transfer_encoding {
+        EncodingPolicy::Auto => {
+            try!(encoding_for(&headers, EncodingPolicy::Auto, &mut try!(open())))
+        },
+        policy => try!(encoding_for(&headers, policy, &mut ::std::io::empty())),
+    };
+    let mut headers = with_transfer_encoding(&headers, encoding);
+    if options.part_content_length {
+        let length = match (encoding, size) {
+            (None, Some(size)) => size,
+            _ => try!(write_body(&mut ::std::io::sink(), &mut try!(open()), encoding)) as u64,
+        };
+        headers.to_mut().set(ContentLength(length));
+    }
+    if let Some(ref part_digest) = options.part_digest {
+        if headers.get_raw(&part_digest.name).is_none() {
+            let value = try!((part_digest.digest)(&mut try!(open())));
+            headers.to_mut().set_raw(part_digest.name.clone(), value);
+        }
+    }
 
-                // write the blank line
-                count += try!(stream.write_all_count(b"\r\n"));
+    // write the part's headers and the blank line
+    count += try!(write_headers(stream, &headers, options));
 
-                // Recurse
-                count += try!(write_multipart(stream, &boundary, &subnodes));
-            },
+    // Write the part's content
+    count += try!(match file {
+        Some(path) if encoding.is_none() && cfg!(feature = "zero-copy") => {
+            copy_file(stream, path)
+        },
+        _ => write_body(stream, &mut try!(open()), encoding),
+    });
+
+    Ok(count)
+}
+
+// Copy the content of a file to the stream.  As the file is copied as a `File`, rather than
+// through a boxed reader, `io::copy` can have the kernel copy it on Linux (with
+// `copy_file_range` or `sendfile`) when the stream is a file or socket, and otherwise copies
+// it as usual.  Returns the number of bytes written.
+fn copy_file<S: Write>(stream: &mut S, path: &Path) -> Result<usize, Error> {
+    let mut file = try!(File::open(path));
+    Ok(try!(::std::io::copy(&mut file, stream)) as usize)
+}
+
+// Compress a part body under `WriteOptions::content_encoding`, unless it has a
+// `Content-Encoding` already, returning the headers to write, the compressed body and its
+// size if still known
+#[cfg(feature = "flate2")]
+fn compress_part<'a>(headers: Cow<'a, Headers>, open: BodySource<'a>, size: Option<u64>,
+                     options: &WriteOptions)
+                     -> (Cow<'a, Headers>, BodySource<'a>, Option<u64>)
+{
+    let coding = match options.content_encoding {
+        Some(coding) if headers.get_raw(CONTENT_ENCODING).is_none() => coding,
+        _ => return (headers, open, size),
+    };
+    let mut headers = headers.into_owned();
+    headers.set_raw(CONTENT_ENCODING, coding.as_str());
+    let open = move || open().map(|body| compression::encoder(body, coding));
+    (Cow::Owned(headers), Box::new(open), None)
+}
+#[cfg(not(feature = "flate2"))]
+fn compress_part<'a>(headers: Cow<'a, Headers>, open: BodySource<'a>, size: Option<u64>,
+                     _options: &WriteOptions)
+                     -> (Cow<'a, Headers>, BodySource<'a>, Option<u64>)
+{
+    (headers, open, size)
+}
+
+/// Stream a multipart body to the output `stream` given, made up of the preamble, nodes and
+/// epilogue of the `MultipartBody` given, under the control of the `WriteOptions` given.  An
+/// empty preamble or epilogue is omitted.  Top-level headers are NOT included in this stream.
+/// Returns the number of bytes written, or an error.
+///
+/// If the body has its `framing` (as parsed under `ParseOptions::keep_framing`), with an
+/// entry for each node, it is written exactly as it was read instead: with the boundaries
+/// it was read with rather than `boundary`, and the bodies of parts as they are, whatever
+/// the options.  Nodes whose entry in `Framing::headers` is empty are written as usual.
+pub fn write_multipart_full<S: Write>(
+    stream: &mut S,
+    boundary: &Vec<u8>,
+    body: &MultipartBody,
+    options: &WriteOptions)
+    -> Result<usize, Error>
+{
+    let mut count: usize = 0;
+
+    if let Some(ref framing) = body.framing {
+        if framing.fits(&body.nodes) {
+            count += try!(stream.write_all_count(&body.preamble));
+            count += try!(write_framed(stream, framing, &body.nodes, options));
+            count += try!(stream.write_all_count(&body.epilogue));
+            return Ok(count);
         }
+    }
+
+    if ! body.preamble.is_empty() {
+        count += try!(stream.write_all_vectored_count(&[&body.preamble, b"\r\n"]));
+    }
 
-        // write a line terminator
-        count += try!(stream.write_all_count(b"\r\n"));
+    count += try!(write_multipart_with_options(stream, boundary, &body.nodes, options));
+
+    if ! body.epilogue.is_empty() {
+        count += try!(stream.write_all_vectored_count(&[b"\r\n", &body.epilogue]));
     }
 
-    // write a final boundary
-    count += try!(stream.write_all_count(b"--"));
-    count += try!(stream.write_all_count(&boundary));
-    count += try!(stream.write_all_count(b"--"));
+    Ok(count)
+}
 
+// Write the nodes exactly as they were read, from their framing, which fits them
+fn write_framed<S: Write>(stream: &mut S, framing: &Framing, nodes: &[Node],
+                          options: &WriteOptions)
+                          -> Result<usize, Error>
+{
+    let mut count: usize = 0;
+    for (i, node) in nodes.iter().enumerate() {
+        count += try!(stream.write_all_count(&framing.delimiters[i]));
+        if framing.headers[i].is_empty() {
+            count += try!(write_entity(stream, node, options));
+        } else {
+            count += try!(stream.write_all_count(&framing.headers[i]));
+            count += try!(write_framed_content(stream, node, framing.nested[i].as_ref(),
+                                               options));
+        }
+    }
+    count += try!(stream.write_all_count(&framing.close));
     Ok(count)
 }
 
+// Write what follows the headers of a node exactly as it was read: its body, or the preamble,
+// nodes and epilogue of a nested multipart as kept in `nested`
+fn write_framed_content<S: Write>(stream: &mut S, node: &Node, nested: Option<&MultipartBody>,
+                                  options: &WriteOptions)
+                                  -> Result<usize, Error>
+{
+    match *node {
+        Node::Part(ref part) => Ok(try!(stream.write_all_count(&part.body))),
+        Node::File(ref filepart) => copy_file(stream, &filepart.path),
+        Node::Multipart((ref headers, ref subnodes)) => {
+            match nested.and_then(|nested| nested.framing.as_ref().map(|f| (nested, f))) {
+                Some((nested, framing)) if framing.fits(subnodes) => {
+                    let mut count = try!(stream.write_all_count(&nested.preamble));
+                    count += try!(write_framed(stream, framing, subnodes, options));
+                    count += try!(stream.write_all_count(&nested.epilogue));
+                    Ok(count)
+                },
+                _ => {
+                    let boundary = try!(get_multipart_boundary(headers))[2..].to_vec();
+                    write_multipart_with_options(stream, &boundary, subnodes, options)
+                },
+            }
+        },
+        Node::Message((_, ref message)) => write_framed_content(stream, message, nested, options),
+        Node::Invalid(ref invalid) => Ok(try!(stream.write_all_count(&invalid.raw))),
+    }
+}
+
+// The Content-Transfer-Encoding to apply to a part body being written, if any.  The `body`
+// is only read under `EncodingPolicy::Auto`.
+fn encoding_for<R: Read>(headers: &Headers, policy: EncodingPolicy, body: &mut R)
+                         -> Result<Option<TransferEncoding>, Error>
+{
+    if headers.get_raw(CONTENT_TRANSFER_ENCODING).is_some() {
+        return Ok(None);
+    }
+    let encoding = match policy {
+        EncodingPolicy::Verbatim => return Ok(None),
+        EncodingPolicy::Always(encoding) => encoding,
+        EncodingPolicy::Auto => {
+            let mut classifier = Classifier::new();
+            try!(::std::io::copy(body, &mut classifier));
+            classifier.choose()
+        },
+    };
+    Ok(if encoding.is_encoded() { Some(encoding) } else { None })
+}
+
+// The headers to write for a part, with the Content-Transfer-Encoding header set if the
+// body is being encoded.
+fn with_transfer_encoding<'a>(headers: &'a Headers, encoding: Option<TransferEncoding>)
+                              -> Cow<'a, Headers>
+{
+    match encoding {
+        Some(encoding) => {
+            let mut headers = headers.clone();
+            headers.set_raw(CONTENT_TRANSFER_ENCODING, encoding.as_str());
+            Cow::Owned(headers)
+        },
+        None => Cow::Borrowed(headers),
+    }
+}
+
+// Copy a part body to the stream, encoding it if so required.  Returns the number of
+// bytes written.
+fn write_body<S: Write, R: Read>(stream: &mut S, body: &mut R,
+                                 encoding: Option<TransferEncoding>)
+                                 -> Result<usize, Error>
+{
+    match encoding {
+        Some(encoding) => {
+            let mut encoder = Encoder::new(stream, encoding);
+            try!(::std::io::copy(body, &mut encoder));
+            let (_, written) = try!(encoder.finish());
+            Ok(written)
+        },
+        None => Ok(try!(::std::io::copy(body, stream)) as usize),
+    }
+}
+
 pub fn write_chunk<S: Write>(
     stream: &mut S,
     chunk: &[u8]) -> Result<(), ::std::io::Error>
@@ -525,86 +3363,66 @@ pub fn write_chunk<S: Write>(
 /// Stream a multipart body to the output `stream` given, made up of the `parts`
 /// given, using Tranfer-Encoding: Chunked.  Top-level headers are NOT included in this
 /// stream; the caller must send those prior to calling write_multipart_chunked().
+///
+/// The body is written in chunks of 16 KiB, but for the last.
 pub fn write_multipart_chunked<S: Write>(
     stream: &mut S,
     boundary: &Vec<u8>,
     nodes: &Vec<Node>)
     -> Result<(), Error>
 {
-    for node in nodes {
-        // write a boundary
-        try!(write_chunk(stream, b"--"));
-        try!(write_chunk(stream, &boundary));
-        try!(write_chunk(stream, b"\r\n"));
-
-        match node {
-            &Node::Part(ref part) => {
-                // write the part's headers
-                for header in part.headers.iter() {
-                    try!(write_chunk(stream, header.name().as_bytes()));
-                    try!(write_chunk(stream, b": "));
-                    try!(write_chunk(stream, header.value_string().as_bytes()));
-                    try!(write_chunk(stream, b"\r\n"));
-                }
-
-                // write the blank line
-                try!(write_chunk(stream, b"\r\n"));
-
-                // Write the part's content
-                try!(write_chunk(stream, &part.body));
-            },
-            &Node::File(ref filepart) => {
-                // write the part's headers
-                for header in filepart.headers.iter() {
-                    try!(write_chunk(stream, header.name().as_bytes()));
-                    try!(write_chunk(stream, b": "));
-                    try!(write_chunk(stream, header.value_string().as_bytes()));
-                    try!(write_chunk(stream, b"\r\n"));
-                }
-
-                // write the blank line
-                try!(write_chunk(stream, b"\r\n"));
-
-                // Write out the files's length
-                let metadata = try!(::std::fs::metadata(&filepart.path));
-                try!(write!(stream, "{:x}\r\n", metadata.len()));
-
-                // Write out the file's content
-                let mut file = try!(File::open(&filepart.path));
-                try!(::std::io::copy(&mut file, stream)) as usize;
-                try!(stream.write(b"\r\n"));
-            },
-            &Node::Multipart((ref headers, ref subnodes)) => {
-                // Get boundary
-                let boundary = try!(get_multipart_boundary(headers));
-
-                // write the multipart headers
-                for header in headers.iter() {
-                    try!(write_chunk(stream, header.name().as_bytes()));
-                    try!(write_chunk(stream, b": "));
-                    try!(write_chunk(stream, header.value_string().as_bytes()));
-                    try!(write_chunk(stream, b"\r\n"));
-                }
+    write_multipart_chunked_with_options(stream, boundary, nodes, &WriteOptions::default())
+}
 
-                // write the blank line
-                try!(write_chunk(stream, b"\r\n"));
+/// Stream a multipart body to the output `stream` given, using Transfer-Encoding: Chunked,
+/// as `write_multipart_chunked()` does, but under the control of the `WriteOptions` given,
+/// which set the size of the chunks.
+pub fn write_multipart_chunked_with_options<S: Write>(
+    stream: &mut S,
+    boundary: &Vec<u8>,
+    nodes: &Vec<Node>,
+    options: &WriteOptions)
+    -> Result<(), Error>
+{
+    let mut chunks = ChunkWriter {
+        stream: stream,
+        buf: Vec::with_capacity(options.chunk_size),
+        chunk_size: ::std::cmp::max(options.chunk_size, 1),
+    };
+    try!(write_multipart_with_options(&mut chunks, boundary, nodes, options));
+    try!(chunks.finish());
+    Ok(())
+}
 
-                // Recurse
-                try!(write_multipart_chunked(stream, &boundary, &subnodes));
-            },
+// A writer which gathers what is written into chunks of `chunk_size` bytes, writing each
+// to the stream as it is filled.  The last chunk, and the empty chunk ending the body, are
+// written by `finish()`.
+struct ChunkWriter<'a, S: Write + 'a> {
+    stream: &'a mut S,
+    buf: Vec<u8>,
+    chunk_size: usize,
+}
+impl<'a, S: Write> ChunkWriter<'a, S> {
+    fn finish(self) -> ::std::io::Result<()> {
+        if ! self.buf.is_empty() {
+            try!(write_chunk(self.stream, &self.buf));
         }
-
-        // write a line terminator
-        try!(write_chunk(stream, b"\r\n"));
+        try!(write_chunk(self.stream, b""));
+        self.stream.flush()
+    }
+}
+impl<'a, S: Write> Write for ChunkWriter<'a, S> {
+    fn write(&mut self, data: &[u8]) -> ::std::io::Result<usize> {
+        let count = ::std::cmp::min(data.len(), self.chunk_size - self.buf.len());
+        self.buf.extend_from_slice(&data[..count]);
+        if self.buf.len() == self.chunk_size {
+            try!(write_chunk(self.stream, &self.buf));
+            self.buf.clear();
+        }
+        Ok(count)
     }
 
-    // write a final boundary
-    try!(write_chunk(stream, b"--"));
-    try!(write_chunk(stream, &boundary));
-    try!(write_chunk(stream, b"--"));
-
-    // Write an empty chunk to signal the end of the body
-    try!(write_chunk(stream, b""));
-
-    Ok(())
+    fn flush(&mut self) -> ::std::io::Result<()> {
+        Ok(())
+    }
 }
@@ -0,0 +1,298 @@
+// Copyright 2016 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Support for MHTML web archives (RFC 2557), as saved by browsers in `.mht` files: an email
+//! message holding a `multipart/related` of the document and its resources, which are found
+//! by their `Content-Location`
+
+use std::io::{Read, Write};
+use std::time::SystemTime;
+
+use header::{ContentType, Date, Headers};
+use mime::{self, Mime};
+
+use super::{generate_boundary, Error, HeaderFields, Node, ParseOptions, Part, Storage};
+use email::{read_email, write_email};
+use related::Related;
+use transfer_encoding::{self, TransferEncoding, CONTENT_TRANSFER_ENCODING};
+
+/// The name of the `Content-Location` header
+pub const CONTENT_LOCATION: &'static str = "Content-Location";
+
+/// The name of the `Content-Base` header
+pub const CONTENT_BASE: &'static str = "Content-Base";
+
+/// A parsed MHTML archive
+#[derive(Debug)]
+pub struct Mhtml {
+    /// The headers of the archive, such as `Subject` and `Content-Type`
+    pub headers: Headers,
+    /// The headers of the archive in their original order
+    pub fields: HeaderFields,
+    /// The document and its resources: the parts of the `multipart/related`, or the one
+    /// part of an archive which is not multipart
+    pub nodes: Vec<Node>,
+}
+
+impl Mhtml {
+    /// The root document: the part named by the `start` parameter of the `Content-Type`, or
+    /// else the first part
+    pub fn root(&self) -> Option<&Node> {
+        match Related::new(&self.headers, &self.nodes) {
+            Ok(related) => related.root,
+            Err(_) => self.nodes.first(),
+        }
+    }
+
+    /// The (URL, node) pairs of the parts with a `Content-Location`, depth-first.  Relative
+    /// locations are resolved against the `Content-Base` or `Content-Location` of the part
+    /// itself or those enclosing it, as RFC 2557 has it.
+    pub fn locations(&self) -> Vec<(String, &Node)> {
+        let mut locations: Vec<(String, &Node)> = Vec::new();
+        let base = base_of(&self.headers, None);
+        add_locations(&mut locations, &self.nodes, base.as_ref().map(|base| &base[..]));
+        locations
+    }
+
+    /// The part a URL refers to: a `cid:` URL by `Content-ID`, and any other by
+    /// `Content-Location`.  A relative URL, as found in the root document, is resolved
+    /// against the location of the root document first.
+    pub fn get(&self, url: &str) -> Option<&Node> {
+        let url = url.trim();
+        if url.as_bytes().get(..4).map_or(false, |scheme| scheme.eq_ignore_ascii_case(b"cid:")) {
+            return Related::new(&self.headers, &self.nodes).ok()
+                .and_then(|related| related.get(url));
+        }
+        let locations = self.locations();
+        let root = self.root().and_then(|root| {
+            locations.iter().find(|&&(_, node)| ::std::ptr::eq(node, root))
+                .map(|&(ref location, _)| location.clone())
+        });
+        let resolved = match root {
+            Some(ref root) => resolve(root, url),
+            None => url.to_owned(),
+        };
+        locations.iter()
+            .find(|&&(ref location, _)| *location == resolved)
+            .or_else(|| locations.iter().find(|&&(ref location, _)| location == url))
+            .map(|&(_, node)| node)
+    }
+}
+
+/// Parse an MHTML archive from a `Read`able stream, its headers first, holding every part in
+/// memory with its `Content-Transfer-Encoding` (typically `quoted-printable` for documents
+/// and `base64` for images) decoded
+pub fn read_mhtml<S: Read>(stream: &mut S) -> Result<Mhtml, Error> {
+    let mut options = ParseOptions::default();
+    options.storage = Storage::Memory;
+    options.decode_transfer_encoding = true;
+    read_mhtml_with_options(stream, &options)
+}
+
+/// Parse an MHTML archive from a `Read`able stream, as `read_mhtml()` does, but under the
+/// control of the `ParseOptions` given, as for `read_email()`
+pub fn read_mhtml_with_options<S: Read>(stream: &mut S, options: &ParseOptions)
+                                        -> Result<Mhtml, Error>
+{
+    let email = try!(read_email(stream, options));
+    let nodes = match email.body {
+        Node::Multipart((_, nodes)) => nodes,
+        node => vec![node],
+    };
+    Ok(Mhtml {
+        headers: email.headers,
+        fields: email.fields,
+        nodes: nodes,
+    })
+}
+
+/// Builds an MHTML archive of an HTML document and the resources it refers to, such as
+/// images and stylesheets, as for saving a page as a single file.
+///
+/// ```rust,ignore
+/// let archive = MhtmlBuilder::new("https://example.com/page.html", html)
+///     .add_resource("img/logo.png", mime::IMAGE_PNG, logo)
+///     .build()?;
+/// ```
+pub struct MhtmlBuilder {
+    location: String,
+    subject: Option<String>,
+    boundary: Vec<u8>,
+    nodes: Vec<Node>,
+}
+
+impl MhtmlBuilder {
+    /// Start building an archive of the HTML document given, saved from `location`
+    pub fn new(location: &str, html: Vec<u8>) -> MhtmlBuilder {
+        let root = resource_part(location, mime::TEXT_HTML, html);
+        MhtmlBuilder {
+            location: location.to_owned(),
+            subject: None,
+            boundary: generate_boundary(),
+            nodes: vec![root],
+        }
+    }
+
+    /// Give the archive a `Subject`, such as the title of the document
+    pub fn subject(mut self, subject: &str) -> MhtmlBuilder {
+        self.subject = Some(subject.to_owned());
+        self
+    }
+
+    /// Use the boundary given, rather than the one generated for the builder
+    pub fn boundary(mut self, boundary: Vec<u8>) -> MhtmlBuilder {
+        self.boundary = boundary;
+        self
+    }
+
+    /// Add a resource the document refers to by `location`, which is resolved against the
+    /// location of the document if relative, just as the reference in the document is
+    pub fn add_resource(mut self, location: &str, content_type: Mime, body: Vec<u8>)
+                        -> MhtmlBuilder
+    {
+        let location = resolve(&self.location, location);
+        self.nodes.push(resource_part(&location, content_type, body));
+        self
+    }
+
+    /// Finish building, returning the archive
+    pub fn build(self) -> Result<Vec<u8>, Error> {
+        let mut archive: Vec<u8> = Vec::new();
+        try!(self.write(&mut archive));
+        Ok(archive)
+    }
+
+    /// Finish building, writing the archive to the output `stream` given.  Returns the
+    /// number of bytes written.
+    pub fn write<S: Write>(self, stream: &mut S) -> Result<usize, Error> {
+        let mut headers = Headers::new();
+        headers.set_raw("From", "<Saved by mime-multipart>");
+        headers.set_raw("Snapshot-Content-Location", self.location.clone());
+        if let Some(subject) = self.subject {
+            headers.set_raw("Subject", subject);
+        }
+        headers.set(Date(SystemTime::now().into()));
+        headers.set(ContentType("multipart/related; type=\"text/html\"".parse().unwrap()));
+        write_email(stream, &headers, &self.boundary, &self.nodes)
+    }
+}
+
+// A part of an archive, encoded as browsers do: with quoted-printable if it is text, and
+// with base64 otherwise
+fn resource_part(location: &str, content_type: Mime, body: Vec<u8>) -> Node {
+    let encoding = if content_type.type_() == mime::TEXT { TransferEncoding::QuotedPrintable }
+                   else { TransferEncoding::Base64 };
+    // Encoding into memory cannot fail
+    let body = transfer_encoding::encode(&body, encoding).unwrap();
+    let mut part = Part::from_bytes(content_type, body);
+    part.headers.set_raw(CONTENT_TRANSFER_ENCODING, encoding.as_str());
+    part.headers.set_raw(CONTENT_LOCATION, location.to_owned());
+    Node::Part(part)
+}
+
+fn add_locations<'a>(locations: &mut Vec<(String, &'a Node)>, nodes: &'a [Node],
+                     base: Option<&str>)
+{
+    for node in nodes {
+        let node_base = base_of(node.headers(), base);
+        if let Some(location) = header_text(node.headers(), CONTENT_LOCATION) {
+            // Resolved against the part's own Content-Base, if it has one
+            let content_base = header_text(node.headers(), CONTENT_BASE).map(|own| {
+                match base { Some(base) => resolve(base, &own), None => own }
+            });
+            let location = match content_base.as_ref().map(|base| &base[..]).or(base) {
+                Some(base) => resolve(base, &location),
+                None => location,
+            };
+            locations.push((location, node));
+        }
+        match *node {
+            Node::Multipart((_, ref subnodes)) => {
+                add_locations(locations, subnodes, node_base.as_ref().map(|base| &base[..]))
+            },
+            Node::Message((_, ref message)) => {
+                add_locations(locations, ::std::slice::from_ref(&**message),
+                              node_base.as_ref().map(|base| &base[..]))
+            },
+            _ => { },
+        }
+    }
+}
+
+// The base URL given by the headers, against which the locations within them are resolved:
+// their Content-Base, or else their Content-Location, resolved against the enclosing base
+fn base_of(headers: &Headers, base: Option<&str>) -> Option<String> {
+    let own = header_text(headers, CONTENT_BASE)
+        .or_else(|| header_text(headers, CONTENT_LOCATION));
+    match (own, base) {
+        (Some(own), Some(base)) => Some(resolve(base, &own)),
+        (Some(own), None) => Some(own),
+        (None, base) => base.map(|base| base.to_owned()),
+    }
+}
+
+fn header_text(headers: &Headers, name: &str) -> Option<String> {
+    headers.get_raw(name)
+        .and_then(|raw| raw.one())
+        .map(|raw| String::from_utf8_lossy(raw).trim().to_owned())
+        .filter(|text| ! text.is_empty())
+}
+
+// Resolve a URL reference against a base URL, as RFC 3986 does but for removing dot segments
+// other than a leading "./"
+fn resolve(base: &str, reference: &str) -> String {
+    if scheme_len(reference).is_some() {
+        return reference.to_owned();
+    }
+    let scheme_end = match scheme_len(base) {
+        Some(len) => len + 1,
+        None => return reference.to_owned(),
+    };
+    // The authority ends at the path, or at the query or fragment if there is no path
+    let authority_end = if base[scheme_end..].starts_with("//") {
+        base[scheme_end + 2..].find(|c| c == '/' || c == '?' || c == '#')
+            .map_or(base.len(), |i| scheme_end + 2 + i)
+    } else {
+        scheme_end
+    };
+
+    if reference.starts_with("//") {
+        format!("{}{}", &base[..scheme_end], reference)
+    } else if reference.starts_with('/') {
+        format!("{}{}", &base[..authority_end], reference)
+    } else if reference.starts_with('?') || reference.starts_with('#') || reference.is_empty() {
+        let end = base.find(if reference.starts_with('#') { '#' } else { '?' })
+            .unwrap_or(base.len());
+        format!("{}{}", &base[..end], reference)
+    } else {
+        let path_end = base.find(|c| c == '?' || c == '#').unwrap_or(base.len());
+        let dir_end = base[authority_end..path_end].rfind('/')
+            .map_or(authority_end, |i| authority_end + i + 1);
+        let reference = if reference.starts_with("./") { &reference[2..] } else { reference };
+        if dir_end == authority_end && authority_end > scheme_end {
+            format!("{}/{}", &base[..authority_end], reference)
+        } else {
+            format!("{}{}", &base[..dir_end], reference)
+        }
+    }
+}
+
+// The length of the scheme of an absolute URL, before its colon
+fn scheme_len(url: &str) -> Option<usize> {
+    let colon = match url.find(':') {
+        Some(colon) if colon > 0 => colon,
+        _ => return None,
+    };
+    let scheme = &url[..colon];
+    if scheme.chars().next().map_or(false, |c| c.is_ascii_alphabetic())
+        && scheme.chars().all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.')
+    {
+        Some(colon)
+    } else {
+        None
+    }
+}
@@ -24,10 +24,6 @@
 
 use std::fmt;
 use std::io::{self, Read, Write, Cursor};
-use std::net::SocketAddr;
-use std::time::Duration;
-
-use hyper::net::NetworkStream;
 
 pub struct MockStream {
     pub read: Cursor<Vec<u8>>,
@@ -80,17 +76,3 @@ impl Write for MockStream {
         Ok(())
     }
 }
-
-impl NetworkStream for MockStream {
-    fn peer_addr(&mut self) -> io::Result<SocketAddr> {
-        Ok("127.0.0.1:1337".parse().unwrap())
-    }
-
-    fn set_read_timeout(&self, _: Option<Duration>) -> io::Result<()> {
-        Ok(())
-    }
-
-    fn set_write_timeout(&self, _: Option<Duration>) -> io::Result<()> {
-        Ok(())
-    }
-}
@@ -0,0 +1,144 @@
+// Copyright 2016 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Storing file parts in an object store as they are parsed, by multipart upload, rather
+//! than in temporary files (requires the `object-store` feature)
+//!
+//! The requests are made through a `MultipartUploadClient`, whose calls are those of the S3
+//! multipart upload API, which S3-compatible stores also offer.  `s3::S3Client` makes them
+//! over HTTP (requires the `s3` feature); otherwise the caller implements it over whichever
+//! SDK is in use.
+
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use header::{ContentType, Headers};
+use textnonce::TextNonce;
+
+use super::{Error, FilePart};
+use storage::{PartStorage, StoredBody};
+
+/// The requests of a multipart upload, as in the S3 API, made to the bucket objects are
+/// stored in
+pub trait MultipartUploadClient: Send + Sync {
+    /// Start a multipart upload of the object `key`, with the `Content-Type` given if
+    /// any, returning the ID of the upload
+    fn create_multipart_upload(&self, key: &str, content_type: Option<&str>)
+                               -> Result<String, Error>;
+
+    /// Upload part `part_number` (counting from 1) of an object, returning its ETag
+    fn upload_part(&self, key: &str, upload_id: &str, part_number: u32, body: Vec<u8>)
+                   -> Result<String, Error>;
+
+    /// Complete an upload from its parts, given as their numbers and ETags
+    fn complete_multipart_upload(&self, key: &str, upload_id: &str, parts: &[(u32, String)])
+                                 -> Result<(), Error>;
+
+    /// Abort an upload, discarding the parts uploaded
+    fn abort_multipart_upload(&self, key: &str, upload_id: &str) -> Result<(), Error>;
+}
+
+/// Stores the body of each file part as an object, uploaded in pieces of `part_size` bytes
+/// as it is read.  The `path` of each `FilePart` produced is the key of its object, which
+/// is left in the store when the `FilePart` is dropped.
+pub struct ObjectStorage<C: MultipartUploadClient> {
+    /// The client through which objects are uploaded
+    pub client: Arc<C>,
+    /// Text to begin the key of every object with, such as `uploads/`.  The rest of the key
+    /// is chosen at random.
+    pub prefix: String,
+    /// The size of the pieces uploaded, but for the last.  S3 requires at least 5 MiB.
+    /// Defaults to 8 MiB.
+    pub part_size: usize,
+}
+
+impl<C: MultipartUploadClient> ObjectStorage<C> {
+    /// Store objects through `client`, with keys beginning with `prefix`
+    pub fn new(client: C, prefix: &str) -> ObjectStorage<C> {
+        ObjectStorage {
+            client: Arc::new(client),
+            prefix: prefix.to_owned(),
+            part_size: 8 * 1024 * 1024,
+        }
+    }
+}
+
+impl<C: MultipartUploadClient + 'static> PartStorage for ObjectStorage<C> {
+    fn create(&self, headers: &Headers) -> Result<Box<dyn StoredBody>, Error> {
+        let key = format!("{}{}", self.prefix,
+                          TextNonce::sized_urlsafe(32).unwrap().into_string());
+        let content_type = headers.get::<ContentType>().map(|ct| ct.0.to_string());
+        let upload_id = try!(self.client.create_multipart_upload(
+            &key, content_type.as_ref().map(|ct| &**ct)));
+        Ok(Box::new(ObjectBody {
+            client: self.client.clone(),
+            key: key,
+            upload_id: upload_id,
+            part_size: ::std::cmp::max(self.part_size, 1),
+            buf: Vec::new(),
+            parts: Vec::new(),
+        }))
+    }
+}
+
+// An object being uploaded, with what has been read of it beyond the parts uploaded
+struct ObjectBody<C: MultipartUploadClient> {
+    client: Arc<C>,
+    key: String,
+    upload_id: String,
+    part_size: usize,
+    buf: Vec<u8>,
+    parts: Vec<(u32, String)>,
+}
+
+impl<C: MultipartUploadClient> ObjectBody<C> {
+    // Upload the bytes given as the next part
+    fn upload(&mut self, body: Vec<u8>) -> Result<(), Error> {
+        let number = self.parts.len() as u32 + 1;
+        let etag = try!(self.client.upload_part(&self.key, &self.upload_id, number, body));
+        self.parts.push((number, etag));
+        Ok(())
+    }
+}
+
+impl<C: MultipartUploadClient> Write for ObjectBody<C> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        while self.buf.len() >= self.part_size {
+            let rest = self.buf.split_off(self.part_size);
+            let part = ::std::mem::replace(&mut self.buf, rest);
+            if let Err(e) = self.upload(part) {
+                return Err(match e {
+                    Error::Io(e) => e,
+                    e => io::Error::new(io::ErrorKind::Other, e.to_string()),
+                });
+            }
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<C: MultipartUploadClient> StoredBody for ObjectBody<C> {
+    fn finalize(mut self: Box<Self>, headers: Headers) -> Result<FilePart, Error> {
+        // An object has at least one part, which may be empty
+        if ! self.buf.is_empty() || self.parts.is_empty() {
+            let part = ::std::mem::replace(&mut self.buf, Vec::new());
+            try!(self.upload(part));
+        }
+        try!(self.client.complete_multipart_upload(&self.key, &self.upload_id, &self.parts));
+        Ok(FilePart::new(headers, Path::new(&self.key)))
+    }
+
+    fn delete(self: Box<Self>) {
+        let _ = self.client.abort_multipart_upload(&self.key, &self.upload_id);
+    }
+}
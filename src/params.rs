@@ -0,0 +1,239 @@
+// Copyright 2016 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Parsing of header parameters such as those of `Content-Disposition`, including the
+// RFC 2231 / RFC 5987 extended (`name*=UTF-8''...`) and continued (`name*0*=...`) forms.
+
+use header::{Charset, Headers};
+
+use super::{charset_decode, Error};
+
+/// The parameters of a header value, such as `form-data; name="field"; filename="a.txt"`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Params {
+    /// The value preceding the parameters, e.g. `form-data`
+    pub value: String,
+    /// The parameters as (lowercased name, value) pairs, in the order given, with quoted
+    /// values unquoted
+    pub params: Vec<(String, String)>,
+}
+impl Params {
+    /// Parse a header value into its leading value and parameters
+    pub fn parse(header: &str) -> Params {
+        let mut chars = header.chars().peekable();
+        let mut value = String::new();
+        while let Some(&c) = chars.peek() {
+            if c == ';' { break; }
+            value.push(c);
+            chars.next();
+        }
+
+        let mut params: Vec<(String, String)> = Vec::new();
+        while chars.next().is_some() {
+            // We are past a ';'.  Read the name up to '='.
+            let mut name = String::new();
+            while let Some(&c) = chars.peek() {
+                if c == '=' || c == ';' { break; }
+                name.push(c);
+                chars.next();
+            }
+            if chars.peek() != Some(&'=') {
+                continue;
+            }
+            chars.next();
+
+            // Skip whitespace, then read a quoted-string or a token.
+            while chars.peek().map_or(false, |c| c.is_whitespace()) {
+                chars.next();
+            }
+            let mut val = String::new();
+            if chars.peek() == Some(&'"') {
+                chars.next();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => if let Some(c) = chars.next() { val.push(c) },
+                        c => val.push(c),
+                    }
+                }
+                // Skip anything up to the next ';'
+                while chars.peek().map_or(false, |&c| c != ';') {
+                    chars.next();
+                }
+            } else {
+                while let Some(&c) = chars.peek() {
+                    if c == ';' { break; }
+                    val.push(c);
+                    chars.next();
+                }
+                let trimmed = val.trim_end().len();
+                val.truncate(trimmed);
+            }
+
+            let name = name.trim().to_ascii_lowercase();
+            if ! name.is_empty() {
+                params.push((name, val));
+            }
+        }
+
+        Params {
+            value: value.trim().to_owned(),
+            params: params,
+        }
+    }
+
+    /// Parse the named header, if it is present
+    pub fn from_header(headers: &Headers, name: &str) -> Result<Option<Params>, Error> {
+        match headers.get_raw(name).and_then(|r| r.one()) {
+            Some(raw) => {
+                let header = try!(String::from_utf8(raw.to_vec()));
+                Ok(Some(Params::parse(&header)))
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Whether a parameter with this name is present in any of its forms
+    pub fn contains(&self, name: &str) -> bool {
+        self.params.iter().any(|&(ref n, _)| {
+            n == name || (n.starts_with(name) && n[name.len()..].starts_with('*'))
+        })
+    }
+
+    /// The decoded value of the named parameter.  RFC 2231 continuations
+    /// (`name*0`, `name*1*`, ...) take precedence over an extended value (`name*`), which
+    /// takes precedence over a plain value (`name`).  In a plain value, the `%22`, `%0D` and
+    /// `%0A` escapes that browsers write for `"`, CR and LF (per the WHATWG HTML standard)
+    /// are decoded.
+    pub fn get(&self, name: &str) -> Result<Option<String>, Error> {
+        // Continuations
+        let mut sections: Vec<(usize, bool, &str)> = Vec::new();
+        for &(ref n, ref v) in &self.params {
+            if ! n.starts_with(name) || ! n[name.len()..].starts_with('*') {
+                continue;
+            }
+            let rest = &n[name.len() + 1..];
+            let (number, encoded) = if rest.ends_with('*') {
+                (&rest[..rest.len() - 1], true)
+            } else {
+                (rest, false)
+            };
+            if let Ok(number) = number.parse::<usize>() {
+                sections.push((number, encoded, v));
+            }
+        }
+        if ! sections.is_empty() {
+            sections.sort_by_key(|&(number, _, _)| number);
+            let mut charset: Option<Charset> = None;
+            let mut bytes: Vec<u8> = Vec::new();
+            for (i, &(number, encoded, value)) in sections.iter().enumerate() {
+                // Sections must be numbered consecutively from zero
+                if number != i { break; }
+                if encoded {
+                    let value = if i == 0 {
+                        let (cs, value) = try!(split_extended(value));
+                        charset = Some(cs);
+                        value
+                    } else {
+                        value
+                    };
+                    bytes.extend(percent_decode(value));
+                } else {
+                    bytes.extend(value.as_bytes());
+                }
+            }
+            return decode(charset, &bytes).map(Some);
+        }
+
+        // Extended value
+        if let Some(&(_, ref v)) = self.params.iter().find(|&&(ref n, _)| {
+            n.len() == name.len() + 1 && n.starts_with(name) && n.ends_with('*')
+        }) {
+            let (charset, value) = try!(split_extended(v));
+            return decode(Some(charset), &percent_decode(value)).map(Some);
+        }
+
+        // Plain value
+        Ok(self.params.iter()
+           .find(|&&(ref n, _)| n == name)
+           .map(|&(_, ref v)| unescape_quoted(v)))
+    }
+}
+
+// Decode the `%22`, `%0D` and `%0A` escapes of a quoted value, leaving any other `%` as it is
+fn unescape_quoted(value: &str) -> String {
+    let mut output = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(i) = rest.find('%') {
+        output.push_str(&rest[..i]);
+        let escape = rest.get(i..i + 3).unwrap_or("");
+        let c = if escape == "%22" {
+            Some('"')
+        } else if escape.eq_ignore_ascii_case("%0D") {
+            Some('\r')
+        } else if escape.eq_ignore_ascii_case("%0A") {
+            Some('\n')
+        } else {
+            None
+        };
+        match c {
+            Some(c) => {
+                output.push(c);
+                rest = &rest[i + 3..];
+            },
+            None => {
+                output.push('%');
+                rest = &rest[i + 1..];
+            },
+        }
+    }
+    output.push_str(rest);
+    output
+}
+
+// Split an RFC 2231 extended value `charset'language'value` into its charset and value.
+fn split_extended(value: &str) -> Result<(Charset, &str), Error> {
+    let mut parts = value.splitn(3, '\'');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(charset), Some(_), Some(value)) => {
+            let charset = if charset.is_empty() {
+                Charset::Us_Ascii
+            } else {
+                try!(charset.parse().map_err(|_| Error::Decoding("Invalid charset".into())))
+            };
+            Ok((charset, value))
+        },
+        _ => Err(Error::Decoding("Malformed extended parameter value".into())),
+    }
+}
+
+fn decode(charset: Option<Charset>, bytes: &[u8]) -> Result<String, Error> {
+    match charset {
+        Some(charset) => charset_decode(&charset, bytes).map_err(Error::Decoding),
+        None => Ok(try!(String::from_utf8(bytes.to_vec()))),
+    }
+}
+
+/// Decode `%XX` escapes, leaving anything else (including malformed escapes) as it is
+pub fn percent_decode(value: &str) -> Vec<u8> {
+    let bytes = value.as_bytes();
+    let mut output: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len()
+            && bytes[i + 1].is_ascii_hexdigit() && bytes[i + 2].is_ascii_hexdigit()
+        {
+            let hex = ::std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap();
+            output.push(u8::from_str_radix(hex, 16).unwrap());
+            i += 3;
+            continue;
+        }
+        output.push(bytes[i]);
+        i += 1;
+    }
+    output
+}
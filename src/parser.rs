@@ -0,0 +1,83 @@
+// Copyright 2016 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! A parser which keeps its buffers from one parse to the next, for servers parsing many
+//! requests
+
+use std::io::Read;
+
+use super::{inner, read_epilogue, read_main_headers, with_context_reusing, Error,
+            MultipartBody, Node, ParseOptions, Scratch};
+use part_headers::PartHeaders;
+
+/// Parses multipart bodies under the same `ParseOptions`, as `read_multipart_with_options()`
+/// and its siblings do, but reusing the read buffer and the buffers headers are read into
+/// rather than allocating them afresh for each body.  Keep one per thread, or per
+/// connection, and parse each request with it.
+pub struct Parser {
+    /// The options each body is parsed under
+    pub options: ParseOptions,
+    scratch: Scratch,
+}
+
+impl Parser {
+    /// Create a parser which parses under the options given
+    pub fn new(options: ParseOptions) -> Parser {
+        Parser {
+            options: options,
+            scratch: Scratch::default(),
+        }
+    }
+
+    /// Parse a MIME `multipart/*` from a `Read`able stream, as
+    /// `read_multipart_with_options()` does.
+    ///
+    /// It is presumed that the headers are still in the stream.
+    pub fn read_multipart<S: Read>(&mut self, stream: &mut S) -> Result<Vec<Node>, Error> {
+        with_context_reusing(stream, &self.options, &mut self.scratch, |reader, context| {
+            let headers = try!(read_main_headers(reader, context.options.max_main_headers));
+            let mut body = MultipartBody::default();
+            try!(inner(reader, &headers, context, None, &mut body));
+            Ok(body.nodes)
+        })
+    }
+
+    /// Parse a MIME `multipart/*` body from a `Read`able stream, as
+    /// `read_multipart_body_with_options()` does.
+    ///
+    /// It is presumed that you have the `Headers` already and the stream starts at the body.
+    pub fn read_multipart_body<S: Read, H: PartHeaders + ?Sized>(
+        &mut self,
+        stream: &mut S,
+        headers: &H)
+        -> Result<Vec<Node>, Error>
+    {
+        with_context_reusing(stream, &self.options, &mut self.scratch, |reader, context| {
+            let mut body = MultipartBody::default();
+            try!(inner(reader, headers, context, None, &mut body));
+            Ok(body.nodes)
+        })
+    }
+
+    /// Parse a MIME `multipart/*` body from a `Read`able stream into a `MultipartBody`, as
+    /// `read_multipart_body_full()` does.
+    ///
+    /// It is presumed that you have the `Headers` already and the stream starts at the body.
+    pub fn read_multipart_body_full<S: Read, H: PartHeaders + ?Sized>(
+        &mut self,
+        stream: &mut S,
+        headers: &H)
+        -> Result<MultipartBody, Error>
+    {
+        with_context_reusing(stream, &self.options, &mut self.scratch, |reader, context| {
+            let mut body = MultipartBody::default();
+            try!(inner(reader, headers, context, None, &mut body));
+            try!(read_epilogue(reader, &mut body));
+            Ok(body)
+        })
+    }
+}
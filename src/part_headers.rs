@@ -0,0 +1,105 @@
+// Copyright 2016 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! The `PartHeaders` trait, through which the top-level headers of a multipart body are
+//! taken, so that they need not be `header::Headers`.  The parser reads the boundary and type
+//! of the body through it directly.  The headers of the parts themselves are still
+//! `header::Headers`, as are those of every `Node`.
+
+use std::borrow::Cow;
+
+use header::Headers;
+use mime::Mime;
+
+use super::Error;
+use params::Params;
+
+/// A set of headers, as needed to parse or write a multipart body.
+///
+/// Implemented for `header::Headers`, for a list of `(name, raw value)` pairs, and (with the
+/// `http` feature) for an `http::HeaderMap`.  Only `header_pairs()` need be implemented;
+/// the rest are derived from it.
+pub trait PartHeaders {
+    /// Each header as its name and raw value, in order.  A header with several values
+    /// appears once for each.
+    fn header_pairs(&self) -> Vec<(String, Vec<u8>)>;
+
+    /// The raw value of the named header, compared without regard to case, if it is
+    /// present exactly once
+    fn raw_value(&self, name: &str) -> Option<Vec<u8>> {
+        let mut values = self.header_pairs().into_iter()
+            .filter(|&(ref n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value);
+        match (values.next(), values.next()) {
+            (Some(value), None) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// The `Content-Type`, if it is present and valid
+    fn content_type(&self) -> Option<Mime> {
+        self.raw_value("Content-Type")
+            .and_then(|raw| String::from_utf8(raw).ok())
+            .and_then(|value| value.trim().parse().ok())
+    }
+
+    /// The decoded value of the named `Content-Disposition` parameter, if the header and
+    /// parameter are present.  Extended (RFC 2231) values are decoded, as are the `%22`,
+    /// `%0D` and `%0A` escapes browsers write in plain values.
+    fn content_disposition_param(&self, name: &str) -> Result<Option<String>, Error> {
+        match self.raw_value("Content-Disposition") {
+            Some(raw) => Params::parse(&try!(String::from_utf8(raw))).get(name),
+            None => Ok(None),
+        }
+    }
+
+    /// The headers as `header::Headers`, as needed where they are changed or kept, such as
+    /// by `write_multipart_with_headers()` in setting the `Content-Type`
+    fn to_headers(&self) -> Cow<'_, Headers> {
+        let mut headers = Headers::new();
+        for (name, value) in self.header_pairs() {
+            headers.append_raw(name, value);
+        }
+        Cow::Owned(headers)
+    }
+}
+
+impl PartHeaders for Headers {
+    fn header_pairs(&self) -> Vec<(String, Vec<u8>)> {
+        let mut pairs = Vec::new();
+        for header in self.iter() {
+            for line in header.raw().iter() {
+                pairs.push((header.name().to_owned(), line.to_vec()));
+            }
+        }
+        pairs
+    }
+
+    fn raw_value(&self, name: &str) -> Option<Vec<u8>> {
+        self.get_raw(name).and_then(|raw| raw.one()).map(|raw| raw.to_vec())
+    }
+
+    fn to_headers(&self) -> Cow<'_, Headers> {
+        Cow::Borrowed(self)
+    }
+}
+
+impl PartHeaders for Vec<(String, Vec<u8>)> {
+    fn header_pairs(&self) -> Vec<(String, Vec<u8>)> {
+        self.clone()
+    }
+
+    fn raw_value(&self, name: &str) -> Option<Vec<u8>> {
+        let mut values = self.iter()
+            .filter(|&&(ref n, _)| n.eq_ignore_ascii_case(name))
+            .map(|&(_, ref value)| value);
+        match (values.next(), values.next()) {
+            (Some(value), None) => Some(value.clone()),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,167 @@
+// Copyright 2016 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Serialization of `Part`, `FilePart` and `Node` with serde (requires the `serde` feature),
+// so that a parsed tree may be stored, e.g. in a job queue, and taken up again later.
+//
+// Headers are stored as (name, raw value) pairs, the bodies of parts as bytes, and files
+// by their path alone.  Each type is converted to a mirror of it which serde can derive
+// for, borrowing what it can when serializing.
+
+use std::borrow::Cow;
+use std::path::Path;
+
+use bytes::Bytes;
+use header::Headers;
+use serde::de::{Deserialize, Deserializer};
+use serde::ser::{self, Serialize, Serializer};
+
+use super::{FilePart, Node, Part};
+use part_headers::PartHeaders;
+
+type HeaderPairs = Vec<(String, Vec<u8>)>;
+
+fn from_pairs(pairs: HeaderPairs) -> Headers {
+    pairs.to_headers().into_owned()
+}
+
+#[derive(Serialize, Deserialize)]
+struct PartRepr<'a> {
+    headers: HeaderPairs,
+    body: Cow<'a, [u8]>,
+    raw_headers: Option<Cow<'a, [u8]>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct FilePartRepr<'a> {
+    headers: HeaderPairs,
+    path: Cow<'a, Path>,
+    size: Option<usize>,
+    raw_headers: Option<Cow<'a, [u8]>>,
+    detected_type: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+enum NodeRepr<'a> {
+    Part(PartRepr<'a>),
+    File(FilePartRepr<'a>),
+    Multipart(HeaderPairs, Cow<'a, [Node]>),
+    Message(HeaderPairs, Cow<'a, Node>),
+}
+
+impl<'a> From<&'a Part> for PartRepr<'a> {
+    fn from(part: &'a Part) -> PartRepr<'a> {
+        PartRepr {
+            headers: part.headers.header_pairs(),
+            body: Cow::Borrowed(&part.body),
+            raw_headers: part.raw_headers.as_ref().map(|raw| Cow::Borrowed(&raw[..])),
+        }
+    }
+}
+
+impl<'a> From<PartRepr<'a>> for Part {
+    fn from(repr: PartRepr<'a>) -> Part {
+        Part {
+            headers: from_pairs(repr.headers),
+            body: Bytes::from(repr.body.into_owned()),
+            raw_headers: repr.raw_headers.map(|raw| raw.into_owned()),
+        }
+    }
+}
+
+impl<'a> FilePartRepr<'a> {
+    // Fails for a file without a name, whose path is only good within this process
+    fn new(filepart: &'a FilePart) -> Result<FilePartRepr<'a>, &'static str> {
+        if filepart.unlinked.is_some() {
+            return Err("a file part without a name on disk cannot be serialized");
+        }
+        Ok(FilePartRepr {
+            headers: filepart.headers.header_pairs(),
+            path: Cow::Borrowed(&filepart.path),
+            size: filepart.size,
+            raw_headers: filepart.raw_headers.as_ref().map(|raw| Cow::Borrowed(&raw[..])),
+            detected_type: filepart.detected_type.as_ref().map(|mime| mime.to_string()),
+        })
+    }
+}
+
+impl<'a> From<FilePartRepr<'a>> for FilePart {
+    fn from(repr: FilePartRepr<'a>) -> FilePart {
+        let mut filepart = FilePart::new(from_pairs(repr.headers), &repr.path);
+        filepart.size = repr.size;
+        filepart.raw_headers = repr.raw_headers.map(|raw| raw.into_owned());
+        filepart.detected_type = repr.detected_type.and_then(|mime| mime.parse().ok());
+        filepart
+    }
+}
+
+impl<'a> NodeRepr<'a> {
+    fn new(node: &'a Node) -> Result<NodeRepr<'a>, &'static str> {
+        Ok(match *node {
+            Node::Part(ref part) => NodeRepr::Part(PartRepr::from(part)),
+            Node::File(ref filepart) => NodeRepr::File(try!(FilePartRepr::new(filepart))),
+            Node::Multipart((ref headers, ref subnodes)) => {
+                NodeRepr::Multipart(headers.header_pairs(), Cow::Borrowed(&subnodes[..]))
+            },
+            Node::Message((ref headers, ref message)) => {
+                NodeRepr::Message(headers.header_pairs(), Cow::Borrowed(&**message))
+            },
+            Node::Invalid(_) => return Err("an invalid part cannot be serialized"),
+        })
+    }
+}
+
+impl<'a> From<NodeRepr<'a>> for Node {
+    fn from(repr: NodeRepr<'a>) -> Node {
+        match repr {
+            NodeRepr::Part(part) => Node::Part(part.into()),
+            NodeRepr::File(filepart) => Node::File(filepart.into()),
+            NodeRepr::Multipart(headers, subnodes) => {
+                Node::Multipart((from_pairs(headers), subnodes.into_owned()))
+            },
+            NodeRepr::Message(headers, message) => {
+                Node::Message((from_pairs(headers), Box::new(message.into_owned())))
+            },
+        }
+    }
+}
+
+impl Serialize for Part {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        PartRepr::from(self).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Part {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Part, D::Error> {
+        PartRepr::deserialize(deserializer).map(Part::from)
+    }
+}
+
+impl Serialize for FilePart {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        try!(FilePartRepr::new(self).map_err(ser::Error::custom)).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for FilePart {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<FilePart, D::Error> {
+        FilePartRepr::deserialize(deserializer).map(FilePart::from)
+    }
+}
+
+impl Serialize for Node {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        try!(NodeRepr::new(self).map_err(ser::Error::custom)).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Node {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Node, D::Error> {
+        NodeRepr::deserialize(deserializer).map(Node::from)
+    }
+}
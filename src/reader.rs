@@ -0,0 +1,388 @@
+// Copyright 2016 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Pull-based parsing of a `multipart/*` body, exposing the body of each part as a `Read`
+//! rather than storing it in memory or in a file
+
+use std::io::{self, Read};
+
+use header::{ContentLength, Headers};
+
+use super::{find, get_multipart_boundary, parse_header_block, Error};
+
+// How much to read from the stream at a time
+const CHUNK_SIZE: usize = 4096;
+
+/// The most bytes the header block of a part may have by default, counting the rest of the
+/// boundary line preceding it, as set by `MultipartReader::set_max_header_length()`
+pub const DEFAULT_MAX_HEADER_LENGTH: usize = 64 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    // Before the first boundary
+    Preamble,
+    // Just past a boundary
+    Boundary,
+    // Within the body of a part
+    Body,
+    // Past a part body of known length, before the boundary following it
+    Trailer,
+    // Past the closing boundary
+    Finished,
+}
+
+/// Reads a `multipart/*` body one part at a time.  Each part is returned as a `PartReader`
+/// holding its headers, from which the part body is read directly off the stream.
+///
+/// Nested multiparts are not parsed; a nested part is returned like any other, and can be
+/// parsed in turn by creating a `MultipartReader` over its `PartReader`.
+///
+/// The stream may be non-blocking.  When a read from it fails with
+/// `io::ErrorKind::WouldBlock` (as `Error::Io` from `next_part()`, or directly from reading
+/// a `PartReader`), nothing read so far is lost, and the call can simply be repeated once
+/// more data has arrived.
+pub struct MultipartReader<R: Read> {
+    stream: R,
+    buf: Vec<u8>,
+    pos: usize,
+    // "--" followed by the boundary
+    boundary: Vec<u8>,
+    // The boundary preceded by a line terminator, which ends each part body.  Only the LF
+    // is included; a CR preceding it is left out of the body.
+    delimiter: Vec<u8>,
+    state: State,
+    max_headers: Option<usize>,
+    max_header_length: Option<usize>,
+    open_ended: bool,
+    // What remains of the current part body, if its length is known
+    remaining: Option<u64>,
+}
+
+impl<R: Read> MultipartReader<R> {
+    /// Create a reader over a stream positioned at the start of a `multipart/*` body.  The
+    /// boundary is taken from the `ContentType` of `headers`.
+    pub fn new(stream: R, headers: &Headers) -> Result<MultipartReader<R>, Error> {
+        let boundary = try!(get_multipart_boundary(headers));
+        let mut delimiter = Vec::with_capacity(1 + boundary.len());
+        delimiter.push(b'\n');
+        delimiter.extend(boundary.iter().cloned());
+        Ok(MultipartReader {
+            stream: stream,
+            buf: Vec::with_capacity(CHUNK_SIZE),
+            pos: 0,
+            boundary: boundary,
+            delimiter: delimiter,
+            state: State::Preamble,
+            max_headers: None,
+            max_header_length: Some(DEFAULT_MAX_HEADER_LENGTH),
+            open_ended: false,
+            remaining: None,
+        })
+    }
+
+    /// Limit the number of header fields a part may have, beyond which `next_part()` fails
+    /// with `Error::TooManyHeaders`.  By default any number is accepted.
+    pub fn set_max_headers(&mut self, max_headers: Option<usize>) {
+        self.max_headers = max_headers;
+    }
+
+    /// Limit the length in bytes of the header block of a part, beyond which `next_part()`
+    /// fails with `Error::HeadersTooLong` rather than buffering any more of it.  Defaults to
+    /// `DEFAULT_MAX_HEADER_LENGTH`; if `None`, there is no limit.
+    pub fn set_max_header_length(&mut self, max_header_length: Option<usize>) {
+        self.max_header_length = max_header_length;
+    }
+
+    /// Treat the body as having no closing boundary, as with the server push of
+    /// `multipart/x-mixed-replace`: `next_part()` returns `None` once the stream ends between
+    /// parts rather than failing.  Parts with a `Content-Length` header also end as soon as
+    /// that many bytes are read, without waiting for the boundary which follows, so that each
+    /// can be handled as soon as it arrives.  Defaults to `false`.
+    pub fn set_open_ended(&mut self, open_ended: bool) {
+        self.open_ended = open_ended;
+    }
+
+    // The stream being read
+    #[cfg(feature = "async-std")]
+    pub(crate) fn get_mut(&mut self) -> &mut R {
+        &mut self.stream
+    }
+
+    /// Read up to the next part, returning `None` once the closing boundary is reached.
+    /// Whatever was left unread of the previous part body is skipped.
+    pub fn next_part<'a>(&'a mut self) -> Result<Option<PartReader<'a, R>>, Error> {
+        if self.state == State::Body {
+            try!(io::copy(&mut PartBody { reader: self }, &mut io::sink()));
+        }
+
+        if self.state == State::Preamble || self.state == State::Trailer {
+            try!(self.skip_to_boundary());
+        }
+
+        if self.state == State::Finished {
+            return Ok(None);
+        }
+
+        // If the next two characters are '--', this was the closing boundary
+        while self.available() < 2 {
+            if ! try!(self.fill()) { break; }
+        }
+        if self.open_ended && self.available() == 0 {
+            self.state = State::Finished;
+            return Ok(None);
+        }
+        if self.buf[self.pos..].starts_with(b"--") {
+            self.pos += 2;
+            self.state = State::Finished;
+            return Ok(None);
+        }
+
+        // Buffer the rest of the boundary line and the header block before consuming any of
+        // it, so that a read which would block leaves nothing half-read.  Should the stream
+        // end first, what follows reports the error.
+        try!(self.buffer_header_block());
+
+        // The rest of the boundary line may only hold transport padding
+        let line = match try!(self.read_line()) {
+            Some(line) => line,
+            None => return Err(Error::NoCrLfAfterBoundary),
+        };
+        if without_line_terminator(&line).iter().any(|&b| b != b' ' && b != b'\t') {
+            return Err(Error::NoCrLfAfterBoundary);
+        }
+
+        // Read the header lines, up to an empty line, normalizing their line terminators
+        // to CRLF as httparse expects.
+        let mut header_block: Vec<u8> = Vec::new();
+        let mut raw_headers: Vec<u8> = Vec::new();
+        loop {
+            let line = match try!(self.read_line()) {
+                Some(line) => line,
+                None if self.open_ended && raw_headers.is_empty() => {
+                    // The stream ended while awaiting the next part
+                    self.state = State::Finished;
+                    return Ok(None);
+                },
+                None => return Err(Error::EofInPartHeaders),
+            };
+            raw_headers.extend(line.iter().cloned());
+            let line = without_line_terminator(&line);
+            if line.is_empty() {
+                break;
+            }
+            header_block.extend(line.iter().cloned());
+            header_block.extend(b"\r\n".iter().cloned());
+        }
+        header_block.extend(b"\r\n".iter().cloned());
+        let headers = try!(parse_header_block(&header_block, self.max_headers));
+
+        self.remaining = if self.open_ended {
+            headers.get::<ContentLength>().map(|&ContentLength(length)| length)
+        } else {
+            None
+        };
+        self.state = State::Body;
+        Ok(Some(PartReader {
+            headers: headers,
+            raw_headers: raw_headers,
+            reader: self,
+        }))
+    }
+
+    // Read past the next boundary, discarding the preamble, or whatever follows a part body
+    // of known length
+    fn skip_to_boundary(&mut self) -> Result<(), Error> {
+        loop {
+            if let Some(i) = find(&self.buf[self.pos..], &self.boundary) {
+                self.pos += i + self.boundary.len();
+                self.state = State::Boundary;
+                return Ok(());
+            }
+            // Keep only what could be the start of the boundary
+            let keep = self.boundary.len() - 1;
+            if self.available() > keep {
+                self.pos = self.buf.len() - keep;
+            }
+            if ! try!(self.fill()) {
+                if self.state == State::Trailer && self.open_ended {
+                    self.state = State::Finished;
+                    return Ok(());
+                }
+                return Err(Error::EofBeforeFirstBoundary);
+            }
+        }
+    }
+
+    // Read until the rest of the boundary line and the header block following it, up to and
+    // including its empty line, are buffered.  Returns false if the stream ends first.
+    fn buffer_header_block(&mut self) -> Result<bool, Error> {
+        let max = self.max_header_length.unwrap_or(usize::max_value());
+        let mut scanned = 0;
+        loop {
+            let end = header_block_end(&self.buf[self.pos..], &mut scanned);
+            if end.unwrap_or(self.available()) > max {
+                return Err(Error::HeadersTooLong { max: max });
+            }
+            if end.is_some() {
+                return Ok(true);
+            }
+            if ! try!(self.fill()) {
+                return Ok(false);
+            }
+        }
+    }
+
+    // Read a line, including its line terminator.  Returns `None` if the stream ends first.
+    fn read_line(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        loop {
+            if let Some(i) = self.buf[self.pos..].iter().position(|&b| b == b'\n') {
+                let line = self.buf[self.pos..self.pos + i + 1].to_vec();
+                self.pos += i + 1;
+                return Ok(Some(line));
+            }
+            if ! try!(self.fill()) {
+                return Ok(None);
+            }
+        }
+    }
+
+    // Read part body bytes into `out`.  Returns 0 once the delimiter has been read past.
+    pub(crate) fn read_body(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.state != State::Body || out.is_empty() {
+                return Ok(0);
+            }
+            if let Some(remaining) = self.remaining {
+                if remaining == 0 {
+                    self.state = State::Trailer;
+                    return Ok(0);
+                }
+                if self.available() == 0 && ! try!(self.fill()) {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                              "the body ended within a part"));
+                }
+                let max = ::std::cmp::min(remaining, self.available() as u64) as usize;
+                let count = self.take(out, max);
+                self.remaining = Some(remaining - count as u64);
+                return Ok(count);
+            }
+            match find(&self.buf[self.pos..], &self.delimiter) {
+                Some(i) => {
+                    let end = if i > 0 && self.buf[self.pos + i - 1] == b'\r' { i - 1 } else { i };
+                    if end > 0 {
+                        return Ok(self.take(out, end));
+                    }
+                    self.pos += i + self.delimiter.len();
+                    self.state = State::Boundary;
+                    return Ok(0);
+                },
+                None => {
+                    // Hold back what could be the start of the delimiter, with its CR
+                    let safe = self.available().saturating_sub(self.delimiter.len());
+                    if safe > 0 {
+                        return Ok(self.take(out, safe));
+                    }
+                    if ! try!(self.fill()) {
+                        return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                                  "the body ended within a part"));
+                    }
+                },
+            }
+        }
+    }
+
+    // Copy up to `max` buffered bytes into `out`
+    fn take(&mut self, out: &mut [u8], max: usize) -> usize {
+        let count = ::std::cmp::min(max, out.len());
+        out[..count].copy_from_slice(&self.buf[self.pos..self.pos + count]);
+        self.pos += count;
+        count
+    }
+
+    fn available(&self) -> usize {
+        self.buf.len() - self.pos
+    }
+
+    // Read more from the stream into the buffer.  Returns false at the end of the stream.
+    fn fill(&mut self) -> io::Result<bool> {
+        if self.pos > 0 {
+            self.buf.drain(..self.pos);
+            self.pos = 0;
+        }
+        let len = self.buf.len();
+        self.buf.resize(len + CHUNK_SIZE, 0);
+        loop {
+            match self.stream.read(&mut self.buf[len..]) {
+                Ok(count) => {
+                    self.buf.truncate(len + count);
+                    return Ok(count > 0);
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    self.buf.truncate(len);
+                    return Err(e);
+                },
+            }
+        }
+    }
+}
+
+/// A part of a `multipart/*` body being read by a `MultipartReader`.  Reading from it
+/// yields the part body, up to the next boundary.
+pub struct PartReader<'a, R: Read + 'a> {
+    /// The headers of the part
+    pub headers: Headers,
+    /// The header block exactly as it was read, including line terminators and the empty
+    /// line ending it
+    pub raw_headers: Vec<u8>,
+    reader: &'a mut MultipartReader<R>,
+}
+
+impl<'a, R: Read> Read for PartReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read_body(buf)
+    }
+}
+
+// Reads the current part body, for skipping it
+struct PartBody<'a, R: Read + 'a> {
+    reader: &'a mut MultipartReader<R>,
+}
+
+impl<'a, R: Read> Read for PartBody<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read_body(buf)
+    }
+}
+
+// The end of the line following a boundary and the header block after it, if it is all in
+// `buf`.  `scanned` keeps the position of the first line not yet found complete, so that
+// lines are not scanned again as more is buffered.
+fn header_block_end(buf: &[u8], scanned: &mut usize) -> Option<usize> {
+    loop {
+        let i = match buf[*scanned..].iter().position(|&b| b == b'\n') {
+            Some(i) => i,
+            None => return None,
+        };
+        let line = &buf[*scanned..*scanned + i + 1];
+        let first = *scanned == 0;
+        *scanned += i + 1;
+        if ! first && without_line_terminator(line).is_empty() {
+            return Some(*scanned);
+        }
+    }
+}
+
+fn without_line_terminator(line: &[u8]) -> &[u8] {
+    if line.ends_with(b"\r\n") {
+        &line[..line.len() - 2]
+    } else if line.ends_with(b"\n") {
+        &line[..line.len() - 1]
+    } else {
+        line
+    }
+}
@@ -0,0 +1,116 @@
+// Copyright 2016 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Support for `multipart/related` (RFC 2387), whose parts refer to each other by
+//! `Content-ID`, as in MHTML
+
+use header::{ContentType, Headers};
+
+use super::{Error, Node};
+use params::percent_decode;
+
+/// The name of the `Content-ID` header
+pub const CONTENT_ID: &'static str = "Content-ID";
+
+/// An index of the parts of a parsed `multipart/related` by `Content-ID`, with its root part
+#[derive(Clone, Debug)]
+pub struct Related<'a> {
+    /// The root part: the one named by the `start` parameter of the `Content-Type`, or the
+    /// first part if there is no such parameter.  `None` only if there are no parts.
+    pub root: Option<&'a Node>,
+    /// The (Content-ID, node) pairs, with Content-IDs stripped of their angle brackets.  Parts
+    /// of nested multiparts are included.
+    pub index: Vec<(String, &'a Node)>,
+}
+impl<'a> Related<'a> {
+    /// Index the nodes parsed from a `multipart/related` with the given headers.  Fails if the
+    /// `start` parameter names no part.
+    pub fn new(headers: &Headers, nodes: &'a [Node]) -> Result<Related<'a>, Error> {
+        let mut index: Vec<(String, &'a Node)> = Vec::new();
+        add_to_index(&mut index, nodes);
+
+        let start = {
+            let ct: Option<&ContentType> = headers.get();
+            ct.and_then(|ct| ct.get_param("start").map(|start| normalize(start.as_str())))
+        };
+        let root = match start {
+            Some(start) => match index.iter().find(|&&(ref id, _)| *id == start) {
+                Some(&(_, node)) => Some(node),
+                None => return Err(Error::StartNotFound),
+            },
+            None => nodes.first(),
+        };
+
+        Ok(Related {
+            root: root,
+            index: index,
+        })
+    }
+
+    /// Index a nested `multipart/related` node.  Returns `None` if the node is not a
+    /// multipart.
+    pub fn from_node(node: &'a Node) -> Option<Result<Related<'a>, Error>> {
+        match *node {
+            Node::Multipart((ref headers, ref subnodes)) => Some(Related::new(headers, subnodes)),
+            _ => None,
+        }
+    }
+
+    /// The part with this Content-ID, given either as a `cid:` URL (RFC 2392), with or
+    /// without angle brackets, or bare
+    pub fn get(&self, id: &str) -> Option<&'a Node> {
+        let id = parse_content_id(id);
+        self.index.iter().find(|&&(ref i, _)| *i == id).map(|&(_, node)| node)
+    }
+}
+
+// A Content-ID given as a `cid:` URL, with or without angle brackets, or bare, as it is
+// held in the index
+pub(crate) fn parse_content_id(id: &str) -> String {
+    let id = id.trim();
+    let is_url = id.as_bytes().get(..4)
+        .map_or(false, |scheme| scheme.eq_ignore_ascii_case(b"cid:"));
+    let id = if is_url {
+        String::from_utf8_lossy(&percent_decode(&id[4..])).into_owned()
+    } else {
+        id.to_owned()
+    };
+    normalize(&id)
+}
+
+fn add_to_index<'a>(index: &mut Vec<(String, &'a Node)>, nodes: &'a [Node]) {
+    for node in nodes {
+        if let Some(id) = content_id(node.headers()) {
+            index.push((id, node));
+        }
+        match *node {
+            Node::Multipart((_, ref subnodes)) => add_to_index(index, subnodes),
+            Node::Message((_, ref message)) => {
+                add_to_index(index, ::std::slice::from_ref(&**message))
+            },
+            _ => { },
+        }
+    }
+}
+
+// The Content-ID of the headers, without its angle brackets
+pub(crate) fn content_id(headers: &Headers) -> Option<String> {
+    headers.get_raw(CONTENT_ID)
+        .and_then(|raw| raw.one())
+        .map(|raw| normalize(&String::from_utf8_lossy(raw)))
+}
+
+// Strip whitespace and angle brackets from a Content-ID
+fn normalize(id: &str) -> String {
+    let id = id.trim();
+    let id = if id.starts_with('<') && id.ends_with('>') && id.len() >= 2 {
+        &id[1..id.len() - 1]
+    } else {
+        id
+    };
+    id.trim().to_owned()
+}
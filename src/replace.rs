@@ -0,0 +1,142 @@
+// Copyright 2016 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Support for `multipart/x-mixed-replace`, the server push used by MJPEG cameras and live
+//! updates, whose parts keep arriving for as long as the connection is open
+
+use std::io::{Read, Write};
+
+use bytes::Bytes;
+use header::{ContentLength, Headers};
+
+use super::{check_headers, write_headers, Error, Part, WriteAllCount, WriteOptions};
+use reader::MultipartReader;
+
+/// Reads the frames of a `multipart/x-mixed-replace` stream as they arrive, each as a
+/// `Part` held in memory.  A frame with a `Content-Length` header is returned as soon as
+/// its body has been read; otherwise it is returned once the boundary following it arrives.
+/// The stream may end after any frame, without a closing boundary.
+///
+/// The stream may be non-blocking, as with `MultipartReader`: should `next_frame()` fail
+/// with `io::ErrorKind::WouldBlock`, the frame read so far is kept, and the call can be
+/// repeated once more data has arrived.
+pub struct ReplaceStreamReader<R: Read> {
+    reader: MultipartReader<R>,
+    // The frame being read, kept across reads which would block
+    frame: Option<Part>,
+}
+
+impl<R: Read> ReplaceStreamReader<R> {
+    /// Create a reader over a stream positioned at the start of the body.  The boundary is
+    /// taken from the `ContentType` of `headers`.
+    pub fn new(stream: R, headers: &Headers) -> Result<ReplaceStreamReader<R>, Error> {
+        let mut reader = try!(MultipartReader::new(stream, headers));
+        reader.set_open_ended(true);
+        Ok(ReplaceStreamReader {
+            reader: reader,
+            frame: None,
+        })
+    }
+
+    /// Limit the number of header fields a frame may have, as
+    /// `MultipartReader::set_max_headers()` does.
+    pub fn set_max_headers(&mut self, max_headers: Option<usize>) {
+        self.reader.set_max_headers(max_headers);
+    }
+
+    /// Read the next frame, blocking until it is complete.  Returns `None` once the stream
+    /// ends, or a closing boundary is sent.  Fails if the stream ends within a frame.
+    pub fn next_frame(&mut self) -> Result<Option<Part>, Error> {
+        if self.frame.is_none() {
+            let mut part = match try!(self.reader.next_part()) {
+                Some(part) => part,
+                None => return Ok(None),
+            };
+            self.frame = Some(Part {
+                headers: ::std::mem::replace(&mut part.headers, Headers::new()),
+                body: Bytes::new(),
+                raw_headers: Some(::std::mem::replace(&mut part.raw_headers, Vec::new())),
+            });
+        }
+
+        let mut buf = [0; 4096];
+        loop {
+            let count = try!(self.reader.read_body(&mut buf));
+            if count == 0 {
+                return Ok(self.frame.take());
+            }
+            if let Some(ref mut frame) = self.frame {
+                frame.body.extend(buf[..count].iter().cloned());
+            }
+        }
+    }
+}
+
+/// Writes a `multipart/x-mixed-replace` stream one frame at a time, flushing after each so
+/// that it reaches the client straight away.  Each frame is followed immediately by a
+/// boundary, as some clients only show a frame once the boundary after it arrives.
+///
+/// The response itself should have a `Content-Type` of `multipart/x-mixed-replace` with
+/// this boundary.
+pub struct ReplaceStreamWriter<W: Write> {
+    stream: W,
+    boundary: Vec<u8>,
+    started: bool,
+}
+
+impl<W: Write> ReplaceStreamWriter<W> {
+    /// Create a writer which writes frames to `stream`, delimited by `boundary` (such as
+    /// from `generate_boundary()`)
+    pub fn new(stream: W, boundary: Vec<u8>) -> ReplaceStreamWriter<W> {
+        ReplaceStreamWriter {
+            stream: stream,
+            boundary: boundary,
+            started: false,
+        }
+    }
+
+    /// Write a frame with the given headers and body, and flush the stream.  A
+    /// `Content-Length` header is added if there is none, so that readers can take the frame
+    /// without waiting for the boundary.  Returns the number of bytes written.
+    pub fn push_part(&mut self, headers: &Headers, body: &[u8]) -> Result<usize, Error> {
+        try!(check_headers(headers));
+        let mut count: usize = 0;
+
+        // write the first boundary
+        if ! self.started {
+            count += try!(self.stream.write_all_vectored_count(&[b"--", &self.boundary]));
+            self.started = true;
+        }
+        count += try!(self.stream.write_all_count(b"\r\n"));
+
+        // write the part's headers and the blank line
+        let mut headers = headers.clone();
+        if ! headers.has::<ContentLength>() {
+            headers.set(ContentLength(body.len() as u64));
+        }
+        count += try!(write_headers(&mut self.stream, &headers, &WriteOptions::default()));
+
+        // Write the part's content, and the boundary ending the frame
+        count += try!(self.stream.write_all_vectored_count(&[body, b"\r\n--",
+                                                             &self.boundary]));
+
+        try!(self.stream.flush());
+        Ok(count)
+    }
+
+    /// End the stream with a closing boundary, and return the underlying stream.  Clients
+    /// also accept the stream simply being closed.
+    pub fn finish(mut self) -> Result<W, Error> {
+        if ! self.started {
+            try!(self.stream.write_all(b"--"));
+            try!(self.stream.write_all(&self.boundary));
+        }
+        try!(self.stream.write_all(b"--"));
+        try!(self.stream.flush());
+        Ok(self.stream)
+    }
+}
@@ -0,0 +1,177 @@
+// Copyright 2016 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Support for `multipart/report` (RFC 6522), as used for delivery status notifications
+//! (bounces, RFC 3464) and message disposition notifications (read receipts, RFC 8098)
+
+use std::io::Read;
+
+use header::{ContentType, Headers};
+use mime;
+
+use super::{open_content, Error, HeaderFields, Node};
+
+/// The parts of a parsed `multipart/report`
+#[derive(Clone, Debug)]
+pub struct Report<'a> {
+    /// The `report-type` parameter of the `Content-Type`, such as `delivery-status` or
+    /// `disposition-notification`
+    pub report_type: Option<String>,
+    /// The part describing the report to a person: the first part
+    pub human: Option<&'a Node>,
+    /// The machine-readable part: the first of type `message/<report-type>`, or the second
+    /// part if there is none
+    pub machine: Option<&'a Node>,
+    /// The message the report is about, or its headers, if returned: the part after the
+    /// machine-readable one of type `message/rfc822`, `text/rfc822-headers`,
+    /// `message/global` or `message/global-headers`.  This is a `Node::Message` if it was
+    /// parsed under `ParseOptions::parse_messages`.
+    pub returned: Option<&'a Node>,
+}
+
+impl<'a> Report<'a> {
+    /// Identify the parts of the nodes parsed from a `multipart/report` with the given
+    /// headers
+    pub fn new(headers: &Headers, nodes: &'a [Node]) -> Report<'a> {
+        let report_type = {
+            let ct: Option<&ContentType> = headers.get();
+            ct.and_then(|ct| {
+                ct.get_param("report-type").map(|value| value.as_str().to_ascii_lowercase())
+            })
+        };
+
+        let machine_index = report_type.as_ref()
+            .and_then(|report_type| {
+                nodes.iter().position(|node| is_type(node, mime::MESSAGE, report_type))
+            })
+            .or_else(|| if nodes.len() >= 2 { Some(1) } else { None });
+        let returned = machine_index.and_then(|machine| {
+            nodes[machine + 1..].iter().find(|node| is_returned(node))
+        });
+
+        Report {
+            report_type: report_type,
+            human: nodes.first(),
+            machine: machine_index.map(|i| &nodes[i]),
+            returned: returned,
+        }
+    }
+
+    /// Identify the parts of a nested `multipart/report` node.  Returns `None` if the node
+    /// is not a multipart.
+    pub fn from_node(node: &'a Node) -> Option<Report<'a>> {
+        match *node {
+            Node::Multipart((ref headers, ref subnodes)) => Some(Report::new(headers, subnodes)),
+            _ => None,
+        }
+    }
+
+    /// The delivery status, if the machine-readable part is a `message/delivery-status`
+    pub fn delivery_status(&self) -> Result<Option<DeliveryStatus>, Error> {
+        match self.machine {
+            Some(node) if is_type(node, mime::MESSAGE, "delivery-status") => {
+                Ok(Some(DeliveryStatus::parse(&try!(read_body(node)))))
+            },
+            _ => Ok(None),
+        }
+    }
+
+    /// The fields of the disposition notification, if the machine-readable part is a
+    /// `message/disposition-notification`
+    pub fn disposition_notification(&self) -> Result<Option<HeaderFields>, Error> {
+        match self.machine {
+            Some(node) if is_type(node, mime::MESSAGE, "disposition-notification") => {
+                Ok(Some(HeaderFields::parse(&try!(read_body(node)))))
+            },
+            _ => Ok(None),
+        }
+    }
+}
+
+/// The fields of a `message/delivery-status` (RFC 3464)
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DeliveryStatus {
+    /// The per-message fields, such as `Reporting-MTA` and `Arrival-Date`
+    pub message: HeaderFields,
+    /// The per-recipient fields, a group for each recipient, such as `Final-Recipient`,
+    /// `Action`, `Status` and `Diagnostic-Code`
+    pub recipients: Vec<HeaderFields>,
+}
+
+impl DeliveryStatus {
+    /// Parse the body of a `message/delivery-status`: groups of fields, separated by empty
+    /// lines, the first of which is per-message and the rest per-recipient
+    pub fn parse(body: &[u8]) -> DeliveryStatus {
+        let mut groups: Vec<HeaderFields> = Vec::new();
+        let mut start = 0;
+        while start < body.len() {
+            // Skip the empty lines before a group
+            let rest = &body[start..];
+            let blank = rest.iter().take_while(|&&b| b == b'\r' || b == b'\n').count();
+            if blank == rest.len() {
+                break;
+            }
+            let group = &rest[blank..];
+            let len = group_len(group);
+            groups.push(HeaderFields::parse(&group[..len]));
+            start += blank + len;
+        }
+
+        let mut groups = groups.into_iter();
+        DeliveryStatus {
+            message: groups.next().unwrap_or_default(),
+            recipients: groups.collect(),
+        }
+    }
+
+    /// The `Status` of each recipient, such as `5.1.1`, with any comment removed
+    pub fn statuses(&self) -> Vec<String> {
+        self.recipients.iter()
+            .filter_map(|recipient| recipient.get("Status"))
+            .map(|status| {
+                let status = String::from_utf8_lossy(status);
+                status.split(|c: char| c == '(' || c.is_whitespace())
+                    .next().unwrap_or("").to_owned()
+            })
+            .collect()
+    }
+}
+
+// The length of a group of fields, up to and including the empty line ending it
+fn group_len(group: &[u8]) -> usize {
+    let mut pos = 0;
+    for line in group.split(|&b| b == b'\n') {
+        pos += line.len() + 1;
+        if line.is_empty() || line == b"\r" {
+            break;
+        }
+    }
+    ::std::cmp::min(pos, group.len())
+}
+
+// Whether the node is of the type given, comparing its subtype without regard to case
+fn is_type(node: &Node, type_: mime::Name, subtype: &str) -> bool {
+    match node.content_type() {
+        Some(ct) => ct.type_() == type_ && ct.subtype().as_str().eq_ignore_ascii_case(subtype),
+        None => false,
+    }
+}
+
+// Whether the node is a returned message or its headers
+fn is_returned(node: &Node) -> bool {
+    is_type(node, mime::MESSAGE, "rfc822") || is_type(node, mime::TEXT, "rfc822-headers")
+        || is_type(node, mime::MESSAGE, "global")
+        || is_type(node, mime::MESSAGE, "global-headers")
+}
+
+fn read_body(node: &Node) -> Result<Vec<u8>, Error> {
+    let mut body: Vec<u8> = Vec::new();
+    if let Some(mut content) = try!(open_content(node)) {
+        try!(content.read_to_end(&mut body));
+    }
+    Ok(body)
+}
@@ -0,0 +1,98 @@
+// Copyright 2016 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Conversion of parsed nodes into `reqwest` multipart forms, for forwarding uploads
+//! (requires the `reqwest` feature)
+//!
+//! Only this direction is supported, as `reqwest` gives no access to the content of a
+//! `Form` or `Part` once built.  `reqwest` parts carry no headers other than their
+//! `Content-Type` and `Content-Disposition` filename, so any others are left behind.
+
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{self, Read};
+
+use reqwest::multipart::{Form, Part as FormPart};
+
+use super::{Error, FilePart, Node};
+use form_data::{FormData, FormValue};
+
+impl TryFrom<Node> for FormPart {
+    type Error = Error;
+
+    /// Convert a `Node::Part` or `Node::File` into a part, keeping its `Content-Type` and
+    /// filename.  The file of a `Node::File` is sent from where it is, and kept until it
+    /// has been sent.  Fails with `Error::NotAPart` for a nested multipart, a message or an
+    /// invalid part.
+    fn try_from(node: Node) -> Result<FormPart, Error> {
+        match node {
+            Node::Part(part) => {
+                let content_type = part.content_type().map(|ct| ct.to_string());
+                let filename = try!(super::disposition_param(&part.headers, "filename"));
+                let form_part = FormPart::bytes(part.body.to_vec());
+                describe(form_part, content_type, filename)
+            },
+            Node::File(file) => {
+                let content_type = file.content_type().map(|ct| ct.to_string());
+                let filename = try!(file.filename());
+                let size = match file.size {
+                    Some(size) => size as u64,
+                    None => try!(::std::fs::metadata(&file.path)).len(),
+                };
+                let body = FileBody {
+                    file: try!(File::open(&file.path)),
+                    _part: file,
+                };
+                describe(FormPart::reader_with_length(body, size), content_type, filename)
+            },
+            Node::Multipart(_) | Node::Message(_) | Node::Invalid(_) => Err(Error::NotAPart),
+        }
+    }
+}
+
+impl TryFrom<FormData> for Form {
+    type Error = Error;
+
+    /// Convert the fields of a form into a `reqwest` form, with the same names, in the same
+    /// order.  Text fields are sent as UTF-8.
+    fn try_from(form_data: FormData) -> Result<Form, Error> {
+        let mut form = Form::new();
+        for (name, value) in form_data.entries {
+            let part = match value {
+                FormValue::Text(text) => FormPart::text(text),
+                FormValue::File(file) => try!(FormPart::try_from(Node::File(file))),
+            };
+            form = form.part(name, part);
+        }
+        Ok(form)
+    }
+}
+
+// Set the `Content-Type` and filename of a part
+fn describe(mut part: FormPart, content_type: Option<String>, filename: Option<String>)
+            -> Result<FormPart, Error>
+{
+    if let Some(content_type) = content_type {
+        part = try!(part.mime_str(&content_type));
+    }
+    if let Some(filename) = filename {
+        part = part.file_name(filename);
+    }
+    Ok(part)
+}
+
+// The file of a part being sent, which is kept until then
+struct FileBody {
+    file: File,
+    _part: FilePart,
+}
+
+impl Read for FileBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.file.read(buf)
+    }
+}
@@ -0,0 +1,260 @@
+// Copyright 2016 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! A client for the multipart upload API of S3 and the stores compatible with it, such as
+//! MinIO, Ceph and R2, through which `ObjectStorage` streams file parts straight to a
+//! bucket as they are parsed (requires the `s3` feature)
+//!
+//! ```rust,ignore
+//! let client = S3Client::new("https://s3.eu-west-1.amazonaws.com", "eu-west-1", "uploads",
+//!                            &access_key_id, &secret_access_key);
+//! let mut options = ParseOptions::default();
+//! options.part_storage = Some(Arc::new(ObjectStorage::new(client, "incoming/")));
+//! ```
+//!
+//! Requests are signed with AWS Signature Version 4, and made with a blocking `reqwest`
+//! client.  `reqwest` is built without TLS here, so for an `https` endpoint enable one of
+//! its TLS features (such as `default-tls`) alongside this crate.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use reqwest::{Client, Method, Response};
+
+use super::Error;
+use date::civil_from_days;
+use object_store::MultipartUploadClient;
+use sha256::{hex, hmac_sha256, sha256};
+
+/// Makes the requests of multipart uploads to a bucket of S3, or of a store compatible with
+/// it
+pub struct S3Client {
+    /// Where the store is, such as `https://s3.eu-west-1.amazonaws.com` or
+    /// `http://localhost:9000`
+    pub endpoint: String,
+    /// The region requests are signed for, such as `eu-west-1`.  Stores without regions
+    /// usually take `us-east-1`.
+    pub region: String,
+    /// The bucket objects are stored in
+    pub bucket: String,
+    /// The ID of the access key requests are signed with
+    pub access_key_id: String,
+    /// The secret of the access key requests are signed with
+    pub secret_access_key: String,
+    /// The session token of temporary credentials, sent with every request.  Defaults to
+    /// `None`.
+    pub session_token: Option<String>,
+    /// Whether the bucket is named in the path (`endpoint/bucket/key`), as most
+    /// S3-compatible stores expect, rather than in the host name (`bucket.endpoint/key`),
+    /// as S3 prefers.  Defaults to `false`.
+    pub path_style: bool,
+    /// The HTTP client requests are made with, which may be replaced with one configured
+    /// otherwise, such as with timeouts
+    pub http: Client,
+}
+
+impl S3Client {
+    /// Make requests to `bucket` at `endpoint`, signed for `region` with the access key
+    /// given
+    pub fn new(endpoint: &str, region: &str, bucket: &str, access_key_id: &str,
+               secret_access_key: &str) -> S3Client
+    {
+        S3Client {
+            endpoint: endpoint.trim_end_matches('/').to_owned(),
+            region: region.to_owned(),
+            bucket: bucket.to_owned(),
+            access_key_id: access_key_id.to_owned(),
+            secret_access_key: secret_access_key.to_owned(),
+            session_token: None,
+            path_style: false,
+            http: Client::new(),
+        }
+    }
+
+    // Make a signed request about the object `key`, with the query parameters given, and
+    // return the response if it is successful
+    fn request(&self, method: Method, key: &str, query: &[(&str, &str)],
+               content_type: Option<&str>, body: Vec<u8>) -> Result<Response, Error>
+    {
+        let (scheme, host) = match self.endpoint.find("://") {
+            Some(i) => (&self.endpoint[..i], &self.endpoint[i + 3..]),
+            None => ("https", &*self.endpoint),
+        };
+        let (host, path) = if self.path_style {
+            (host.to_owned(), format!("/{}/{}", uri_encode(&self.bucket, false),
+                                      uri_encode(key, false)))
+        } else {
+            (format!("{}.{}", self.bucket, host), format!("/{}", uri_encode(key, false)))
+        };
+        let mut query: Vec<(String, String)> = query.iter()
+            .map(|&(name, value)| (uri_encode(name, true), uri_encode(value, true)))
+            .collect();
+        query.sort();
+        let query: Vec<String> = query.iter()
+            .map(|&(ref name, ref value)| format!("{}={}", name, value))
+            .collect();
+        let query = query.join("&");
+
+        let mut headers: Vec<(String, String)> = vec![
+            ("host".to_owned(), host.clone()),
+            ("x-amz-content-sha256".to_owned(), hex(&sha256(&body))),
+            ("x-amz-date".to_owned(), amz_date(SystemTime::now())),
+        ];
+        if let Some(content_type) = content_type {
+            headers.push(("content-type".to_owned(), content_type.to_owned()));
+        }
+        if let Some(ref token) = self.session_token {
+            headers.push(("x-amz-security-token".to_owned(), token.clone()));
+        }
+        headers.sort();
+        let authorization = self.authorization(method.as_str(), &path, &query, &headers);
+
+        let url = if query.is_empty() {
+            format!("{}://{}{}", scheme, host, path)
+        } else {
+            format!("{}://{}{}?{}", scheme, host, path, query)
+        };
+        let mut request = self.http.request(method, &*url)
+            .header("Authorization", authorization)
+            .body(body);
+        for (name, value) in headers {
+            request = request.header(&*name, value);
+        }
+        let mut response = try!(request.send());
+        if ! response.status().is_success() {
+            let text = response.text().unwrap_or_default();
+            return Err(Error::ObjectStore {
+                status: response.status().as_u16(),
+                code: element(&text, "Code"),
+            });
+        }
+        Ok(response)
+    }
+
+    // The `Authorization` header of a request, with its headers given as lowercase names
+    // and values in order of name, including `x-amz-date` and `x-amz-content-sha256`
+    pub(crate) fn authorization(&self, method: &str, path: &str, query: &str,
+                                headers: &[(String, String)]) -> String
+    {
+        let header = |name: &str| headers.iter()
+            .find(|&&(ref n, _)| n == name)
+            .map_or("", |&(_, ref value)| &**value);
+        let date_time = header("x-amz-date");
+        let scope = format!("{}/{}/s3/aws4_request", &date_time[..8], self.region);
+        let signed_headers: Vec<&str> = headers.iter().map(|&(ref name, _)| &**name).collect();
+        let signed_headers = signed_headers.join(";");
+
+        let mut canonical = format!("{}\n{}\n{}\n", method, path, query);
+        for &(ref name, ref value) in headers {
+            canonical.push_str(&format!("{}:{}\n", name, value.trim()));
+        }
+        canonical.push_str(&format!("\n{}\n{}", signed_headers,
+                                    header("x-amz-content-sha256")));
+        let string_to_sign = format!("AWS4-HMAC-SHA256\n{}\n{}\n{}", date_time, scope,
+                                     hex(&sha256(canonical.as_bytes())));
+
+        let key = format!("AWS4{}", self.secret_access_key);
+        let key = hmac_sha256(key.as_bytes(), &date_time.as_bytes()[..8]);
+        let key = hmac_sha256(&key, self.region.as_bytes());
+        let key = hmac_sha256(&key, b"s3");
+        let key = hmac_sha256(&key, b"aws4_request");
+        let signature = hex(&hmac_sha256(&key, string_to_sign.as_bytes()));
+        format!("AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+                self.access_key_id, scope, signed_headers, signature)
+    }
+}
+
+impl MultipartUploadClient for S3Client {
+    fn create_multipart_upload(&self, key: &str, content_type: Option<&str>)
+                               -> Result<String, Error>
+    {
+        let mut response = try!(self.request(Method::POST, key, &[("uploads", "")],
+                                             content_type, Vec::new()));
+        let text = try!(response.text());
+        element(&text, "UploadId").ok_or_else(|| missing("UploadId"))
+    }
+
+    fn upload_part(&self, key: &str, upload_id: &str, part_number: u32, body: Vec<u8>)
+                   -> Result<String, Error>
+    {
+        let part_number = part_number.to_string();
+        let response = try!(self.request(Method::PUT, key, &[("partNumber", &*part_number),
+                                                            ("uploadId", upload_id)],
+                                         None, body));
+        response.headers().get("ETag")
+            .and_then(|etag| etag.to_str().ok())
+            .map(|etag| etag.to_owned())
+            .ok_or_else(|| missing("ETag"))
+    }
+
+    fn complete_multipart_upload(&self, key: &str, upload_id: &str, parts: &[(u32, String)])
+                                 -> Result<(), Error>
+    {
+        let mut body = String::from("<CompleteMultipartUpload>");
+        for &(number, ref etag) in parts {
+            body.push_str(&format!("<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>",
+                                   number, xml_escape(etag)));
+        }
+        body.push_str("</CompleteMultipartUpload>");
+        let mut response = try!(self.request(Method::POST, key, &[("uploadId", upload_id)],
+                                             Some("application/xml"), body.into_bytes()));
+        // Completion may fail after the response has begun, with an error in its body
+        let text = try!(response.text());
+        if text.contains("<Error>") {
+            return Err(Error::ObjectStore {
+                status: response.status().as_u16(),
+                code: element(&text, "Code"),
+            });
+        }
+        Ok(())
+    }
+
+    fn abort_multipart_upload(&self, key: &str, upload_id: &str) -> Result<(), Error> {
+        try!(self.request(Method::DELETE, key, &[("uploadId", upload_id)], None, Vec::new()));
+        Ok(())
+    }
+}
+
+// Percent-encode as Signature Version 4 requires: all but unreserved characters, and `/`
+// too if `slash`
+fn uri_encode(value: &str, slash: bool) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for &b in value.as_bytes() {
+        match b {
+            b'0'..=b'9' | b'a'..=b'z' | b'A'..=b'Z' | b'-' | b'.' | b'_' | b'~' =>
+                encoded.push(b as char),
+            b'/' if ! slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+// A time as `x-amz-date` gives it, e.g. `20130524T000000Z`
+pub(crate) fn amz_date(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let secs = secs % 86400;
+    format!("{:04}{:02}{:02}T{:02}{:02}{:02}Z", year, month, day, secs / 3600,
+            secs / 60 % 60, secs % 60)
+}
+
+// The text of the first element of an XML response with the name given
+fn element(xml: &str, name: &str) -> Option<String> {
+    let open = format!("<{}>", name);
+    let start = xml.find(&open).map(|i| i + open.len());
+    start.and_then(|start| {
+        xml[start..].find(&format!("</{}>", name)).map(|end| xml[start..start + end].to_owned())
+    })
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+fn missing(name: &str) -> Error {
+    Error::Decoding(format!("No {} in the response of the object store", name).into())
+}
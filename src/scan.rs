@@ -0,0 +1,220 @@
+// Copyright 2016 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Searching for boundaries and other tokens, in memory and in buffered streams.  Tokens are
+// searched for a whole buffer at a time, skipping ahead by up to the length of the token
+// (Boyer-Moore-Horspool), rather than a byte at a time.  With the `simd` feature on x86-64,
+// vector instructions are used instead, AVX2 if the CPU supports it and SSE2 otherwise.
+
+use std::cmp;
+use std::io::{BufRead, ErrorKind, Result, Write};
+
+/// The position of the first occurrence of `needle` within `haystack`
+pub fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    let n = needle.len();
+    if n == 0 {
+        return Some(0);
+    }
+    if haystack.len() < n {
+        return None;
+    }
+    if n == 1 {
+        return haystack.iter().position(|&b| b == needle[0]);
+    }
+
+    find_long(haystack, needle)
+}
+
+// `find()` for a needle of at least two bytes, using the vector instructions available
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+fn find_long(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if is_x86_feature_detected!("avx2") {
+        unsafe { simd::find_avx2(haystack, needle) }
+    } else {
+        unsafe { simd::find_sse2(haystack, needle) }
+    }
+}
+
+#[cfg(not(all(feature = "simd", target_arch = "x86_64")))]
+fn find_long(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    find_scalar(haystack, needle)
+}
+
+// `find()` without vector instructions, for a needle of at least two bytes
+fn find_scalar(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    let n = needle.len();
+    if haystack.len() < n {
+        return None;
+    }
+
+    // How far the needle may be moved along, given the byte of the haystack under its last
+    // byte, without skipping over an occurrence
+    let mut skip = [n; 256];
+    for (i, &b) in needle[..n - 1].iter().enumerate() {
+        skip[b as usize] = n - 1 - i;
+    }
+
+    let last = needle[n - 1];
+    let mut pos = 0;
+    while pos + n <= haystack.len() {
+        let b = haystack[pos + n - 1];
+        if b == last && haystack[pos..pos + n - 1] == needle[..n - 1] {
+            return Some(pos);
+        }
+        pos += skip[b as usize];
+    }
+    None
+}
+
+/// Stream all bytes to `out` until the `token` or the end of the stream is reached, leaving
+/// the stream just past the token.  Returns the number of bytes streamed to `out`, which
+/// excludes the token, and whether the token was found.  `ErrorKind::Interrupted` errors
+/// are retried.
+pub fn stream_until_token<R: BufRead + ?Sized, W: Write>(reader: &mut R, token: &[u8],
+                                                          out: &mut W)
+                                                          -> Result<(usize, bool)>
+{
+    let mut written: usize = 0;
+
+    // Bytes held back from the end of the buffers read so far, as they may begin the token.
+    // Always a proper prefix of the token.
+    let mut partial: Vec<u8> = Vec::new();
+
+    loop {
+        let (used, found) = {
+            let buf = match reader.fill_buf() {
+                Ok(buf) => buf,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            if buf.is_empty() {
+                try!(out.write_all(&partial));
+                written += partial.len();
+                return Ok((written, false));
+            }
+
+            if partial.is_empty() {
+                match find(buf, token) {
+                    Some(i) => {
+                        try!(out.write_all(&buf[..i]));
+                        written += i;
+                        (i + token.len(), true)
+                    },
+                    None => {
+                        let keep = prefix_at_end(buf, token);
+                        let emit = buf.len() - keep;
+                        try!(out.write_all(&buf[..emit]));
+                        written += emit;
+                        partial.extend(buf[emit..].iter().cloned());
+                        (buf.len(), false)
+                    },
+                }
+            } else {
+                // See whether the token continues into this buffer
+                let matched = partial.len();
+                let n = cmp::min(token.len() - matched, buf.len());
+                if buf[..n] == token[matched..matched + n] {
+                    if matched + n == token.len() {
+                        (n, true)
+                    } else {
+                        partial.extend(buf[..n].iter().cloned());
+                        (n, false)
+                    }
+                } else {
+                    // The held back bytes do not begin the token after all.  Write out those
+                    // which cannot, and look again with what remains.
+                    let shift = (1..partial.len())
+                        .find(|&s| token.starts_with(&partial[s..]))
+                        .unwrap_or(partial.len());
+                    try!(out.write_all(&partial[..shift]));
+                    written += shift;
+                    partial.drain(..shift);
+                    (0, false)
+                }
+            }
+        };
+        reader.consume(used);
+        if found {
+            return Ok((written, true));
+        }
+    }
+}
+
+// The length of the longest proper prefix of `token` which `buf` ends with
+fn prefix_at_end(buf: &[u8], token: &[u8]) -> usize {
+    let max = cmp::min(token.len() - 1, buf.len());
+    for i in buf.len() - max..buf.len() {
+        if buf[i] == token[0] && token.starts_with(&buf[i..]) {
+            return buf.len() - i;
+        }
+    }
+    0
+}
+
+// Searches comparing the first and last bytes of the needle against many positions of the
+// haystack at once, and the rest of the needle only where both match.  The needle must be
+// at least two bytes long.
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+mod simd {
+    use std::arch::x86_64::*;
+
+    use super::find_scalar;
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn find_avx2(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+        let n = needle.len();
+        let first = _mm256_set1_epi8(needle[0] as i8);
+        let last = _mm256_set1_epi8(needle[n - 1] as i8);
+        let ptr = haystack.as_ptr();
+
+        let mut pos = 0;
+        while pos + n - 1 + 32 <= haystack.len() {
+            let a = _mm256_loadu_si256(ptr.add(pos) as *const __m256i);
+            let b = _mm256_loadu_si256(ptr.add(pos + n - 1) as *const __m256i);
+            let eq = _mm256_and_si256(_mm256_cmpeq_epi8(a, first), _mm256_cmpeq_epi8(b, last));
+            let mut mask = _mm256_movemask_epi8(eq) as u32;
+            while mask != 0 {
+                let i = pos + mask.trailing_zeros() as usize;
+                if haystack[i + 1..i + n - 1] == needle[1..n - 1] {
+                    return Some(i);
+                }
+                mask &= mask - 1;
+            }
+            pos += 32;
+        }
+
+        // The rest is too short for a whole vector
+        find_scalar(&haystack[pos..], needle).map(|i| pos + i)
+    }
+
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn find_sse2(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+        let n = needle.len();
+        let first = _mm_set1_epi8(needle[0] as i8);
+        let last = _mm_set1_epi8(needle[n - 1] as i8);
+        let ptr = haystack.as_ptr();
+
+        let mut pos = 0;
+        while pos + n - 1 + 16 <= haystack.len() {
+            let a = _mm_loadu_si128(ptr.add(pos) as *const __m128i);
+            let b = _mm_loadu_si128(ptr.add(pos + n - 1) as *const __m128i);
+            let eq = _mm_and_si128(_mm_cmpeq_epi8(a, first), _mm_cmpeq_epi8(b, last));
+            let mut mask = _mm_movemask_epi8(eq) as u32;
+            while mask != 0 {
+                let i = pos + mask.trailing_zeros() as usize;
+                if haystack[i + 1..i + n - 1] == needle[1..n - 1] {
+                    return Some(i);
+                }
+                mask &= mask - 1;
+            }
+            pos += 16;
+        }
+
+        // The rest is too short for a whole vector
+        find_scalar(&haystack[pos..], needle).map(|i| pos + i)
+    }
+}
@@ -0,0 +1,70 @@
+// Copyright 2016 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Parsing the body of a request received by a hyper server
+
+use std::io::{self, Read};
+
+use futures::stream::{Stream, Wait};
+use hyper::{Body, Chunk};
+use hyper::server::Request;
+
+use super::{read_multipart_body_with_options, Error, Node, ParseOptions};
+
+/// Reads a hyper `Body` as a blocking `Read`, waiting for each chunk as it is needed.
+///
+/// As this blocks, it must not be used on the thread running the event loop which delivers
+/// the body, but on another, such as that of a `CpuPool`.
+pub struct BodyReader {
+    chunks: Wait<Body>,
+    chunk: Chunk,
+    pos: usize,
+}
+
+impl BodyReader {
+    /// Create a reader over the body
+    pub fn new(body: Body) -> BodyReader {
+        BodyReader {
+            chunks: body.wait(),
+            chunk: Chunk::default(),
+            pos: 0,
+        }
+    }
+}
+
+impl Read for BodyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.chunk.len() {
+            match self.chunks.next() {
+                Some(Ok(chunk)) => {
+                    self.chunk = chunk;
+                    self.pos = 0;
+                },
+                Some(Err(e)) => return Err(io::Error::new(io::ErrorKind::Other, e)),
+                None => return Ok(0),
+            }
+        }
+        let count = ::std::cmp::min(buf.len(), self.chunk.len() - self.pos);
+        buf[..count].copy_from_slice(&self.chunk[self.pos..self.pos + count]);
+        self.pos += count;
+        Ok(count)
+    }
+}
+
+/// Parse the `multipart/*` body of a request received by a hyper server into a `Vec` of
+/// `Node`s, under the control of the `ParseOptions` given, taking the boundary from the
+/// request headers.  The body is taken out of the request.
+///
+/// This blocks until the whole body has been received, so it must not be called on the
+/// thread running the server's event loop, as `BodyReader` explains.
+pub fn read_multipart_request(request: &mut Request, options: &ParseOptions)
+                              -> Result<Vec<Node>, Error>
+{
+    let body = request.body_mut().take().unwrap_or_default();
+    let mut reader = BodyReader::new(body);
+    read_multipart_body_with_options(&mut reader, request.headers(), options)
+}
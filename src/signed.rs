@@ -0,0 +1,194 @@
+// Copyright 2016 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Support for `multipart/signed` and `multipart/encrypted` (RFC 1847), whose parts must be
+//! kept exactly as they were received for a signature to be verified
+
+use std::cell::Cell;
+use std::io::{BufRead, Read};
+
+use bytes::Bytes;
+use header::{ContentType, Headers};
+use mime;
+
+use super::{get_multipart_boundary, parse_header_block, read_multipart_body_with_options,
+            skip_whitespace, stream_part_body, with_context, Error, Node, ParseOptions, Part,
+            CONTENT_TRANSFER_ENCODING};
+use scan::stream_until_token;
+use transfer_encoding::{self, TransferEncoding};
+
+/// A part together with the exact bytes it was parsed from
+#[derive(Clone, Debug)]
+pub struct RawPart {
+    /// The entity (headers and body) exactly as it appeared between its boundaries, without
+    /// the line terminator preceding the next boundary, which belongs to that boundary.
+    /// This is the input to signature verification.
+    pub bytes: Vec<u8>,
+    /// The part as parsed from `bytes`
+    pub node: Node,
+}
+
+/// A parsed `multipart/signed`
+#[derive(Clone, Debug)]
+pub struct Signed {
+    /// The signed content, with its exact bytes
+    pub content: RawPart,
+    /// The signature
+    pub signature: Node,
+    /// The `protocol` parameter of the `Content-Type`, naming the type of the signature
+    pub protocol: Option<String>,
+    /// The `micalg` parameter of the `Content-Type`, naming the message integrity check
+    /// algorithm
+    pub micalg: Option<String>,
+}
+
+/// Parse a `multipart/*` body from a `Read`able stream, keeping the exact bytes of each
+/// part alongside it.  Each part is held in memory.  Nested multiparts are parsed according
+/// to the options, but the bytes of the nested multipart as a whole are what is kept.
+///
+/// It is presumed that you have the `Headers` already and the stream starts at the body.
+pub fn read_raw_parts<S: Read>(stream: &mut S, headers: &Headers, options: &ParseOptions)
+                               -> Result<Vec<RawPart>, Error>
+{
+    let entities = try!(with_context(stream, options, |reader, context| {
+        read_entities(reader, headers, context.options)
+    }));
+
+    let mut parts: Vec<RawPart> = Vec::with_capacity(entities.len());
+    for bytes in entities {
+        let node = try!(parse_entity(&bytes, options));
+        parts.push(RawPart { bytes: bytes, node: node });
+    }
+    Ok(parts)
+}
+
+/// Parse a `multipart/signed` body from a `Read`able stream into the signed content, with
+/// its exact bytes, and the signature.  Fails with `Error::WrongNumberOfParts` unless there
+/// are exactly two parts.
+///
+/// It is presumed that you have the `Headers` already and the stream starts at the body.
+pub fn read_signed<S: Read>(stream: &mut S, headers: &Headers, options: &ParseOptions)
+                            -> Result<Signed, Error>
+{
+    let mut parts = try!(read_raw_parts(stream, headers, options));
+    if parts.len() != 2 {
+        return Err(Error::WrongNumberOfParts);
+    }
+    let signature = parts.pop().unwrap().node;
+    let content = parts.pop().unwrap();
+
+    let param = |name: &str| {
+        let ct: Option<&ContentType> = headers.get();
+        ct.and_then(|ct| ct.get_param(name).map(|value| value.as_str().to_owned()))
+    };
+    Ok(Signed {
+        content: content,
+        signature: signature,
+        protocol: param("protocol"),
+        micalg: param("micalg"),
+    })
+}
+
+// Read the entity of each part, exactly, without parsing it
+fn read_entities<R: BufRead>(reader: &mut R, headers: &Headers, options: &ParseOptions)
+                             -> Result<Vec<Vec<u8>>, Error>
+{
+    let boundary = try!(get_multipart_boundary(headers));
+
+    // Read past the initial boundary, discarding the preamble
+    let (_, found) = try!(stream_until_token(reader, &boundary, &mut ::std::io::sink()));
+    if ! found { return Err(Error::EofBeforeFirstBoundary); }
+
+    let mut delimiter = Vec::with_capacity(1 + boundary.len());
+    delimiter.push(b'\n');
+    delimiter.extend(boundary.iter().cloned());
+
+    let mut entities: Vec<Vec<u8>> = Vec::new();
+    loop {
+        if options.lenient {
+            try!(skip_whitespace(reader));
+        }
+
+        // If the next two lookahead characters are '--', parsing is finished.
+        {
+            let peeker = try!(reader.fill_buf());
+            if peeker.len() >= 2 && &peeker[..2] == b"--" {
+                break;
+            }
+        }
+
+        // Read the line terminator after the boundary, allowing for transport padding
+        let mut line: Vec<u8> = Vec::new();
+        let (_, found) = try!(stream_until_token(reader, b"\n", &mut line));
+        if ! found || line.iter().any(|&b| b != b' ' && b != b'\t' && b != b'\r') {
+            return Err(Error::NoCrLfAfterBoundary);
+        }
+
+        let mut entity: Vec<u8> = Vec::new();
+        let (_, found) = try!(stream_part_body(reader, &delimiter, None, &mut entity,
+                                               &Cell::new(false), options));
+        if ! found { return Err(Error::EofInPart); }
+        entities.push(entity);
+    }
+    Ok(entities)
+}
+
+// Parse an entity held in memory into a node
+fn parse_entity(bytes: &[u8], options: &ParseOptions) -> Result<Node, Error> {
+    // Split off the header lines, up to an empty line, normalizing their line terminators
+    // to CRLF as httparse expects.
+    let mut header_block: Vec<u8> = Vec::new();
+    let mut pos = 0;
+    loop {
+        let end = match bytes[pos..].iter().position(|&b| b == b'\n') {
+            Some(len) => pos + len,
+            None => return Err(Error::EofInPartHeaders),
+        };
+        let line = if end > pos && bytes[end - 1] == b'\r' { &bytes[pos..end - 1] }
+                   else { &bytes[pos..end] };
+        pos = end + 1;
+        if line.is_empty() {
+            break;
+        }
+        header_block.extend(line.iter().cloned());
+        header_block.extend(b"\r\n".iter().cloned());
+    }
+    header_block.extend(b"\r\n".iter().cloned());
+    let mut headers = try!(parse_header_block(&header_block, options.max_part_headers));
+    let raw_headers = bytes[..pos].to_vec();
+    let body = &bytes[pos..];
+
+    let nested = {
+        let ct: Option<&ContentType> = headers.get();
+        ct.map_or(false, |ct| ct.type_() == mime::MULTIPART)
+    };
+    if nested {
+        let nodes = try!(read_multipart_body_with_options(&mut &*body, &headers, options));
+        return Ok(Node::Multipart((headers, nodes)));
+    }
+
+    let decode = if options.decode_transfer_encoding {
+        TransferEncoding::from_headers(&headers).and_then(|e| {
+            if e.is_encoded() { Some(e) } else { None }
+        })
+    } else {
+        None
+    };
+    let body = match decode {
+        Some(encoding) => {
+            headers.remove_raw(CONTENT_TRANSFER_ENCODING);
+            try!(transfer_encoding::decode(body, encoding))
+        },
+        None => body.to_vec(),
+    };
+
+    Ok(Node::Part(Part {
+        headers: headers,
+        body: Bytes::from(body),
+        raw_headers: Some(raw_headers),
+    }))
+}
@@ -0,0 +1,102 @@
+// Copyright 2016 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Detecting the type of file content from its leading bytes ("magic bytes"), as a check
+//! on the `Content-Type` a client declares (requires the `sniff` feature)
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use mime::{self, Mime};
+
+use super::Error;
+
+/// How many leading bytes of content are examined
+pub const SNIFF_LENGTH: usize = 8192;
+
+/// The type of content beginning with `bytes`, if it is of a type recognised.  Only the
+/// first `SNIFF_LENGTH` bytes are examined.
+pub fn detect(bytes: &[u8]) -> Option<Mime> {
+    let bytes = &bytes[..::std::cmp::min(bytes.len(), SNIFF_LENGTH)];
+    infer::get(bytes).and_then(|kind| kind.mime_type().parse().ok())
+}
+
+/// The type of the content of the file at `path`, if it is of a type recognised
+pub fn detect_file(path: &Path) -> Result<Option<Mime>, Error> {
+    let mut bytes: Vec<u8> = Vec::with_capacity(SNIFF_LENGTH);
+    let file = try!(File::open(path));
+    try!(file.take(SNIFF_LENGTH as u64).read_to_end(&mut bytes));
+    Ok(detect(&bytes))
+}
+
+/// Which types of file content are accepted, as set by `ParseOptions::content_type_policy`.
+///
+/// A file part, or a part with a filename kept in memory, is rejected with
+/// `Error::ContentTypeMismatch` if the type detected from its content contradicts its
+/// `Content-Type` (unless that is `application/octet-stream`, which claims nothing in
+/// particular).  It is rejected with `Error::ContentTypeNotAllowed` if its type, as detected
+/// or otherwise as declared, is not in `allowed`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ContentTypePolicy {
+    /// The types accepted.  A subtype of `*` accepts any subtype, as in `image/*`.
+    pub allowed: Vec<Mime>,
+    /// Whether to accept content of no type recognised, on the strength of its
+    /// `Content-Type` alone.  Many types, including plain text, have no leading bytes to
+    /// recognise them by.  Defaults to `true`.
+    pub allow_undetected: bool,
+}
+impl ContentTypePolicy {
+    /// A policy accepting the types in `allowed`
+    pub fn new(allowed: Vec<Mime>) -> ContentTypePolicy {
+        ContentTypePolicy {
+            allowed: allowed,
+            allow_undetected: true,
+        }
+    }
+
+    /// Whether `content_type` is one of the types accepted
+    pub fn allows(&self, content_type: &Mime) -> bool {
+        self.allowed.iter().any(|allowed| {
+            (allowed.type_() == mime::STAR || allowed.type_() == content_type.type_()) &&
+                (allowed.subtype() == mime::STAR || allowed.subtype() == content_type.subtype())
+        })
+    }
+
+    /// Check a file part with the `declared` type of its `Content-Type` and the type
+    /// `detected` from its content
+    pub fn check(&self, declared: Option<&Mime>, detected: Option<&Mime>) -> Result<(), Error> {
+        let content_type = match (declared, detected) {
+            (Some(declared), Some(detected)) => {
+                let generic = same_type(declared, &mime::APPLICATION_OCTET_STREAM);
+                if ! generic && ! same_type(declared, detected) {
+                    return Err(Error::ContentTypeMismatch {
+                        declared: Box::new(declared.clone()),
+                        detected: Box::new(detected.clone()),
+                    });
+                }
+                detected.clone()
+            },
+            (None, Some(detected)) => detected.clone(),
+            (_, None) if ! self.allow_undetected => {
+                return Err(Error::ContentTypeNotAllowed(declared.cloned()
+                    .unwrap_or(mime::APPLICATION_OCTET_STREAM)));
+            },
+            (Some(declared), None) => declared.clone(),
+            (None, None) => mime::APPLICATION_OCTET_STREAM,
+        };
+        if ! self.allows(&content_type) {
+            return Err(Error::ContentTypeNotAllowed(content_type));
+        }
+        Ok(())
+    }
+}
+
+// Whether two types are the same, disregarding their parameters
+fn same_type(a: &Mime, b: &Mime) -> bool {
+    a.type_() == b.type_() && a.subtype() == b.subtype()
+}
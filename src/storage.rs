@@ -0,0 +1,113 @@
+// Copyright 2016 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Where the bodies of file parts go as they are parsed: temporary files by default, or
+//! any other store through `ParseOptions::part_storage`
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use header::Headers;
+
+use super::{Error, FilePart, TempDirectory};
+
+/// A store for the bodies of file parts, such as a directory, an object store or memory.
+///
+/// The parser calls `create()` for each part to be stored, streams the body to the
+/// `StoredBody` returned, and then calls `StoredBody::finalize()` to get the `FilePart`
+/// produced, or `StoredBody::delete()` if the body is not read completely (as when the
+/// parse fails).  A storage is shared by every parse made with the `ParseOptions` it is set
+/// in, which may be on different threads.
+pub trait PartStorage: Send + Sync {
+    /// Begin storing the body of a part with the headers given, as they were read.  Those
+    /// the `FilePart` is finalized with may differ, as when a `Content-Transfer-Encoding`
+    /// has been decoded.
+    fn create(&self, headers: &Headers) -> Result<Box<dyn StoredBody>, Error>;
+}
+
+/// The body of a part being stored by a `PartStorage`, written as it is read
+pub trait StoredBody: Write {
+    /// Finish storing the body, returning the `FilePart` referring to it, with the headers
+    /// given.  Its `path` is wherever the body is stored, e.g. a path on disk or the key of
+    /// an object; its `size` is set by the parser.
+    fn finalize(self: Box<Self>, headers: Headers) -> Result<FilePart, Error>;
+
+    /// Delete whatever was stored of the body, which will not be finished
+    fn delete(self: Box<Self>);
+}
+
+/// The default storage: temporary files, each deleted when its `FilePart` (and every clone
+/// of it) is dropped.  The files are put in one temporary directory, made with the first of
+/// them, and removed once they are all deleted.  Without `ParseOptions::part_storage`, each
+/// parse has a `TempFileStorage` of its own; one set there is shared by every parse made
+/// with those options.  With `unlinked`, the files have no name, as made by
+/// `FilePart::create_unlinked()`.
+#[derive(Debug, Default)]
+pub struct TempFileStorage {
+    /// Whether to make files without a name, as `ParseOptions::unlinked_files` asks
+    pub unlinked: bool,
+    // The directory the files are put in, once made
+    dir: Mutex<Option<Arc<TempDirectory>>>,
+}
+
+impl TempFileStorage {
+    /// Store files in a temporary directory, or without a name if `unlinked`
+    pub fn new(unlinked: bool) -> TempFileStorage {
+        TempFileStorage {
+            unlinked: unlinked,
+            dir: Mutex::new(None),
+        }
+    }
+}
+
+impl PartStorage for TempFileStorage {
+    fn create(&self, _headers: &Headers) -> Result<Box<dyn StoredBody>, Error> {
+        let filepart = if self.unlinked {
+            try!(FilePart::create_unlinked(Headers::new()))
+        } else {
+            let mut dir = self.dir.lock().unwrap();
+            if dir.is_none() {
+                *dir = Some(Arc::new(try!(TempDirectory::new())));
+            }
+            FilePart::create_in(Headers::new(), dir.as_ref().unwrap().clone())
+        };
+        let file = try!(File::create(&filepart.path));
+        Ok(Box::new(TempFileBody {
+            filepart: filepart,
+            file: file,
+        }))
+    }
+}
+
+// A part body being written to a temporary file, which is deleted as the `FilePart` drops
+struct TempFileBody {
+    filepart: FilePart,
+    file: File,
+}
+
+impl Write for TempFileBody {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.file.write(data)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl StoredBody for TempFileBody {
+    fn finalize(self: Box<Self>, headers: Headers) -> Result<FilePart, Error> {
+        let mut filepart = self.filepart;
+        filepart.headers = headers;
+        Ok(filepart)
+    }
+
+    fn delete(self: Box<Self>) {
+        // The file goes with the `FilePart`
+    }
+}
@@ -0,0 +1,196 @@
+// Copyright 2016 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Producing a multipart body from `Node`s lazily, for clients which take a body to read,
+//! or in chunks, rather than writing it themselves
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Read};
+
+use header::Headers;
+
+use super::{check_headers, get_multipart_boundary, Error, FilePart, Node};
+
+/// Reads the multipart body which `write_multipart()` would write for the nodes given.
+///
+/// Only the boundaries and headers are prepared up front; the content of `Node::File`s is
+/// read from their files as it is reached, so that the whole body is never held in memory.
+/// The nodes are owned by the reader, so that temporary files outlive it.
+pub struct MultipartBodyReader {
+    segments: VecDeque<Segment>,
+    // How far into the first segment has been read, if it is in memory
+    pos: usize,
+    // The file of the first segment, once it has been opened
+    file: Option<File>,
+}
+
+// A piece of the body: bytes in memory, or the content of a file
+enum Segment {
+    Bytes(Vec<u8>),
+    File(FilePart),
+}
+
+impl MultipartBodyReader {
+    /// Create a reader of the body made up of `nodes`, delimited by `boundary`.  Fails if a
+    /// nested multipart has no boundary, or if a header contains a CR or LF.
+    pub fn new(boundary: &[u8], nodes: Vec<Node>) -> Result<MultipartBodyReader, Error> {
+        let mut segments = VecDeque::new();
+        try!(push_multipart(&mut segments, boundary, nodes));
+        Ok(MultipartBodyReader {
+            segments: segments,
+            pos: 0,
+            file: None,
+        })
+    }
+
+    /// Iterate over the rest of the body in chunks of `chunk_size` bytes, e.g. to send each
+    /// as a chunk of a chunked transfer encoding, or as a message.  A `chunk_size` of 0 is
+    /// taken as 1.
+    pub fn chunks(self, chunk_size: usize) -> MultipartChunks {
+        MultipartChunks {
+            reader: self,
+            chunk_size: ::std::cmp::max(chunk_size, 1),
+            done: false,
+        }
+    }
+}
+
+impl Read for MultipartBodyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let count = match self.segments.front() {
+                Some(&Segment::Bytes(ref bytes)) => {
+                    let count = ::std::cmp::min(buf.len(), bytes.len() - self.pos);
+                    buf[..count].copy_from_slice(&bytes[self.pos..self.pos + count]);
+                    self.pos += count;
+                    count
+                },
+                Some(&Segment::File(ref filepart)) => {
+                    if self.file.is_none() {
+                        self.file = Some(try!(File::open(&filepart.path)));
+                    }
+                    try!(self.file.as_mut().unwrap().read(buf))
+                },
+                None => return Ok(0),
+            };
+            if count > 0 {
+                return Ok(count);
+            }
+            // The first segment is exhausted
+            self.segments.pop_front();
+            self.pos = 0;
+            self.file = None;
+        }
+    }
+}
+
+// Add the segments of a multipart body, as `write_multipart()` writes it
+fn push_multipart(segments: &mut VecDeque<Segment>, boundary: &[u8], nodes: Vec<Node>)
+                  -> Result<(), Error>
+{
+    for node in nodes {
+        push_bytes(segments, b"--");
+        push_bytes(segments, boundary);
+        push_bytes(segments, b"\r\n");
+        try!(push_entity(segments, node));
+        push_bytes(segments, b"\r\n");
+    }
+    push_bytes(segments, b"--");
+    push_bytes(segments, boundary);
+    push_bytes(segments, b"--");
+    Ok(())
+}
+
+// Add the segments of a node: its headers, a blank line and its content
+fn push_entity(segments: &mut VecDeque<Segment>, node: Node) -> Result<(), Error> {
+    match node {
+        Node::Part(part) => {
+            try!(push_headers(segments, &part.headers));
+            segments.push_back(Segment::Bytes(part.body.to_vec()));
+        },
+        Node::File(filepart) => {
+            try!(push_headers(segments, &filepart.headers));
+            segments.push_back(Segment::File(filepart));
+        },
+        Node::Multipart((headers, subnodes)) => {
+            let boundary = try!(get_multipart_boundary(&headers));
+            try!(push_headers(segments, &headers));
+            try!(push_multipart(segments, &boundary[2..], subnodes));
+        },
+        Node::Message((headers, message)) => {
+            try!(push_headers(segments, &headers));
+            try!(push_entity(segments, *message));
+        },
+        Node::Invalid(invalid) => push_bytes(segments, &invalid.raw),
+    }
+    Ok(())
+}
+
+// Add a header block, including the blank line ending it
+fn push_headers(segments: &mut VecDeque<Segment>, headers: &Headers) -> Result<(), Error> {
+    try!(check_headers(headers));
+    for header in headers.iter() {
+        push_bytes(segments, header.name().as_bytes());
+        push_bytes(segments, b": ");
+        push_bytes(segments, header.value_string().as_bytes());
+        push_bytes(segments, b"\r\n");
+    }
+    push_bytes(segments, b"\r\n");
+    Ok(())
+}
+
+// Add bytes, joining them onto the last segment if that is in memory too
+fn push_bytes(segments: &mut VecDeque<Segment>, bytes: &[u8]) {
+    if let Some(&mut Segment::Bytes(ref mut last)) = segments.back_mut() {
+        last.extend_from_slice(bytes);
+        return;
+    }
+    segments.push_back(Segment::Bytes(bytes.to_vec()));
+}
+
+/// An iterator over the body read by a `MultipartBodyReader`, in chunks of a target size:
+/// each chunk but the last is exactly that size.  Iteration ends after an error.
+pub struct MultipartChunks {
+    reader: MultipartBodyReader,
+    chunk_size: usize,
+    done: bool,
+}
+
+impl Iterator for MultipartChunks {
+    type Item = Result<Vec<u8>, Error>;
+
+    fn next(&mut self) -> Option<Result<Vec<u8>, Error>> {
+        if self.done {
+            return None;
+        }
+        let mut chunk = vec![0u8; self.chunk_size];
+        let mut filled = 0;
+        while filled < chunk.len() {
+            match self.reader.read(&mut chunk[filled..]) {
+                Ok(0) => {
+                    self.done = true;
+                    break;
+                },
+                Ok(count) => filled += count,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => { },
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(Error::Io(e)));
+                },
+            }
+        }
+        if filled == 0 {
+            return None;
+        }
+        chunk.truncate(filled);
+        Some(Ok(chunk))
+    }
+}
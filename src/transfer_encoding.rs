@@ -0,0 +1,524 @@
+// Copyright 2016 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Support for the `Content-Transfer-Encoding` header (RFC 2045 section 6)
+
+use std::io::{self, Write};
+
+use header::Headers;
+
+/// The name of the `Content-Transfer-Encoding` header
+pub const CONTENT_TRANSFER_ENCODING: &'static str = "Content-Transfer-Encoding";
+
+/// A `Content-Transfer-Encoding` mechanism
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TransferEncoding {
+    /// `7bit`: short lines of US-ASCII, not encoded
+    SevenBit,
+    /// `8bit`: short lines, possibly containing non-ASCII octets, not encoded
+    EightBit,
+    /// `binary`: arbitrary octets, not encoded
+    Binary,
+    /// `base64`
+    Base64,
+    /// `quoted-printable`
+    QuotedPrintable,
+}
+impl TransferEncoding {
+    /// The `Content-Transfer-Encoding` specified in the headers, if any.  Returns `None` if
+    /// the header is missing or names an unrecognized mechanism.
+    pub fn from_headers(headers: &Headers) -> Option<TransferEncoding> {
+        let raw = match headers.get_raw(CONTENT_TRANSFER_ENCODING).and_then(|r| r.one()) {
+            Some(raw) => raw,
+            None => return None,
+        };
+        let value = String::from_utf8_lossy(raw).trim().to_ascii_lowercase();
+        match &*value {
+            "7bit" => Some(TransferEncoding::SevenBit),
+            "8bit" => Some(TransferEncoding::EightBit),
+            "binary" => Some(TransferEncoding::Binary),
+            "base64" => Some(TransferEncoding::Base64),
+            "quoted-printable" => Some(TransferEncoding::QuotedPrintable),
+            _ => None,
+        }
+    }
+
+    /// The header value naming this mechanism
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            TransferEncoding::SevenBit => "7bit",
+            TransferEncoding::EightBit => "8bit",
+            TransferEncoding::Binary => "binary",
+            TransferEncoding::Base64 => "base64",
+            TransferEncoding::QuotedPrintable => "quoted-printable",
+        }
+    }
+
+    /// Whether the mechanism actually transforms the content (`base64` and
+    /// `quoted-printable` do, the identity mechanisms do not)
+    pub fn is_encoded(&self) -> bool {
+        match *self {
+            TransferEncoding::Base64 | TransferEncoding::QuotedPrintable => true,
+            _ => false,
+        }
+    }
+}
+
+/// A writer which decodes `base64` or `quoted-printable` content written to it, passing
+/// the decoded bytes on to an inner writer.  Identity encodings are passed through as is.
+///
+/// Encoded input may be written in arbitrary pieces.  Call `finish()` once all input has
+/// been written.
+pub struct Decoder<W: Write> {
+    inner: W,
+    encoding: TransferEncoding,
+    // Input which could not be decoded yet, awaiting more input
+    pending: Vec<u8>,
+    written: usize,
+}
+impl<W: Write> Decoder<W> {
+    pub fn new(inner: W, encoding: TransferEncoding) -> Decoder<W> {
+        Decoder {
+            inner: inner,
+            encoding: encoding,
+            pending: Vec::new(),
+            written: 0,
+        }
+    }
+
+    /// Decode any remaining input, returning the inner writer and the total count of
+    /// decoded bytes written to it.
+    pub fn finish(mut self) -> io::Result<(W, usize)> {
+        let pending = ::std::mem::replace(&mut self.pending, Vec::new());
+        match self.encoding {
+            TransferEncoding::Base64 => {
+                if pending.iter().any(|&b| b != b'=') {
+                    return Err(invalid_data("truncated base64 content"));
+                }
+            },
+            TransferEncoding::QuotedPrintable => {
+                // Padding ends with the last line, and an incomplete escape at the very end
+                // is taken literally
+                let length = pending.iter().rposition(|&b| b != b' ' && b != b'\t')
+                    .map_or(0, |last| last + 1);
+                try!(self.output(&pending[..length]));
+            },
+            _ => { },
+        }
+        try!(self.inner.flush());
+        Ok((self.inner, self.written))
+    }
+
+    fn output(&mut self, data: &[u8]) -> io::Result<()> {
+        try!(self.inner.write_all(data));
+        self.written += data.len();
+        Ok(())
+    }
+
+    fn decode_base64(&mut self, data: &[u8]) -> io::Result<()> {
+        let mut output: Vec<u8> = Vec::with_capacity(data.len() / 4 * 3 + 3);
+        for &b in data {
+            if b == b' ' || b == b'\t' || b == b'\r' || b == b'\n' {
+                continue;
+            }
+            self.pending.push(b);
+            if self.pending.len() == 4 {
+                try!(decode_base64_quantum(&self.pending, &mut output));
+                self.pending.clear();
+            }
+        }
+        self.output(&output)
+    }
+
+    fn decode_quoted_printable(&mut self, data: &[u8]) -> io::Result<()> {
+        let mut input = ::std::mem::replace(&mut self.pending, Vec::new());
+        input.extend_from_slice(data);
+
+        let mut output: Vec<u8> = Vec::with_capacity(input.len());
+        let mut i = 0;
+        while i < input.len() {
+            if input[i] == b' ' || input[i] == b'\t' {
+                // Trailing whitespace is transport padding, to be dropped at the end of a
+                // line; wait for more input if the line may end after it
+                let end = skip_whitespace(&input, i);
+                match line_break(&input, end) {
+                    None => break,
+                    Some(0) => output.extend_from_slice(&input[i..end]),
+                    Some(_) => { },
+                }
+                i = end;
+                continue;
+            }
+            if input[i] != b'=' {
+                output.push(input[i]);
+                i += 1;
+                continue;
+            }
+            // An escape or soft line break, which may be followed by padding; wait for more
+            // input if it is incomplete
+            let end = skip_whitespace(&input, i + 1);
+            match line_break(&input, end) {
+                None => break,
+                Some(0) => { },
+                Some(length) => {
+                    i = end + length;
+                    continue;
+                },
+            }
+            if end > i + 1 {
+                // Padding not ending the line; RFC 2045 suggests taking the `=` literally
+                output.push(b'=');
+                i += 1;
+                continue;
+            }
+            if i + 2 >= input.len() { break; }
+            match (hex_value(input[i + 1]), hex_value(input[i + 2])) {
+                (Some(hi), Some(lo)) => {
+                    output.push(hi << 4 | lo);
+                    i += 3;
+                },
+                _ => {
+                    // Not a valid escape; RFC 2045 suggests taking it literally
+                    output.push(b'=');
+                    i += 1;
+                },
+            }
+        }
+        self.pending = input[i..].to_vec();
+        self.output(&output)
+    }
+}
+// The offset of the first byte from `start` which is not a space or tab
+fn skip_whitespace(input: &[u8], start: usize) -> usize {
+    let mut end = start;
+    while end < input.len() && (input[end] == b' ' || input[end] == b'\t') {
+        end += 1;
+    }
+    end
+}
+
+// The length of the line break at `start`, `Some(0)` if there is none there, or `None` if
+// more input is needed to tell
+fn line_break(input: &[u8], start: usize) -> Option<usize> {
+    match input.get(start) {
+        None => None,
+        Some(&b'\n') => Some(1),
+        Some(&b'\r') => match input.get(start + 1) {
+            None => None,
+            Some(&b'\n') => Some(2),
+            Some(_) => Some(0),
+        },
+        Some(_) => Some(0),
+    }
+}
+
+impl<W: Write> Write for Decoder<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        match self.encoding {
+            TransferEncoding::Base64 => try!(self.decode_base64(data)),
+            TransferEncoding::QuotedPrintable => try!(self.decode_quoted_printable(data)),
+            _ => try!(self.output(data)),
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// How part bodies are given a `Content-Transfer-Encoding` when written.  Parts which
+/// already carry a `Content-Transfer-Encoding` header are always written verbatim.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EncodingPolicy {
+    /// Bodies are written as they are
+    Verbatim,
+    /// Bodies which are 7-bit safe are written as they are.  Others are encoded with
+    /// `quoted-printable` if they are mostly US-ASCII text, or with `base64` otherwise.
+    Auto,
+    /// Bodies are always encoded with the given mechanism
+    Always(TransferEncoding),
+}
+
+/// A writer which tallies the content written to it, in order to choose a suitable
+/// `Content-Transfer-Encoding` for it.
+#[derive(Clone, Debug, Default)]
+pub struct Classifier {
+    total: usize,
+    non_ascii: usize,
+    bare_line_ending: bool,
+    line_len: usize,
+    max_line_len: usize,
+    prev: Option<u8>,
+}
+impl Classifier {
+    pub fn new() -> Classifier {
+        Classifier::default()
+    }
+
+    /// Whether the content can be sent as `7bit`: US-ASCII without NULs, in CRLF
+    /// terminated lines of at most 998 octets.
+    pub fn is_7bit_safe(&self) -> bool {
+        self.non_ascii == 0 && ! self.bare_line_ending && self.prev != Some(b'\r')
+            && self.max_line_len.max(self.line_len) <= 998
+    }
+
+    /// The encoding best suited to the content
+    pub fn choose(&self) -> TransferEncoding {
+        if self.is_7bit_safe() {
+            TransferEncoding::SevenBit
+        } else if self.non_ascii * 6 <= self.total {
+            TransferEncoding::QuotedPrintable
+        } else {
+            TransferEncoding::Base64
+        }
+    }
+}
+impl Write for Classifier {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        for &b in data {
+            self.total += 1;
+            if b == 0 || b > 127 {
+                self.non_ascii += 1;
+            }
+            if b == b'\n' {
+                if self.prev != Some(b'\r') {
+                    self.bare_line_ending = true;
+                }
+                self.max_line_len = self.max_line_len.max(self.line_len);
+                self.line_len = 0;
+            } else {
+                if self.prev == Some(b'\r') {
+                    self.bare_line_ending = true;
+                }
+                if b != b'\r' {
+                    self.line_len += 1;
+                }
+            }
+            self.prev = Some(b);
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A writer which encodes content written to it with `base64` or `quoted-printable`,
+/// in lines of at most 76 characters, passing the encoded bytes on to an inner writer.
+/// Identity encodings are passed through as is.
+///
+/// Call `finish()` once all content has been written.
+pub struct Encoder<W: Write> {
+    inner: W,
+    encoding: TransferEncoding,
+    // Content which could not be encoded yet, awaiting more content
+    pending: Vec<u8>,
+    line_len: usize,
+    written: usize,
+}
+impl<W: Write> Encoder<W> {
+    pub fn new(inner: W, encoding: TransferEncoding) -> Encoder<W> {
+        Encoder {
+            inner: inner,
+            encoding: encoding,
+            pending: Vec::new(),
+            line_len: 0,
+            written: 0,
+        }
+    }
+
+    /// Encode any remaining content, returning the inner writer and the total count of
+    /// encoded bytes written to it.
+    pub fn finish(mut self) -> io::Result<(W, usize)> {
+        let mut output: Vec<u8> = Vec::new();
+        match self.encoding {
+            TransferEncoding::Base64 => self.encode_base64(&[], true, &mut output),
+            TransferEncoding::QuotedPrintable =>
+                self.encode_quoted_printable(&[], true, &mut output),
+            _ => { },
+        }
+        try!(self.output(&output));
+        try!(self.inner.flush());
+        Ok((self.inner, self.written))
+    }
+
+    fn output(&mut self, data: &[u8]) -> io::Result<()> {
+        try!(self.inner.write_all(data));
+        self.written += data.len();
+        Ok(())
+    }
+
+    fn encode_base64(&mut self, data: &[u8], last: bool, output: &mut Vec<u8>) {
+        let mut input = ::std::mem::replace(&mut self.pending, Vec::new());
+        input.extend_from_slice(data);
+
+        let whole = input.len() / 3 * 3;
+        for chunk in input[..whole].chunks(3) {
+            if self.line_len == 76 {
+                output.extend_from_slice(b"\r\n");
+                self.line_len = 0;
+            }
+            output.push(BASE64_ALPHABET[(chunk[0] >> 2) as usize]);
+            output.push(BASE64_ALPHABET[((chunk[0] & 0x03) << 4 | chunk[1] >> 4) as usize]);
+            output.push(BASE64_ALPHABET[((chunk[1] & 0x0f) << 2 | chunk[2] >> 6) as usize]);
+            output.push(BASE64_ALPHABET[(chunk[2] & 0x3f) as usize]);
+            self.line_len += 4;
+        }
+        let rest = &input[whole..];
+        if ! last || rest.is_empty() {
+            self.pending = rest.to_vec();
+            return;
+        }
+        if self.line_len == 76 {
+            output.extend_from_slice(b"\r\n");
+        }
+        output.push(BASE64_ALPHABET[(rest[0] >> 2) as usize]);
+        if rest.len() == 1 {
+            output.push(BASE64_ALPHABET[((rest[0] & 0x03) << 4) as usize]);
+            output.extend_from_slice(b"==");
+        } else {
+            output.push(BASE64_ALPHABET[((rest[0] & 0x03) << 4 | rest[1] >> 4) as usize]);
+            output.push(BASE64_ALPHABET[((rest[1] & 0x0f) << 2) as usize]);
+            output.push(b'=');
+        }
+    }
+
+    fn encode_quoted_printable(&mut self, data: &[u8], last: bool, output: &mut Vec<u8>) {
+        let mut input = ::std::mem::replace(&mut self.pending, Vec::new());
+        input.extend_from_slice(data);
+
+        // Whitespace and CRs need two bytes of lookahead, so hold back the last two bytes
+        // until more content (or the end) arrives.
+        let end = if last { input.len() } else { input.len().saturating_sub(2) };
+        let mut i = 0;
+        while i < end {
+            let b = input[i];
+            let next = input.get(i + 1).cloned();
+            if b == b'\r' && next == Some(b'\n') {
+                // A hard line break
+                output.extend_from_slice(b"\r\n");
+                self.line_len = 0;
+                i += 2;
+                continue;
+            }
+            let at_line_end = next.is_none()
+                || (next == Some(b'\r') && input.get(i + 2) == Some(&b'\n'));
+            let literal = match b {
+                b' ' | b'\t' => ! at_line_end,
+                b'=' => false,
+                33..=126 => true,
+                _ => false,
+            };
+            let width = if literal { 1 } else { 3 };
+            // Leave room for the '=' of a soft line break
+            if self.line_len + width > 75 {
+                output.extend_from_slice(b"=\r\n");
+                self.line_len = 0;
+            }
+            if literal {
+                output.push(b);
+            } else {
+                output.push(b'=');
+                output.push(HEX_DIGITS[(b >> 4) as usize]);
+                output.push(HEX_DIGITS[(b & 0x0f) as usize]);
+            }
+            self.line_len += width;
+            i += 1;
+        }
+        self.pending = input[i..].to_vec();
+    }
+}
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let mut output: Vec<u8> = Vec::with_capacity(data.len() * 4 / 3 + 4);
+        match self.encoding {
+            TransferEncoding::Base64 => self.encode_base64(data, false, &mut output),
+            TransferEncoding::QuotedPrintable =>
+                self.encode_quoted_printable(data, false, &mut output),
+            _ => output.extend_from_slice(data),
+        }
+        try!(self.output(&output));
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Encode content held in memory with `base64` or `quoted-printable`
+pub fn encode(data: &[u8], encoding: TransferEncoding) -> io::Result<Vec<u8>> {
+    let mut encoder = Encoder::new(Vec::with_capacity(data.len() * 4 / 3 + 4), encoding);
+    try!(encoder.write_all(data));
+    let (output, _) = try!(encoder.finish());
+    Ok(output)
+}
+
+const BASE64_ALPHABET: &'static [u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+const HEX_DIGITS: &'static [u8; 16] = b"0123456789ABCDEF";
+
+/// Decode `base64` or `quoted-printable` content held in memory
+pub fn decode(data: &[u8], encoding: TransferEncoding) -> io::Result<Vec<u8>> {
+    let mut decoder = Decoder::new(Vec::with_capacity(data.len()), encoding);
+    try!(decoder.write_all(data));
+    let (output, _) = try!(decoder.finish());
+    Ok(output)
+}
+
+fn decode_base64_quantum(quantum: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+    let mut values = [0u8; 4];
+    let mut padding = 0;
+    for (i, &b) in quantum.iter().enumerate() {
+        if b == b'=' && i >= 2 {
+            padding += 1;
+            continue;
+        }
+        if padding > 0 {
+            return Err(invalid_data("invalid base64 padding"));
+        }
+        values[i] = match base64_value(b) {
+            Some(v) => v,
+            None => return Err(invalid_data("invalid base64 character")),
+        };
+    }
+    output.push(values[0] << 2 | values[1] >> 4);
+    if padding < 2 {
+        output.push(values[1] << 4 | values[2] >> 2);
+    }
+    if padding < 1 {
+        output.push(values[2] << 6 | values[3]);
+    }
+    Ok(())
+}
+
+fn base64_value(b: u8) -> Option<u8> {
+    match b {
+        b'A'..=b'Z' => Some(b - b'A'),
+        b'a'..=b'z' => Some(b - b'a' + 26),
+        b'0'..=b'9' => Some(b - b'0' + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    }
+}
+
+fn hex_value(b: u8) -> Option<u8> {
+    match b {
+        b'0'..=b'9' => Some(b - b'0'),
+        b'A'..=b'F' => Some(b - b'A' + 10),
+        b'a'..=b'f' => Some(b - b'a' + 10),
+        _ => None,
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
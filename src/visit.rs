@@ -0,0 +1,176 @@
+// Copyright 2016 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Changing the nodes of a tree in place, such as to strip metadata from images or redact
+//! fields, before it is written out again
+
+use std::io::{self, Write};
+
+use header::{ContentType, Headers};
+
+use super::{generate_boundary, get_multipart_boundary, write_entity, Error, Node,
+            WriteOptions};
+use scan;
+
+/// Call `f` on each node, and on all those nested within them, depth-first in the order they
+/// would be written, with the depth of the node as for `walk()`.  It may change the headers
+/// and bodies of the nodes, or replace them.  The nodes within a `Node::Multipart` or
+/// `Node::Message` are visited after it, so as they are once `f` has changed it.
+///
+/// Once the nodes within a nested multipart have been visited, it is given a new boundary
+/// (from `generate_boundary()`) in its `Content-Type` if its boundary now appears within
+/// them, or it has none.  The boundary the nodes given are written with is up to the
+/// caller.  Stops at the first error from `f`, or from reading the file of a file part.
+pub fn map_nodes<F>(nodes: &mut [Node], mut f: F) -> Result<(), Error>
+    where F: FnMut(&mut Node, usize) -> Result<(), Error>
+{
+    map_nodes_at(nodes, 0, &mut f)
+}
+
+fn map_nodes_at<F>(nodes: &mut [Node], depth: usize, f: &mut F) -> Result<(), Error>
+    where F: FnMut(&mut Node, usize) -> Result<(), Error>
+{
+    for node in nodes.iter_mut() {
+        try!(f(node, depth));
+        match *node {
+            Node::Multipart((ref mut headers, ref mut subnodes)) => {
+                try!(map_nodes_at(subnodes, depth + 1, f));
+                try!(fit_boundary(headers, subnodes));
+            },
+            Node::Message((_, ref mut message)) => {
+                try!(map_nodes_at(::std::slice::from_mut(&mut **message), depth + 1, f));
+            },
+            _ => { },
+        }
+    }
+    Ok(())
+}
+
+/// Call `f` on each part and file part (but not on multiparts or messages, nor invalid
+/// parts), including those nested within other nodes, running the calls in parallel on
+/// rayon's thread pool, e.g. to make thumbnails of several uploaded images at once.  Returns
+/// what each call returned, in the order of the parts as `walk()` visits them.
+///
+/// `f` may change the headers and bodies of the parts it is given, or replace them.  The
+/// nodes remain owned by `nodes` throughout, so that the temporary files of file parts are
+/// removed when the nodes are dropped as usual, whether or not `f` succeeded.  Once all the
+/// calls have returned, nested multiparts are given new boundaries as by `map_nodes()`.
+/// Fails with the error from one of the calls if any fail, once those running have
+/// finished.
+#[cfg(feature = "rayon")]
+pub fn process_parts_parallel<F, T>(nodes: &mut [Node], f: F) -> Result<Vec<T>, Error>
+    where F: Fn(&mut Node) -> Result<T, Error> + Sync,
+          T: Send
+{
+    use rayon::prelude::*;
+
+    let results = {
+        let mut parts: Vec<&mut Node> = Vec::new();
+        collect_parts(nodes, &mut parts);
+        parts.into_par_iter().map(|node| f(node)).collect::<Vec<_>>()
+    };
+    try!(fit_boundaries(nodes));
+    results.into_iter().collect()
+}
+
+// Gather the parts and file parts within the nodes, depth-first
+#[cfg(feature = "rayon")]
+fn collect_parts<'a>(nodes: &'a mut [Node], parts: &mut Vec<&'a mut Node>) {
+    for node in nodes.iter_mut() {
+        match *node {
+            Node::Part(_) | Node::File(_) => parts.push(node),
+            Node::Multipart((_, ref mut subnodes)) => collect_parts(subnodes, parts),
+            Node::Message((_, ref mut message)) => {
+                collect_parts(::std::slice::from_mut(&mut **message), parts)
+            },
+            Node::Invalid(_) => { },
+        }
+    }
+}
+
+// Give the nested multiparts within the nodes new boundaries where needed, innermost first
+#[cfg(feature = "rayon")]
+fn fit_boundaries(nodes: &mut [Node]) -> Result<(), Error> {
+    for node in nodes.iter_mut() {
+        match *node {
+            Node::Multipart((ref mut headers, ref mut subnodes)) => {
+                try!(fit_boundaries(subnodes));
+                try!(fit_boundary(headers, subnodes));
+            },
+            Node::Message((_, ref mut message)) => {
+                try!(fit_boundaries(::std::slice::from_mut(&mut **message)));
+            },
+            _ => { },
+        }
+    }
+    Ok(())
+}
+
+// Give the multipart with these headers a new boundary if its own is missing or appears
+// within its nodes, keeping the other parameters of its Content-Type
+fn fit_boundary(headers: &mut Headers, nodes: &[Node]) -> Result<(), Error> {
+    let mime = match headers.get::<ContentType>() {
+        Some(&ContentType(ref mime)) => mime.clone(),
+        None => return Ok(()),
+    };
+    if let Ok(boundary) = get_multipart_boundary(headers) {
+        let mut finder = Finder::new(boundary);
+        for node in nodes {
+            try!(write_entity(&mut finder, node, &WriteOptions::default()));
+        }
+        if ! finder.found {
+            return Ok(());
+        }
+    }
+
+    let mut content_type = format!("{}/{}", mime.type_(), mime.subtype());
+    for (name, value) in mime.params() {
+        if name != ::mime::BOUNDARY {
+            content_type.push_str(&format!("; {}=\"{}\"", name, value));
+        }
+    }
+    content_type.push_str(&format!("; boundary=\"{}\"",
+                                   String::from_utf8_lossy(&generate_boundary())));
+    headers.set(ContentType(try!(content_type.parse()
+        .map_err(|_| Error::MalformedHeader("Content-Type".to_owned())))));
+    Ok(())
+}
+
+// A writer which only looks for a token within what is written to it
+struct Finder {
+    token: Vec<u8>,
+    // The end of what was written, in case the token spans two writes
+    tail: Vec<u8>,
+    found: bool,
+}
+
+impl Finder {
+    fn new(token: Vec<u8>) -> Finder {
+        Finder {
+            token: token,
+            tail: Vec::new(),
+            found: false,
+        }
+    }
+}
+
+impl Write for Finder {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if ! self.found {
+            self.tail.extend_from_slice(buf);
+            self.found = scan::find(&self.tail, &self.token).is_some();
+            let keep = ::std::cmp::min(self.tail.len(), self.token.len() - 1);
+            let start = self.tail.len() - keep;
+            self.tail.drain(..start);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,82 @@
+// Copyright 2016 mime-multipart Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! A `warp` filter decoding `multipart/*` request bodies into `Node`s (requires the `warp`
+//! feature)
+
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_core::Stream;
+use header::Headers;
+use warp::hyper::body::{Buf, Bytes};
+use warp::http::HeaderMap;
+use warp::reject::{self, Reject, Rejection};
+use warp::Filter;
+
+use super::{Error, Node, ParseOptions};
+use body_reader::{ChunkSource, MultipartFuture};
+
+/// A filter extracting the `Node`s of a `multipart/*` request body.
+///
+/// The body is parsed under the control of the `ParseOptions` returned by `options`, which
+/// is called for each request, and within which limits such as `max_part_headers` may be
+/// set.  A body longer than `max_length` bytes is rejected, whether or not it has a
+/// `Content-Length`.
+///
+/// Parsing is blocking, and is done on `tokio`'s blocking thread pool.  Should it fail, the
+/// request is rejected with a `MultipartRejection`, which may be turned into a response
+/// with `Filter::recover()`.
+pub fn multipart<F>(max_length: u64, options: F)
+                    -> impl Filter<Extract = (Vec<Node>,), Error = Rejection> + Clone
+    where F: Fn() -> ParseOptions + Clone + Send + Sync + Unpin + 'static
+{
+    warp::header::headers_cloned()
+        .and(warp::body::stream())
+        .and_then(move |header_map: HeaderMap, body| {
+            let mut headers = Headers::new();
+            for (name, value) in header_map.iter() {
+                headers.append_raw(name.as_str().to_owned(), value.as_bytes().to_vec());
+            }
+            let options = options.clone();
+            MultipartFuture::new(headers, BodyStream(body), move || options(),
+                                 Some(max_length), |result| {
+                result.map_err(|e| reject::custom(MultipartRejection(e)))
+            })
+        })
+}
+
+/// Rejection of a request whose body could not be parsed
+#[derive(Debug)]
+pub struct MultipartRejection(pub Error);
+
+impl Reject for MultipartRejection {}
+
+// The stream of a request body, as chunks of data
+struct BodyStream<S>(S);
+
+impl<S, B> ChunkSource for BodyStream<S>
+    where S: Stream<Item = Result<B, warp::Error>> + Unpin,
+          B: Buf
+{
+    type Chunk = Bytes;
+
+    fn poll_chunk(&mut self, cx: &mut Context) -> Poll<Option<io::Result<Bytes>>> {
+        match Pin::new(&mut self.0).poll_next(cx) {
+            Poll::Ready(Some(Ok(mut chunk))) => {
+                let length = chunk.remaining();
+                Poll::Ready(Some(Ok(chunk.copy_to_bytes(length))))
+            },
+            Poll::Ready(Some(Err(e))) => {
+                Poll::Ready(Some(Err(io::Error::new(io::ErrorKind::Other, e))))
+            },
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}